@@ -0,0 +1,59 @@
+use chnots_core::utils::diffutils::distance_exceeds;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A tiptap document holding a table of `rows` rows, about 150 bytes each.
+fn document(rows: usize, cell: &str) -> String {
+    let rows: Vec<String> = (0..rows)
+        .map(|i| {
+            format!(
+                r#"{{"type":"tableRow","content":[{{"type":"tableCell","content":[{{"type":"text","text":"row {}"}}]}},{{"type":"tableCell","content":[{{"type":"text","text":"{}"}}]}}]}}"#,
+                i, cell
+            )
+        })
+        .collect();
+    format!(
+        r#"{{"type":"doc","content":[{{"type":"table","content":[{}]}}]}}"#,
+        rows.join(",")
+    )
+}
+
+/// The same document with one cell in the middle edited.
+fn edited(rows: usize) -> (String, String) {
+    let old = document(rows, "value");
+    let mid = old.len() / 2;
+    let at = old[mid..].find("value").unwrap() + mid;
+    let new = format!("{}changed value{}", &old[..at], &old[at + "value".len()..]);
+    (old, new)
+}
+
+fn change_detection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("change_detection");
+    group.sample_size(10);
+
+    for rows in [16, 128, 2048] {
+        let (old, new) = edited(rows);
+        let size = old.len() / 1024;
+
+        // Quadratic, only bearable for the small documents.
+        if rows <= 128 {
+            group.bench_with_input(BenchmarkId::new("levenshtein", size), &rows, |b, _| {
+                b.iter(|| distance::levenshtein(black_box(&old), black_box(&new)) > 8)
+            });
+        }
+        group.bench_with_input(BenchmarkId::new("distance_exceeds", size), &rows, |b, _| {
+            b.iter(|| distance_exceeds(black_box(&old), black_box(&new), 8))
+        });
+
+        // Edits all over the document, nothing in common to cut.
+        let rewritten = document(rows, "other");
+        group.bench_with_input(
+            BenchmarkId::new("distance_exceeds_rewritten", size),
+            &rows,
+            |b, _| b.iter(|| distance_exceeds(black_box(&old), black_box(&rewritten), 8)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, change_detection);
+criterion_main!(benches);
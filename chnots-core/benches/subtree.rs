@@ -0,0 +1,142 @@
+use std::sync::Arc;
+
+use chnots_core::{
+    config::DbConfig,
+    mapper::{
+        nodefilter::{NodeFetchReq, NodeFilter},
+        page::{NodeSort, NodeSortKey},
+        Mapper,
+    },
+    model::node::{MagicNodeId, NodeType},
+};
+use chrono::Utc;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rusqlite::{params, Connection};
+use tokio::runtime::Runtime;
+
+const NODES: usize = 100_000;
+const FANOUT: usize = 10;
+
+/// The old recursive walk, kept to compare the closure table against.
+const RECURSIVE_DESCENDANTS: &str = "with recursive children(id, parent_id) as (
+select n.id, n.parent_id from nodes n where n.parent_id = ?1
+union
+select n.id, n.parent_id from nodes n, children c where n.parent_id = c.id
+)
+select count(*) from children";
+
+/// A tree of `NODES` nodes, `FANOUT` children each, in a fresh database:
+/// `n0` is the root, the children of `n<i>` are `n<i * FANOUT + 1>` onwards.
+fn tree(rt: &Runtime, path: &str) -> Arc<dyn Mapper> {
+    let _ = std::fs::remove_file(path);
+    let config: DbConfig =
+        serde_json::from_value(serde_json::json!({"type": "sqlite", "filepath": path})).unwrap();
+    let mapper = rt.block_on(config.into()).unwrap();
+
+    // Written directly, one transaction is much faster than a move per node.
+    let mut conn = Connection::open(path).unwrap();
+    let tx = conn.transaction().unwrap();
+    {
+        let mut stmt = tx
+            .prepare(
+                "insert into nodes(id, name, content, node_type, domain, parent_id, prev_sliding_id, version_time, initial_time) values (?1, ?1, '', ?2, '', ?3, ?4, ?5, ?5)",
+            )
+            .unwrap();
+        let now = Utc::now();
+        for i in 0..NODES {
+            let (parent_id, prev_id) = match i {
+                0 => (
+                    MagicNodeId::Empty.as_ref().to_owned(),
+                    MagicNodeId::Empty.as_ref().to_owned(),
+                ),
+                _ if (i - 1) % FANOUT == 0 => (
+                    format!("n{}", (i - 1) / FANOUT),
+                    MagicNodeId::Empty.as_ref().to_owned(),
+                ),
+                _ => (format!("n{}", (i - 1) / FANOUT), format!("n{}", i - 1)),
+            };
+            stmt.execute(params![
+                format!("n{}", i),
+                NodeType::TiptapV1.as_ref(),
+                parent_id,
+                prev_id,
+                now
+            ])
+            .unwrap();
+        }
+    }
+    tx.commit().unwrap();
+
+    rt.block_on(mapper.rebuild_tree()).unwrap();
+    mapper
+}
+
+fn subtree(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    // The pool hands its connections back to the runtime when dropped.
+    let _guard = rt.enter();
+    let path = std::env::temp_dir().join("chnots-subtree-bench.db");
+    let path = path.to_str().unwrap();
+    let mapper = tree(&rt, path);
+    let conn = Connection::open(path).unwrap();
+
+    let mut group = c.benchmark_group("subtree");
+    group.sample_size(10);
+
+    // About a tenth of the tree.
+    let id = "n1".into();
+    group.bench_function("descendants_closure", |b| {
+        b.iter(|| {
+            rt.block_on(mapper.find_descendant_ids(black_box(&id)))
+                .unwrap()
+        })
+    });
+    group.bench_function("descendants_recursive", |b| {
+        b.iter(|| {
+            conn.query_row(RECURSIVE_DESCENDANTS, params![black_box("n1")], |row| {
+                row.get::<_, i64>(0)
+            })
+            .unwrap()
+        })
+    });
+
+    group.bench_function("descendants_within_2", |b| {
+        let req = NodeFetchReq {
+            selection: None,
+            filter: Some(NodeFilter::DescendantWithin("n1".into(), 2)),
+            ..Default::default()
+        };
+        b.iter(|| rt.block_on(mapper.query_nodes(black_box(&req))).unwrap())
+    });
+
+    let leaf = format!("n{}", NODES - 1).into();
+    group.bench_function("ancestors", |b| {
+        b.iter(|| {
+            rt.block_on(mapper.find_ancestor_ids(black_box(&leaf)))
+                .unwrap()
+        })
+    });
+
+    group.bench_function("children_by_position", |b| {
+        let req = NodeFetchReq {
+            selection: None,
+            filter: Some(NodeFilter::Children("n1".into())),
+            sort: Some(NodeSort {
+                key: NodeSortKey::Position,
+                desc: false,
+            }),
+            ..Default::default()
+        };
+        b.iter(|| rt.block_on(mapper.query_nodes(black_box(&req))).unwrap())
+    });
+
+    group.bench_function("rebuild_tree", |b| {
+        b.iter(|| rt.block_on(mapper.rebuild_tree()).unwrap())
+    });
+    group.finish();
+
+    let _ = std::fs::remove_file(path);
+}
+
+criterion_group!(benches, subtree);
+criterion_main!(benches);
@@ -9,6 +9,8 @@ use serde::{Deserialize, Serialize};
 use strum::{AsRefStr, EnumString};
 use tracing::info;
 
+#[cfg(feature = "sqlite")]
+use crate::mapper::sqlite_mapper::SqliteMapper;
 use crate::{
     config::{BackupConfig, Config},
     mapper::postgres_mapper::PostgresMapper,
@@ -19,8 +21,10 @@ use self::{rowmapper::row_to_table, table::TableRow};
 
 use super::BackupVersion;
 
-pub enum RowSum {
+pub enum RowSum<'a> {
     Pg(tokio_postgres::Row),
+    #[cfg(feature = "sqlite")]
+    Sqlite(&'a rusqlite::Row<'a>),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, EnumString, AsRefStr)]
@@ -274,3 +278,38 @@ impl BackupHandlerV1 for PostgresMapper {
         Ok(arr)
     }
 }
+
+#[cfg(feature = "sqlite")]
+#[async_trait]
+impl BackupHandlerV1 for SqliteMapper {
+    async fn fetch_table(
+        &self,
+        table_name: &BackupContent,
+        time_field: &str,
+        start_time: &DateTime<Utc>,
+        end_time: &DateTime<Utc>,
+    ) -> anyhow::Result<Vec<TableRow>> {
+        let table_name = table_name.clone();
+        let sql = format!(
+            "select * from {} where {} <= ?1 and {} >= ?2",
+            table_name.as_ref(),
+            time_field,
+            time_field
+        );
+        let start_time = start_time.clone();
+        let end_time = end_time.clone();
+
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(rusqlite::params![end_time, start_time])?;
+
+            let mut arr: Vec<TableRow> = vec![];
+            while let Some(row) = rows.next()? {
+                arr.push(row_to_table(&table_name, RowSum::Sqlite(row))?);
+            }
+
+            Ok(arr)
+        })
+        .await
+    }
+}
@@ -3,10 +3,10 @@ use super::{
     BackupContent, RowSum,
 };
 
-impl TryFrom<RowSum> for NodesHistory {
+impl<'a> TryFrom<RowSum<'a>> for NodesHistory {
     type Error = anyhow::Error;
 
-    fn try_from(value: RowSum) -> Result<Self, Self::Error> {
+    fn try_from(value: RowSum<'a>) -> Result<Self, Self::Error> {
         match value {
             RowSum::Pg(row) => Ok(NodesHistory {
                 id: row.try_get("id")?,
@@ -18,14 +18,25 @@ impl TryFrom<RowSum> for NodesHistory {
                 version_time: row.try_get("version_time")?,
                 initial_time: row.try_get("initial_time")?,
             }),
+            #[cfg(feature = "sqlite")]
+            RowSum::Sqlite(row) => Ok(NodesHistory {
+                id: row.get("id")?,
+                delete_time: row.get("delete_time")?,
+                name: row.get("name")?,
+                content: row.get("content")?,
+                node_type: crate::model::node::NodeType::TiptapV1,
+                domain: row.get("domain")?,
+                version_time: row.get("version_time")?,
+                initial_time: row.get("initial_time")?,
+            }),
         }
     }
 }
 
-impl TryFrom<RowSum> for Nodes {
+impl<'a> TryFrom<RowSum<'a>> for Nodes {
     type Error = anyhow::Error;
 
-    fn try_from(value: RowSum) -> Result<Nodes, Self::Error> {
+    fn try_from(value: RowSum<'a>) -> Result<Nodes, Self::Error> {
         match value {
             RowSum::Pg(row) => Ok(Nodes {
                 id: row.try_get("id")?,
@@ -39,14 +50,27 @@ impl TryFrom<RowSum> for Nodes {
                 parent_id: row.try_get("parent_id")?,
                 prev_sliding_id: row.try_get("prev_sliding_id")?,
             }),
+            #[cfg(feature = "sqlite")]
+            RowSum::Sqlite(row) => Ok(Nodes {
+                id: row.get("id")?,
+                delete_time: row.get("delete_time")?,
+                name: row.get("name")?,
+                content: row.get("content")?,
+                node_type: crate::model::node::NodeType::TiptapV1,
+                domain: row.get("domain")?,
+                version_time: row.get("version_time")?,
+                initial_time: row.get("initial_time")?,
+                parent_id: row.get("parent_id")?,
+                prev_sliding_id: row.get("prev_sliding_id")?,
+            }),
         }
     }
 }
 
-impl TryFrom<RowSum> for Assets {
+impl<'a> TryFrom<RowSum<'a>> for Assets {
     type Error = anyhow::Error;
 
-    fn try_from(value: RowSum) -> Result<Assets, Self::Error> {
+    fn try_from(value: RowSum<'a>) -> Result<Assets, Self::Error> {
         match value {
             RowSum::Pg(row) => Ok(Assets {
                 id: row.try_get("id")?,
@@ -55,6 +79,14 @@ impl TryFrom<RowSum> for Assets {
                 create_time: row.try_get("create_time")?,
                 content_type: row.try_get("content_type")?,
             }),
+            #[cfg(feature = "sqlite")]
+            RowSum::Sqlite(row) => Ok(Assets {
+                id: row.get("id")?,
+                ori_file_name: row.get("ori_file_name")?,
+                domain: row.get("domain")?,
+                create_time: row.get("create_time")?,
+                content_type: row.get("content_type")?,
+            }),
         }
     }
 }
@@ -1,8 +1,9 @@
-use std::sync::Arc;
+use std::{num::NonZeroU32, sync::Arc};
 
 use crate::mapper::{
+    history::HistoryConfig,
     postgres_mapper::{PostgresConfig, PostgresMapper},
-    sqlite_mapper::SqliteConfig,
+    sqlite_mapper::{SqliteConfig, SqliteMapper},
     Mapper,
 };
 use anyhow::Ok;
@@ -28,17 +29,34 @@ pub struct BackupConfig {
     pub interval: Option<u32>,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecycleConfig {
+    /// Deleted nodes are purged after this many days.
+    pub keep_days: u32,
+    /// Seconds between two purges, one day by default. Zero is refused when
+    /// the config is read.
+    pub interval: Option<NonZeroU32>,
+}
+
 impl DbConfig {
     pub async fn into(self) -> anyhow::Result<Arc<(dyn Mapper + 'static)>> {
+        self.build(&HistoryConfig::default()).await
+    }
+
+    pub async fn build(self, history: &HistoryConfig) -> anyhow::Result<Arc<dyn Mapper + 'static>> {
         let mapper = match self {
             DbConfig::Postgres(pg) => {
                 let mut mapper = PostgresMapper::new(pg)?;
+                mapper.snapshot_distance = history.snapshot_distance;
+                mapper.init().await?;
+                Arc::new(mapper) as Arc<dyn Mapper>
+            }
+            DbConfig::Sqlite(cfg) => {
+                let mut mapper = SqliteMapper::new(cfg)?;
+                mapper.snapshot_distance = history.snapshot_distance;
                 mapper.init().await?;
                 Arc::new(mapper) as Arc<dyn Mapper>
             }
-            /*             DbConfig::Sqlite(cfg) => Arc::new(SqliteMapper::new(cfg)?) as Arc<dyn Mapper>,
-             */
-            _ => todo!(),
         };
 
         Ok(mapper)
@@ -51,4 +69,20 @@ pub struct Config {
     pub db_config: DbConfig,
     pub common: Common,
     pub backup: Option<BackupConfig>,
+    pub recycle: Option<RecycleConfig>,
+    #[serde(default)]
+    pub history: HistoryConfig,
+}
+
+#[cfg(test)]
+mod test {
+    use super::RecycleConfig;
+
+    #[test]
+    fn test_recycle_interval() {
+        let config: RecycleConfig =
+            serde_json::from_str(r#"{"keep_days":30,"interval":60}"#).unwrap();
+        assert_eq!(config.interval.map(|e| e.get()), Some(60));
+        assert!(serde_json::from_str::<RecycleConfig>(r#"{"keep_days":30,"interval":0}"#).is_err());
+    }
 }
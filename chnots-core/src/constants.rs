@@ -5,8 +5,11 @@ pub const TABLE_NAME_TAGS: &str = "tags";
 pub const TABLE_NAME_ALARMS: &str = "alarms";
 pub const TABLE_NAME_TODOS: &str = "todos";
 pub const TABLE_NAME_ASSETS: &str = "assets";
+pub const TABLE_NAME_SCHEMA_MIGRATIONS: &str = "schema_migrations";
 
 
 pub const MAGIC_RECYCLE_BIN: &str = "##Recycle_Bin##";
 pub const MAGIC_EMPTY: &str = "##Empty##";
-pub const MAGIC_NEVER: &str = "##Never##";
\ No newline at end of file
+pub const MAGIC_NEVER: &str = "##Never##";
+
+pub const LOST_AND_FOUND_NODE_ID: &str = "lost-and-found";
\ No newline at end of file
@@ -0,0 +1,68 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::node::{MagicNodeId, Node, NodeId};
+
+/// Failures a client can act on.
+///
+/// They travel inside `anyhow::Error` like every other failure, the server
+/// finds them with `downcast_ref` and answers with a fitting status.
+#[derive(Debug)]
+pub enum MapperError {
+    /// The node is saved by someone else since the client read it.
+    Conflict(Box<NodeConflict>),
+    /// The node is in the recycle bin, restore it instead.
+    Deleted(NodeId),
+    /// A node can not be moved under itself or one of its descendants.
+    IntoDescendant { id: NodeId, parent_id: NodeId },
+    /// The parent is missing, deleted or no parent a client may choose.
+    InvalidParent(MagicNodeId),
+    /// The previous sibling is not a live child of the parent.
+    NotAChild {
+        id: MagicNodeId,
+        parent_id: MagicNodeId,
+    },
+    /// A request which can not succeed as it is, with the reason.
+    InvalidRequest(String),
+    /// No live node is at the path, which is cut after the first missing segment.
+    NoSuchPath(String),
+}
+
+/// Both sides of a stale write, enough for the client to merge.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeConflict {
+    pub id: NodeId,
+    /// The version the client started from, `None` when no snapshot of it
+    /// was kept or it was compacted away.
+    pub base: Option<Node>,
+    pub current: Node,
+}
+
+impl fmt::Display for MapperError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapperError::Conflict(conflict) => write!(
+                f,
+                "node {:?} is updated at {}, after the edited version",
+                conflict.id, conflict.current.version_time
+            ),
+            MapperError::Deleted(id) => write!(f, "node {:?} is in the recycle bin", id),
+            MapperError::IntoDescendant { id, parent_id } => write!(
+                f,
+                "node {:?} can not be moved under {:?}, which is inside it",
+                id, parent_id
+            ),
+            MapperError::InvalidParent(parent_id) => {
+                write!(f, "{:?} can not be a parent", parent_id)
+            }
+            MapperError::NotAChild { id, parent_id } => {
+                write!(f, "{:?} is not a child of {:?}", id, parent_id)
+            }
+            MapperError::InvalidRequest(reason) => write!(f, "{}", reason),
+            MapperError::NoSuchPath(path) => write!(f, "there is no node at {:?}", path),
+        }
+    }
+}
+
+impl std::error::Error for MapperError {}
@@ -0,0 +1,626 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    constants::LOST_AND_FOUND_NODE_ID,
+    model::node::{ContentParsedInfo, MagicNodeId, Node, NodeId, NodeType},
+};
+
+use super::{
+    nodefilter::{NodeFetchReq, NodeFilter, NodeSelection},
+    tx::NodeTx,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FsckReq {
+    /// Relink what is broken, then rebuild the ancestry and order keys.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+/// One inconsistency of the tree, reported under the parent it belongs to.
+#[derive(Serialize, Debug, Clone)]
+pub enum TreeIssue {
+    /// Several siblings claim the same predecessor.
+    DuplicatePrev {
+        prev_id: MagicNodeId,
+        ids: Vec<NodeId>,
+    },
+    /// The predecessor is missing or lives under another parent.
+    DanglingPrev { id: NodeId, prev_id: MagicNodeId },
+    /// Siblings which can not be reached from the head of the list, because
+    /// their predecessors form a loop.
+    Cycle { ids: Vec<NodeId> },
+    /// A live node whose parent is deleted.
+    DeletedParent { id: NodeId },
+    /// The parent does not exist or the node was never linked.
+    Orphan { id: NodeId },
+    /// Nodes which are their own ancestors.
+    ParentCycle { ids: Vec<NodeId> },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ParentReport {
+    pub parent_id: MagicNodeId,
+    pub issues: Vec<TreeIssue>,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct FsckReport {
+    pub scanned: usize,
+    pub parents: Vec<ParentReport>,
+    /// Nodes whose relation was rewritten, always empty without repairing.
+    pub relinked: Vec<NodeId>,
+}
+
+/// Check the whole tree and repair it when asked to.
+///
+/// The tree is locked for the whole run, so the report and the repair are
+/// based on the same snapshot.
+pub async fn fsck(tx: &mut dyn NodeTx, req: &FsckReq) -> anyhow::Result<FsckReport> {
+    tx.lock_tree().await?;
+
+    let mut nodes = tx
+        .query_nodes(&NodeFetchReq {
+            selection: Some(vec![NodeSelection::WithHistory]),
+            filter: Some(NodeFilter::All),
+            ..Default::default()
+        })
+        .await?;
+
+    let parents = check_tree(&nodes);
+    let mut report = FsckReport {
+        scanned: nodes.len(),
+        parents,
+        relinked: vec![],
+    };
+
+    if !req.repair {
+        return Ok(report);
+    }
+
+    let lost_found: NodeId = LOST_AND_FOUND_NODE_ID.into();
+    let mut relations = plan_repair(&nodes, &report.parents, &lost_found);
+    let needs_lost_found = relations
+        .values()
+        .any(|(parent_id, _)| parent_id.as_ref() == lost_found.as_str());
+    if needs_lost_found && !nodes.iter().any(|n| n.id == lost_found) {
+        let node = lost_found_node();
+        tx.insert_node_row(&node).await?;
+        nodes.push(node);
+        relations = plan_repair(&nodes, &report.parents, &lost_found);
+    }
+    // The nodes moved under a lost and found node in the recycle bin would
+    // have a deleted parent again, it is restored with its subtree.
+    if needs_lost_found
+        && nodes
+            .iter()
+            .any(|n| n.id == lost_found && n.delete_time.is_some())
+    {
+        tx.clear_deleted(&subtree_ids(&nodes, &lost_found)).await?;
+        tx.delete_recycle_origin(&lost_found).await?;
+    }
+
+    for node in nodes.iter() {
+        if let Some((parent_id, prev_id)) = relations.get(&node.id) {
+            if parent_id.as_ref() != node.parent_id.as_ref()
+                || prev_id.as_ref() != node.prev_sliding_id.as_ref()
+            {
+                tx.update_relation(&node.id, parent_id, prev_id).await?;
+                report.relinked.push(node.id.clone());
+            }
+        }
+    }
+    report.relinked.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+    // The ancestry and order keys follow the links, they are rebuilt even when
+    // the links were fine.
+    tx.rebuild_tree().await?;
+
+    info!(
+        "fsck repaired {} issue groups, relinked {} nodes",
+        report.parents.len(),
+        report.relinked.len()
+    );
+
+    Ok(report)
+}
+
+/// `id` and every node below it, following the parent links.
+fn subtree_ids(nodes: &[Node], id: &NodeId) -> Vec<NodeId> {
+    let mut children: HashMap<&str, Vec<&NodeId>> = HashMap::new();
+    for node in nodes.iter() {
+        children
+            .entry(node.parent_id.as_ref())
+            .or_default()
+            .push(&node.id);
+    }
+
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut queue = vec![id];
+    let mut ids = vec![];
+    while let Some(id) = queue.pop() {
+        if seen.insert(id.as_str()) {
+            ids.push(id.clone());
+            queue.extend(children.get(id.as_str()).into_iter().flatten());
+        }
+    }
+    ids
+}
+
+fn lost_found_node() -> Node {
+    let now = Utc::now();
+    Node {
+        id: LOST_AND_FOUND_NODE_ID.into(),
+        delete_time: None,
+        name: "Lost and Found".to_owned(),
+        content: "".to_owned(),
+        node_type: NodeType::TiptapV1,
+        domain: "".to_owned(),
+        todo_status: None,
+        parsed_info: ContentParsedInfo::default(),
+        parent_id: MagicNodeId::Never,
+        prev_sliding_id: MagicNodeId::Never,
+        position: 0,
+        readonly: false,
+        version_time: now,
+        initial_time: now.fixed_offset(),
+    }
+}
+
+/// Find every inconsistency among `nodes`, which should be the whole table,
+/// deleted nodes included.
+pub fn check_tree(nodes: &[Node]) -> Vec<ParentReport> {
+    let by_id: HashMap<&str, &Node> = nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+    let mut reports: BTreeMap<String, ParentReport> = BTreeMap::new();
+    let mut report = |parent_id: &MagicNodeId, issue: TreeIssue| {
+        reports
+            .entry(parent_id.as_ref().to_owned())
+            .or_insert_with(|| ParentReport {
+                parent_id: parent_id.clone(),
+                issues: vec![],
+            })
+            .issues
+            .push(issue)
+    };
+
+    let mut sorted: Vec<&Node> = nodes.iter().collect();
+    sorted.sort_by(|a, b| a.id.as_str().cmp(b.id.as_str()));
+
+    for node in sorted.iter() {
+        match &node.parent_id {
+            MagicNodeId::Empty | MagicNodeId::RecycleBin => {}
+            MagicNodeId::Never => report(
+                &node.parent_id,
+                TreeIssue::Orphan {
+                    id: node.id.clone(),
+                },
+            ),
+            MagicNodeId::Id(pid) => match by_id.get(pid.as_str()) {
+                None => report(
+                    &node.parent_id,
+                    TreeIssue::Orphan {
+                        id: node.id.clone(),
+                    },
+                ),
+                Some(parent) if parent.delete_time.is_some() && node.delete_time.is_none() => {
+                    report(
+                        &node.parent_id,
+                        TreeIssue::DeletedParent {
+                            id: node.id.clone(),
+                        },
+                    )
+                }
+                Some(_) => {}
+            },
+        }
+    }
+
+    for ids in parent_cycles(&sorted, &by_id) {
+        let parent_id = &by_id[ids[0].as_str()].parent_id;
+        report(parent_id, TreeIssue::ParentCycle { ids });
+    }
+
+    let mut groups: BTreeMap<&str, Vec<&Node>> = BTreeMap::new();
+    for node in sorted.iter() {
+        groups
+            .entry(node.parent_id.as_ref())
+            .or_default()
+            .push(node);
+    }
+
+    for siblings in groups.values() {
+        let parent_id = &siblings[0].parent_id;
+        let ids: HashSet<&str> = siblings.iter().map(|n| n.id.as_str()).collect();
+
+        let mut by_prev: BTreeMap<&str, Vec<&Node>> = BTreeMap::new();
+        for node in siblings.iter() {
+            by_prev
+                .entry(node.prev_sliding_id.as_ref())
+                .or_default()
+                .push(node);
+        }
+
+        for (_, dups) in by_prev.iter().filter(|(_, v)| v.len() > 1) {
+            report(
+                parent_id,
+                TreeIssue::DuplicatePrev {
+                    prev_id: dups[0].prev_sliding_id.clone(),
+                    ids: dups.iter().map(|n| n.id.clone()).collect(),
+                },
+            );
+        }
+
+        for node in siblings.iter().filter(|n| is_dangling(n, &ids)) {
+            report(
+                parent_id,
+                TreeIssue::DanglingPrev {
+                    id: node.id.clone(),
+                    prev_id: node.prev_sliding_id.clone(),
+                },
+            );
+        }
+
+        let mut reached: HashSet<&str> = HashSet::new();
+        let mut queue: Vec<&Node> = siblings
+            .iter()
+            .filter(|n| is_head(n, &ids))
+            .copied()
+            .collect();
+        while let Some(node) = queue.pop() {
+            if reached.insert(node.id.as_str()) {
+                queue.extend(by_prev.get(node.id.as_str()).into_iter().flatten());
+            }
+        }
+
+        let unreached: Vec<NodeId> = siblings
+            .iter()
+            .filter(|n| !reached.contains(n.id.as_str()))
+            .map(|n| n.id.clone())
+            .collect();
+        if !unreached.is_empty() {
+            report(parent_id, TreeIssue::Cycle { ids: unreached });
+        }
+    }
+
+    reports.into_values().collect()
+}
+
+fn is_dangling(node: &Node, siblings: &HashSet<&str>) -> bool {
+    match &node.prev_sliding_id {
+        MagicNodeId::Empty => false,
+        MagicNodeId::Id(prev) => !siblings.contains(prev.as_str()),
+        MagicNodeId::RecycleBin | MagicNodeId::Never => true,
+    }
+}
+
+fn is_head(node: &Node, siblings: &HashSet<&str>) -> bool {
+    matches!(node.prev_sliding_id, MagicNodeId::Empty) || is_dangling(node, siblings)
+}
+
+/// Loops in the parent chains, each one sorted and reported once.
+fn parent_cycles(sorted: &[&Node], by_id: &HashMap<&str, &Node>) -> Vec<Vec<NodeId>> {
+    let mut done: HashSet<&str> = HashSet::new();
+    let mut cycles = vec![];
+
+    for node in sorted.iter() {
+        let mut path: Vec<&str> = vec![];
+        let mut cursor = Some(node.id.as_str());
+
+        while let Some(id) = cursor {
+            if done.contains(id) {
+                break;
+            }
+            if let Some(pos) = path.iter().position(|e| *e == id) {
+                let mut ids: Vec<NodeId> = path[pos..].iter().map(|e| (*e).into()).collect();
+                ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+                cycles.push(ids);
+                break;
+            }
+            path.push(id);
+            cursor = match &by_id[id].parent_id {
+                MagicNodeId::Id(pid) => by_id.get(pid.as_str()).map(|n| n.id.as_str()),
+                _ => None,
+            };
+        }
+
+        done.extend(path);
+    }
+
+    cycles
+}
+
+/// The relation each node of a touched sibling list should have.
+///
+/// Orphans, live children of deleted parents and one node of every parent
+/// loop are moved under `lost_found`, deleted orphans go to the recycle bin.
+/// Every sibling list which has an issue, lost a node or got a new one is
+/// chained again, see `order_siblings`.
+fn plan_repair(
+    nodes: &[Node],
+    reports: &[ParentReport],
+    lost_found: &NodeId,
+) -> HashMap<NodeId, (MagicNodeId, MagicNodeId)> {
+    let mut parents: HashMap<&str, MagicNodeId> = HashMap::new();
+    let mut touched: BTreeSet<String> = BTreeSet::new();
+
+    let by_id: HashMap<&str, &Node> = nodes.iter().map(|n| (n.id.as_str(), n)).collect();
+    let mut reparent = |id: &NodeId| {
+        if let Some(node) = by_id.get(id.as_str()) {
+            let parent = if node.delete_time.is_some() {
+                MagicNodeId::RecycleBin
+            } else {
+                lost_found.clone().into()
+            };
+            touched.insert(node.parent_id.as_ref().to_owned());
+            touched.insert(parent.as_ref().to_owned());
+            parents.insert(node.id.as_str(), parent);
+        }
+    };
+
+    for report in reports {
+        for issue in report.issues.iter() {
+            match issue {
+                TreeIssue::Orphan { id } | TreeIssue::DeletedParent { id } => reparent(id),
+                TreeIssue::ParentCycle { ids } => reparent(&ids[0]),
+                _ => {}
+            }
+        }
+    }
+
+    // The lost and found node itself lives at the top level, it leaves the
+    // recycle bin when nodes are moved under it.
+    if let Some(node) = by_id.get(lost_found.as_str()) {
+        let used = parents.values().any(|e| e.as_ref() == lost_found.as_str());
+        if matches!(node.parent_id, MagicNodeId::Never) || (used && node.delete_time.is_some()) {
+            touched.insert(node.parent_id.as_ref().to_owned());
+            parents.insert(node.id.as_str(), MagicNodeId::Empty);
+            touched.insert(MagicNodeId::Empty.as_ref().to_owned());
+        }
+    }
+
+    for report in reports {
+        touched.insert(report.parent_id.as_ref().to_owned());
+    }
+
+    let mut groups: BTreeMap<String, Vec<Node>> = BTreeMap::new();
+    for node in nodes.iter() {
+        let (parent_id, prev_id) = match parents.get(node.id.as_str()) {
+            Some(parent_id) => (parent_id.clone(), MagicNodeId::Never),
+            None => (node.parent_id.clone(), node.prev_sliding_id.clone()),
+        };
+        if touched.contains(parent_id.as_ref()) {
+            groups
+                .entry(parent_id.as_ref().to_owned())
+                .or_default()
+                .push(Node {
+                    parent_id,
+                    prev_sliding_id: prev_id,
+                    ..node.clone()
+                });
+        }
+    }
+
+    let mut relations = HashMap::new();
+    for siblings in groups.into_values() {
+        let ordered = order_siblings(&siblings.iter().collect::<Vec<&Node>>());
+        let mut prev_id = MagicNodeId::Empty;
+        for node in ordered {
+            relations.insert(node.id.clone(), (node.parent_id.clone(), prev_id));
+            prev_id = node.id.clone().into();
+        }
+    }
+
+    relations
+}
+
+/// Order a broken sibling list, the valid chains are kept as they are.
+///
+/// Walks from every head, a node with an empty or dangling predecessor, and
+/// puts each node right before the nodes pointing at it. Heads, several
+/// successors of one node and the nodes left in loops are taken by
+/// `version_time`.
+pub(crate) fn order_siblings<'a>(siblings: &[&'a Node]) -> Vec<&'a Node> {
+    let by_time = |a: &&Node, b: &&Node| {
+        a.version_time
+            .cmp(&b.version_time)
+            .then_with(|| a.id.as_str().cmp(b.id.as_str()))
+    };
+
+    let ids: HashSet<&str> = siblings.iter().map(|n| n.id.as_str()).collect();
+    let mut nexts: HashMap<&str, Vec<&Node>> = HashMap::new();
+    for node in siblings.iter().copied() {
+        nexts
+            .entry(node.prev_sliding_id.as_ref())
+            .or_default()
+            .push(node);
+    }
+    nexts.values_mut().for_each(|v| v.sort_by(by_time));
+
+    let mut starts: Vec<&Node> = siblings
+        .iter()
+        .copied()
+        .filter(|n| is_head(n, &ids))
+        .collect();
+    starts.sort_by(by_time);
+    let mut rest: Vec<&Node> = siblings.to_vec();
+    rest.sort_by(by_time);
+    starts.extend(rest);
+
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut ordered = vec![];
+    for start in starts {
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            if !visited.insert(node.id.as_str()) {
+                continue;
+            }
+            ordered.push(node);
+            if let Some(next) = nexts.get(node.id.as_str()) {
+                stack.extend(next.iter().rev());
+            }
+        }
+    }
+
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, Utc};
+
+    use crate::{
+        constants::LOST_AND_FOUND_NODE_ID,
+        mapper::{
+            memory_mapper::MemoryMapper,
+            node::NodeMapper,
+            nodefilter::{NodeFetchReq, NodeFilter, NodeSelection},
+            testutils,
+        },
+        model::node::{MagicNodeId, Node},
+    };
+
+    use super::{check_tree, FsckReq, TreeIssue};
+
+    fn node(id: &str, parent_id: &str, prev_sliding_id: &str, age: i64) -> Node {
+        Node {
+            version_time: Utc::now() - Duration::seconds(age),
+            ..testutils::node(
+                id,
+                parent_id.to_owned().into(),
+                prev_sliding_id.to_owned().into(),
+            )
+        }
+    }
+
+    fn broken() -> Vec<Node> {
+        let empty = MagicNodeId::Empty;
+        let e = empty.as_ref();
+        vec![
+            node("a", e, e, 10),
+            node("b", e, "a", 9),
+            // duplicate predecessor
+            node("c", e, "a", 8),
+            // dangling predecessor
+            node("d", "a", "x", 7),
+            // loop among siblings
+            node("e", "a", "f", 6),
+            node("f", "a", "e", 5),
+            // orphan
+            node("g", "y", e, 4),
+            // loop among parents
+            node("h", "i", e, 3),
+            node("i", "h", e, 2),
+        ]
+    }
+
+    #[test]
+    fn test_check() {
+        let reports = check_tree(&broken());
+        let issues: Vec<&TreeIssue> = reports.iter().flat_map(|r| r.issues.iter()).collect();
+
+        assert!(issues
+            .iter()
+            .any(|i| matches!(i, TreeIssue::DuplicatePrev { ids, .. } if ids.len() == 2)));
+        assert!(issues
+            .iter()
+            .any(|i| matches!(i, TreeIssue::DanglingPrev { id, .. } if id.as_str() == "d")));
+        assert!(issues
+            .iter()
+            .any(|i| matches!(i, TreeIssue::Cycle { ids } if ids.len() == 2)));
+        assert!(issues
+            .iter()
+            .any(|i| matches!(i, TreeIssue::Orphan { id } if id.as_str() == "g")));
+        assert!(issues
+            .iter()
+            .any(|i| matches!(i, TreeIssue::ParentCycle { ids } if ids.len() == 2)));
+    }
+
+    #[tokio::test]
+    async fn test_repair() {
+        let mapper = MemoryMapper::new();
+        {
+            let mut tx = mapper.begin().await.unwrap();
+            for node in broken() {
+                tx.insert_node_row(&node).await.unwrap();
+            }
+            tx.commit().await.unwrap();
+        }
+
+        let report = mapper.fsck(&FsckReq { repair: true }).await.unwrap();
+        assert!(!report.parents.is_empty());
+        assert!(!report.relinked.is_empty());
+
+        let report = mapper.fsck(&FsckReq { repair: false }).await.unwrap();
+        assert!(report.parents.is_empty(), "{:?}", report.parents);
+
+        let nodes = mapper
+            .query_nodes(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::WithHistory]),
+                filter: Some(NodeFilter::Children(LOST_AND_FOUND_NODE_ID.into())),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(nodes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repair_deleted_lost_found() {
+        let mapper = MemoryMapper::new();
+        {
+            let recycle_bin = MagicNodeId::RecycleBin;
+            let empty = MagicNodeId::Empty;
+            let mut tx = mapper.begin().await.unwrap();
+            for mut node in [
+                node(
+                    LOST_AND_FOUND_NODE_ID,
+                    recycle_bin.as_ref(),
+                    empty.as_ref(),
+                    3,
+                ),
+                node("z", LOST_AND_FOUND_NODE_ID, empty.as_ref(), 2),
+            ] {
+                node.delete_time = Some(Utc::now());
+                tx.insert_node_row(&node).await.unwrap();
+            }
+            // orphan
+            tx.insert_node_row(&node("g", "y", empty.as_ref(), 1))
+                .await
+                .unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        mapper.fsck(&FsckReq { repair: true }).await.unwrap();
+        let report = mapper.fsck(&FsckReq { repair: false }).await.unwrap();
+        assert!(report.parents.is_empty(), "{:?}", report.parents);
+
+        let lost_found = mapper
+            .query_nodes(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::WithHistory]),
+                filter: Some(NodeFilter::Id(LOST_AND_FOUND_NODE_ID.into())),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .remove(0);
+        assert!(lost_found.delete_time.is_none());
+        assert!(matches!(lost_found.parent_id, MagicNodeId::Empty));
+
+        let mut ids: Vec<String> = mapper
+            .query_nodes(&NodeFetchReq {
+                selection: None,
+                filter: Some(NodeFilter::Children(LOST_AND_FOUND_NODE_ID.into())),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id.as_str().to_owned())
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec!["g", "z"]);
+    }
+}
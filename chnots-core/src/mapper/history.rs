@@ -0,0 +1,133 @@
+use std::{collections::HashSet, num::NonZeroU32};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_SNAPSHOT_DISTANCE: usize = 8;
+
+const HOUR: i64 = 3600;
+const DAY: i64 = 24 * HOUR;
+const WEEK: i64 = 7 * DAY;
+
+/// How the old versions of nodes are kept in `nodes_history`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct HistoryConfig {
+    /// An edit keeps the replaced version when the Levenshtein distance
+    /// between the contents is over it.
+    #[serde(default = "default_snapshot_distance")]
+    pub snapshot_distance: usize,
+    /// Seconds between two compactions, one day by default. Zero is
+    /// refused when the config is read.
+    pub interval: Option<NonZeroU32>,
+    /// Nothing is ever removed without it.
+    pub retention: Option<RetentionPolicy>,
+}
+
+fn default_snapshot_distance() -> usize {
+    DEFAULT_SNAPSHOT_DISTANCE
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            snapshot_distance: DEFAULT_SNAPSHOT_DISTANCE,
+            interval: None,
+            retention: None,
+        }
+    }
+}
+
+/// Every snapshot younger than `keep_all_days` is kept, then the newest one
+/// of each hour until `hourly_days`, of each day until `daily_days` and of
+/// each week after that. Snapshots older than `max_days` are removed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    pub keep_all_days: u32,
+    pub hourly_days: u32,
+    pub daily_days: u32,
+    #[serde(default)]
+    pub max_days: Option<u32>,
+}
+
+impl RetentionPolicy {
+    /// The snapshots of one node that the policy drops at `now`.
+    pub fn expired(&self, times: &[DateTime<Utc>], now: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let mut times = times.to_vec();
+        times.sort_by_key(|e| std::cmp::Reverse(*e));
+        times.dedup();
+
+        // The newest snapshot of a bucket is seen first and kept.
+        let mut kept = HashSet::new();
+        let mut expired = vec![];
+        for time in times {
+            let age = (now - time).num_seconds();
+            let span = if age < self.keep_all_days as i64 * DAY {
+                continue;
+            } else if age < self.hourly_days as i64 * DAY {
+                HOUR
+            } else if age < self.daily_days as i64 * DAY {
+                DAY
+            } else if self
+                .max_days
+                .is_none_or(|max_days| age < max_days as i64 * DAY)
+            {
+                WEEK
+            } else {
+                expired.push(time);
+                continue;
+            };
+
+            if !kept.insert((span, time.timestamp().div_euclid(span))) {
+                expired.push(time);
+            }
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{HistoryConfig, RetentionPolicy};
+
+    #[test]
+    fn test_expired() {
+        let policy = RetentionPolicy {
+            keep_all_days: 1,
+            hourly_days: 2,
+            daily_days: 7,
+            max_days: Some(30),
+        };
+        let now = Utc.with_ymd_and_hms(2024, 6, 1, 12, 0, 0).unwrap();
+        let ago = |minutes: i64| now - Duration::minutes(minutes);
+
+        let times = vec![
+            // Kept, all of them.
+            ago(1),
+            ago(2),
+            // The same hour a day and a half ago, one is kept.
+            ago(36 * 60 + 50),
+            ago(36 * 60 + 10),
+            // The same day a few days ago, one is kept.
+            ago(4 * 24 * 60 + 60),
+            ago(4 * 24 * 60 + 120),
+            // Too old.
+            ago(31 * 24 * 60),
+        ];
+
+        let mut expired = policy.expired(&times, now);
+        expired.sort();
+        assert_eq!(
+            expired,
+            vec![ago(31 * 24 * 60), ago(4 * 24 * 60 + 120), ago(36 * 60 + 50)]
+        );
+    }
+
+    #[test]
+    fn test_interval() {
+        let config: HistoryConfig = serde_json::from_str(r#"{"interval":60}"#).unwrap();
+        assert_eq!(config.interval.map(|e| e.get()), Some(60));
+        assert!(serde_json::from_str::<HistoryConfig>(r#"{"interval":0}"#).is_err());
+    }
+}
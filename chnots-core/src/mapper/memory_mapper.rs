@@ -0,0 +1,2500 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use chin_tools::log_and_err;
+use chrono::{DateTime, Utc};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+use crate::{
+    backup::v1::{
+        table::{Assets, Nodes, NodesHistory, TableRow},
+        BackupContent, BackupHandlerV1,
+    },
+    model::{
+        asset::Asset,
+        node::{ContentParsedInfo, MagicNodeId, Node, NodeId, NodeRef},
+        tag::TagStat,
+        todo::TodoEvent,
+    },
+};
+
+use super::{
+    asset::AssetMapper,
+    history::HistoryConfig,
+    migration::Migration,
+    node::{
+        NodeLink, NodeMapper, NodeRenameReq, NodeUpdateReadonlyReq, NodeVersion, NodeVersionReq,
+    },
+    nodefilter::{NodeFetchReq, NodeFilter, NodeSelection, TimeField},
+    page::{self, sort_value, CursorValue, NodeSortKey},
+    recycle::{RecycleMapper, RecycleOrigin},
+    search::{search_terms, SearchMapper, SearchReq, SearchRow},
+    tag::TagMapper,
+    todo::{TodoCreateReq, TodoMapper},
+    tx::NodeTx,
+    Mapper,
+};
+
+const NODE_FIELDS: [&str; 13] = [
+    "id",
+    "name",
+    "content",
+    "node_type",
+    "domain",
+    "todo_status",
+    "delete_time",
+    "parent_id",
+    "prev_sliding_id",
+    "position",
+    "readonly",
+    "version_time",
+    "initial_time",
+];
+
+#[derive(Default)]
+struct MemoryStore {
+    nodes: HashMap<NodeId, Node>,
+    nodes_history: Vec<Node>,
+    assets: HashMap<String, Asset>,
+    tags: HashMap<NodeId, Vec<String>>,
+    links: HashMap<NodeId, Vec<NodeRef>>,
+    /// Plain text of nodes for the full text search.
+    search: HashMap<NodeId, String>,
+    recycle_bin: HashMap<NodeId, RecycleOrigin>,
+}
+
+impl MemoryStore {
+    fn query_nodes(&self, node_filter: &NodeFetchReq) -> Vec<Node> {
+        let with_content = node_filter.with_selection(&NodeSelection::WithContent, false);
+        let with_history = node_filter.with_selection(&NodeSelection::WithHistory, false);
+        let limit = node_filter.with_limit();
+        let sort = node_filter.sql_sort();
+
+        let value = |n: &Node| match sort {
+            Some(sort) if sort.key == NodeSortKey::TreeOrder => Some(CursorValue::Text(
+                page::tree_key(n, |parent_id| match parent_id {
+                    MagicNodeId::Id(id) => self.nodes.get(id),
+                    _ => None,
+                }),
+            )),
+            Some(sort) => sort_value(sort.key, n),
+            None => None,
+        };
+        let mut nodes: Vec<(Option<CursorValue>, &Node)> = self
+            .nodes
+            .values()
+            .filter(|n| with_history || n.delete_time.is_none())
+            .filter(|n| {
+                node_filter
+                    .filter
+                    .as_ref()
+                    .is_none_or(|f| self.matches(f, n))
+            })
+            .map(|n| (value(n), n))
+            .filter(|(value, n)| match (sort, node_filter.cursor.as_ref()) {
+                (Some(_), Some(cursor)) => cursor.is_before(value, &n.id),
+                _ => true,
+            })
+            .collect();
+        if let Some(sort) = sort {
+            nodes.sort_by(|a, b| sort.compare_values((&a.0, &a.1.id), (&b.0, &b.1.id)));
+        }
+
+        nodes
+            .into_iter()
+            .map(|(_, n)| n)
+            .take(limit.map_or(usize::MAX, |l| l.max(0) as usize))
+            .map(|n| Node {
+                content: if with_content {
+                    n.content.clone()
+                } else {
+                    "".to_owned()
+                },
+                ..n.clone()
+            })
+            .collect()
+    }
+
+    fn matches(&self, filter: &NodeFilter, node: &Node) -> bool {
+        match filter {
+            NodeFilter::All => true,
+            NodeFilter::Children(id) => node.parent_id.as_ref() == id.as_str(),
+            NodeFilter::Id(id) => &node.id == id,
+            NodeFilter::Tag(tag) => self
+                .tags
+                .get(&node.id)
+                .is_some_and(|tags| tags.contains(tag)),
+            NodeFilter::And(nf) => nf.iter().all(|f| self.matches(f, node)),
+            NodeFilter::Not(nf) => !self.matches(nf, node),
+            NodeFilter::Or(nf) => nf.is_empty() || nf.iter().any(|f| self.matches(f, node)),
+            NodeFilter::Contains(part) => {
+                let part = part.to_lowercase();
+                node.content.to_lowercase().contains(&part)
+                    || node.name.to_lowercase().contains(&part)
+            }
+            NodeFilter::Search(query) => self.search_rank(query, node).is_some(),
+            NodeFilter::Time(field, range) => {
+                let time = match field {
+                    TimeField::VersionTime => Some(node.version_time),
+                    TimeField::InitialTime => Some(node.initial_time.to_utc()),
+                    TimeField::DeleteTime => node.delete_time,
+                };
+                time.is_some_and(|time| range.contains(&time))
+            }
+            NodeFilter::TodoStatus(statuses) => node
+                .todo_status
+                .as_ref()
+                .is_some_and(|status| statuses.iter().any(|e| e.as_ref() == status.as_ref())),
+            NodeFilter::Domain(domain) => &node.domain == domain,
+            NodeFilter::Readonly(readonly) => node.readonly == *readonly,
+            NodeFilter::NodeType(node_type) => node.node_type.as_ref() == node_type.as_ref(),
+            NodeFilter::Descendant(id) => self.descendant_ids(id).contains_key(&node.id),
+            NodeFilter::DescendantWithin(id, depth) => self.is_within(id, node, *depth),
+            NodeFilter::Ancestor(id) => self
+                .ancestor_ids(id)
+                .values()
+                .any(|pid| pid.as_ref() == node.id.as_str()),
+        }
+    }
+
+    /// Every term must be a word of the name or the text, the name weighs
+    /// more like in the other backends.
+    fn search_rank(&self, query: &str, node: &Node) -> Option<f64> {
+        let terms = search_terms(query);
+        let name = search_terms(&node.name);
+        let body = search_terms(self.search.get(&node.id).map_or("", |e| e.as_str()));
+        if terms.is_empty() {
+            return None;
+        }
+
+        let mut rank = 0.0;
+        for term in terms.iter() {
+            let (in_name, in_body) = (name.contains(term), body.contains(term));
+            if !in_name && !in_body {
+                return None;
+            }
+            if in_name {
+                rank += 10.0;
+            }
+            if in_body {
+                rank += 1.0;
+            }
+        }
+        Some(rank)
+    }
+
+    fn versions(&self, id: &NodeId) -> Vec<NodeVersion> {
+        let version = |node: &Node, current| NodeVersion {
+            version_time: node.version_time,
+            name: node.name.clone(),
+            size: node.content.chars().count() as i64,
+            current,
+        };
+
+        let mut history: Vec<NodeVersion> = self
+            .nodes_history
+            .iter()
+            .filter(|n| &n.id == id)
+            .map(|n| version(n, false))
+            .collect();
+        history.sort_by_key(|e| std::cmp::Reverse(e.version_time));
+
+        self.nodes
+            .get(id)
+            .map(|n| version(n, true))
+            .into_iter()
+            .chain(history)
+            .collect()
+    }
+
+    fn version(&self, id: &NodeId, version_time: &DateTime<Utc>) -> Option<Node> {
+        let current = self.nodes.get(id)?;
+        if &current.version_time == version_time {
+            return Some(current.clone());
+        }
+        self.nodes_history
+            .iter()
+            .rev()
+            .find(|n| &n.id == id && &n.version_time == version_time)
+            .map(|n| Node {
+                parent_id: current.parent_id.clone(),
+                prev_sliding_id: current.prev_sliding_id.clone(),
+                position: current.position,
+                ..n.clone()
+            })
+    }
+
+    fn live_name(&self, id: &NodeId) -> Option<String> {
+        self.nodes
+            .get(id)
+            .filter(|n| n.delete_time.is_none())
+            .map(|n| n.name.clone())
+    }
+
+    /// The sibling whose predecessor is `prev_id` under `parent_id`, deleted
+    /// or not, as in the SQL backends.
+    fn next_of(&self, parent_id: &MagicNodeId, prev_id: &MagicNodeId) -> MagicNodeId {
+        self.nodes
+            .values()
+            .find(|n| {
+                n.parent_id.as_ref() == parent_id.as_ref()
+                    && n.prev_sliding_id.as_ref() == prev_id.as_ref()
+            })
+            .map(|n| n.id.clone().into())
+            .unwrap_or_default()
+    }
+
+    fn descendant_ids(&self, id: &NodeId) -> HashMap<NodeId, MagicNodeId> {
+        let mut children: HashMap<&str, Vec<&Node>> = HashMap::new();
+        self.nodes.values().for_each(|n| {
+            children.entry(n.parent_id.as_ref()).or_default().push(n);
+        });
+
+        let mut map = HashMap::new();
+        let mut queue = VecDeque::from([id.as_str()]);
+        while let Some(pid) = queue.pop_front() {
+            for child in children.get(pid).into_iter().flatten() {
+                if map
+                    .insert(child.id.clone(), child.parent_id.clone())
+                    .is_none()
+                {
+                    queue.push_back(child.id.as_str());
+                }
+            }
+        }
+        map
+    }
+
+    /// Whether `node` is below `id`, at most `depth` levels down.
+    fn is_within(&self, id: &NodeId, node: &Node, depth: u32) -> bool {
+        let mut parent_id = &node.parent_id;
+        for _ in 0..depth {
+            match parent_id {
+                MagicNodeId::Id(pid) if pid == id => return true,
+                MagicNodeId::Id(pid) => match self.nodes.get(pid) {
+                    Some(parent) => parent_id = &parent.parent_id,
+                    None => return false,
+                },
+                _ => return parent_id.as_ref() == id.as_str(),
+            }
+        }
+        false
+    }
+
+    fn ancestor_ids(&self, id: &NodeId) -> HashMap<NodeId, MagicNodeId> {
+        let mut map = HashMap::new();
+        let mut cursor = self.nodes.get(id);
+        while let Some(node) = cursor {
+            if map
+                .insert(node.id.clone(), node.parent_id.clone())
+                .is_some()
+            {
+                break;
+            }
+            cursor = match &node.parent_id {
+                MagicNodeId::Id(pid) => self.nodes.get(pid),
+                _ => None,
+            };
+        }
+        map
+    }
+}
+
+/// A `Mapper` kept entirely in process memory.
+///
+/// Nothing is persisted, which makes it suitable for embedding `chnots-core`
+/// and for tests that should not depend on a running database.
+#[derive(Clone)]
+pub struct MemoryMapper {
+    store: Arc<Mutex<MemoryStore>>,
+    pub snapshot_distance: usize,
+}
+
+impl Default for MemoryMapper {
+    fn default() -> Self {
+        Self {
+            store: Default::default(),
+            snapshot_distance: HistoryConfig::default().snapshot_distance,
+        }
+    }
+}
+
+impl MemoryMapper {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+#[async_trait]
+impl AssetMapper for MemoryMapper {
+    async fn insert_asset(
+        &self,
+        ori_file_name: &str,
+        id: String,
+        content_type: String,
+        domain: Option<String>,
+    ) -> anyhow::Result<Asset> {
+        let asset = Asset {
+            id,
+            domain,
+            ori_file_name: ori_file_name.to_string(),
+            content_type,
+            create_time: Utc::now(),
+        };
+
+        let mut store = self.store.lock().await;
+        if store.assets.contains_key(&asset.id) {
+            return log_and_err!("asset {} is already existed", asset.id);
+        }
+        store.assets.insert(asset.id.clone(), asset.clone());
+
+        Ok(asset)
+    }
+
+    async fn query_asset_by_id(&self, id: &str) -> anyhow::Result<Asset> {
+        let store = self.store.lock().await;
+        match store.assets.get(id) {
+            Some(asset) => Ok(asset.clone()),
+            None => log_and_err!("there are no asset with id: {}", id),
+        }
+    }
+}
+
+#[async_trait]
+impl NodeMapper for MemoryMapper {
+    async fn begin(&self) -> anyhow::Result<Box<dyn NodeTx>> {
+        Ok(Box::new(MemoryTx {
+            guard: self.store.clone().lock_owned().await,
+            undo: vec![],
+            snapshot_distance: self.snapshot_distance,
+        }))
+    }
+
+    async fn update_node_name(&self, req: &NodeRenameReq) -> anyhow::Result<u64> {
+        let mut store = self.store.lock().await;
+        Ok(match store.nodes.get_mut(&req.id) {
+            Some(node) => {
+                node.name = req.name.clone();
+                1
+            }
+            None => 0,
+        })
+    }
+
+    async fn update_node_readonly(&self, req: &NodeUpdateReadonlyReq) -> anyhow::Result<u64> {
+        let mut store = self.store.lock().await;
+        Ok(match store.nodes.get_mut(&req.id) {
+            Some(node) => {
+                node.readonly = req.readonly;
+                1
+            }
+            None => 0,
+        })
+    }
+
+    async fn query_nodes(&self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
+        Ok(self.store.lock().await.query_nodes(node_filter))
+    }
+
+    async fn query_child_counts(&self, ids: &[NodeId]) -> anyhow::Result<HashMap<NodeId, u64>> {
+        let store = self.store.lock().await;
+        let mut counts: HashMap<NodeId, u64> = HashMap::new();
+        for node in store.nodes.values() {
+            if let MagicNodeId::Id(parent_id) = &node.parent_id {
+                if node.delete_time.is_none() && ids.contains(parent_id) {
+                    *counts.entry(parent_id.clone()).or_default() += 1;
+                }
+            }
+        }
+        Ok(counts)
+    }
+
+    async fn query_backlinks(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>> {
+        let store = self.store.lock().await;
+
+        let mut links: Vec<(&Node, &NodeRef)> = store
+            .links
+            .iter()
+            .filter_map(|(source_id, refs)| {
+                let source = store.nodes.get(source_id)?;
+                let node_ref = refs.iter().find(|r| &r.id == id)?;
+                source.delete_time.is_none().then_some((source, node_ref))
+            })
+            .collect();
+        links.sort_by_key(|e| std::cmp::Reverse(e.0.version_time));
+
+        Ok(links
+            .into_iter()
+            .map(|(source, r)| NodeLink {
+                source_id: source.id.clone(),
+                target_id: r.id.clone(),
+                name: Some(source.name.clone()),
+                text: r.text.clone(),
+                snippet: r.snippet.clone(),
+            })
+            .collect())
+    }
+
+    async fn query_outgoing_links(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>> {
+        let store = self.store.lock().await;
+
+        Ok(store
+            .links
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|r| NodeLink {
+                source_id: id.clone(),
+                target_id: r.id.clone(),
+                name: store.live_name(&r.id),
+                text: r.text.clone(),
+                snippet: r.snippet.clone(),
+            })
+            .collect())
+    }
+
+    async fn query_versions(&self, id: &NodeId) -> anyhow::Result<Vec<NodeVersion>> {
+        Ok(self.store.lock().await.versions(id))
+    }
+
+    async fn query_version(&self, req: &NodeVersionReq) -> anyhow::Result<Option<Node>> {
+        Ok(self.store.lock().await.version(&req.id, &req.version_time))
+    }
+
+    async fn find_descendant_ids(
+        &self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        Ok(self.store.lock().await.descendant_ids(id))
+    }
+
+    async fn find_ancestor_ids(&self, id: &NodeId) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        Ok(self.store.lock().await.ancestor_ids(id))
+    }
+}
+
+/// A `NodeTx` writing straight into the store.
+///
+/// The store stays locked for the whole transaction. Every write keeps what
+/// it replaced in `undo`, which is put back when the transaction is dropped
+/// without `commit`.
+struct MemoryTx {
+    guard: OwnedMutexGuard<MemoryStore>,
+    undo: Vec<Undo>,
+    snapshot_distance: usize,
+}
+
+/// What one write replaced.
+enum Undo {
+    Node(NodeId, Option<Box<Node>>),
+    HistoryPush,
+    History(Vec<(usize, Node)>),
+    Asset(String, Option<Asset>),
+    Tags(NodeId, Option<Vec<String>>),
+    Links(NodeId, Option<Vec<NodeRef>>),
+    Search(NodeId, Option<String>),
+    RecycleOrigin(NodeId, Option<RecycleOrigin>),
+}
+
+/// Set or remove `key`, return the value it had.
+fn put<K: Eq + std::hash::Hash, V>(map: &mut HashMap<K, V>, key: K, value: Option<V>) -> Option<V> {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    }
+}
+
+/// `Vec::retain` which returns the removed items with their old indexes.
+fn retain_indexed<T>(items: &mut Vec<T>, keep: impl Fn(&T) -> bool) -> Vec<(usize, T)> {
+    let mut removed = vec![];
+    let mut kept = Vec::with_capacity(items.len());
+    for (index, item) in std::mem::take(items).into_iter().enumerate() {
+        if keep(&item) {
+            kept.push(item);
+        } else {
+            removed.push((index, item));
+        }
+    }
+    *items = kept;
+    removed
+}
+
+/// Put the removed items back, the indexes are ascending.
+fn restore_indexed<T>(items: &mut Vec<T>, removed: Vec<(usize, T)>) {
+    for (index, item) in removed {
+        items.insert(index, item);
+    }
+}
+
+impl MemoryTx {
+    fn node_mut(&mut self, id: &NodeId) -> Option<&mut Node> {
+        let node = self.guard.nodes.get_mut(id)?;
+        self.undo
+            .push(Undo::Node(id.clone(), Some(Box::new(node.clone()))));
+        Some(node)
+    }
+
+    /// Set or remove a node, return whether there was one.
+    fn put_node(&mut self, id: &NodeId, node: Option<Node>) -> bool {
+        let old = put(&mut self.guard.nodes, id.clone(), node);
+        let existed = old.is_some();
+        self.undo.push(Undo::Node(id.clone(), old.map(Box::new)));
+        existed
+    }
+
+    fn retain_history(&mut self, keep: impl Fn(&Node) -> bool) -> usize {
+        let removed = retain_indexed(&mut self.guard.nodes_history, keep);
+        let count = removed.len();
+        if count > 0 {
+            self.undo.push(Undo::History(removed));
+        }
+        count
+    }
+
+    /// Number the children of `parent_id` along their links, return how many
+    /// of them got another order key.
+    fn renumber(&mut self, parent_id: &MagicNodeId) -> u64 {
+        let mut count = 0;
+        let mut prev_id = MagicNodeId::Empty;
+        let mut position = 0;
+        while let MagicNodeId::Id(id) = self.guard.next_of(parent_id, &prev_id) {
+            if self.guard.nodes[&id].position != position {
+                self.node_mut(&id).unwrap().position = position;
+                count += 1;
+            }
+            position += 1;
+            prev_id = id.into();
+        }
+        count
+    }
+}
+
+impl Drop for MemoryTx {
+    fn drop(&mut self) {
+        let store = &mut *self.guard;
+        while let Some(undo) = self.undo.pop() {
+            match undo {
+                Undo::Node(id, node) => {
+                    put(&mut store.nodes, id, node.map(|e| *e));
+                }
+                Undo::HistoryPush => {
+                    store.nodes_history.pop();
+                }
+                Undo::History(removed) => restore_indexed(&mut store.nodes_history, removed),
+                Undo::Asset(id, asset) => {
+                    put(&mut store.assets, id, asset);
+                }
+                Undo::Tags(id, tags) => {
+                    put(&mut store.tags, id, tags);
+                }
+                Undo::Links(id, links) => {
+                    put(&mut store.links, id, links);
+                }
+                Undo::Search(id, text) => {
+                    put(&mut store.search, id, text);
+                }
+                Undo::RecycleOrigin(id, origin) => {
+                    put(&mut store.recycle_bin, id, origin);
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl NodeTx for MemoryTx {
+    fn snapshot_distance(&self) -> usize {
+        self.snapshot_distance
+    }
+
+    async fn lock_parents(&mut self, _parent_ids: &[&MagicNodeId]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn lock_tree(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn query_nodes(&mut self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
+        Ok(self.guard.query_nodes(node_filter))
+    }
+
+    async fn query_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        Ok(self.guard.nodes.get(id).cloned())
+    }
+
+    async fn lock_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        Ok(self.guard.nodes.get(id).cloned())
+    }
+
+    async fn query_next_id(
+        &mut self,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<MagicNodeId> {
+        Ok(self.guard.next_of(parent_id, prev_id))
+    }
+
+    async fn update_relation(
+        &mut self,
+        id: &NodeId,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<u64> {
+        if !self.guard.nodes.contains_key(id) {
+            return Ok(0);
+        }
+        let position = match prev_id {
+            MagicNodeId::Id(prev_id) => self.guard.nodes.get(prev_id).map_or(0, |e| e.position + 1),
+            _ => 0,
+        };
+        let shifted: Vec<NodeId> = self
+            .guard
+            .nodes
+            .values()
+            .filter(|n| {
+                n.parent_id.as_ref() == parent_id.as_ref() && n.position >= position && &n.id != id
+            })
+            .map(|n| n.id.clone())
+            .collect();
+        for shifted_id in shifted.iter() {
+            self.node_mut(shifted_id).unwrap().position += 1;
+        }
+
+        let node = self.node_mut(id).unwrap();
+        node.parent_id = parent_id.clone();
+        node.prev_sliding_id = prev_id.clone();
+        node.position = position;
+        Ok(1)
+    }
+
+    async fn move_paths(&mut self, _id: &NodeId, _parent_id: &MagicNodeId) -> anyhow::Result<()> {
+        // The ancestry is walked along the parents, nothing is kept for it.
+        Ok(())
+    }
+
+    async fn renumber_children(&mut self, parent_id: &MagicNodeId) -> anyhow::Result<u64> {
+        Ok(self.renumber(parent_id))
+    }
+
+    async fn rebuild_tree(&mut self) -> anyhow::Result<u64> {
+        let parent_ids: HashMap<String, MagicNodeId> = self
+            .guard
+            .nodes
+            .values()
+            .filter(|n| !matches!(n.parent_id, MagicNodeId::Never))
+            .map(|n| (n.parent_id.as_ref().to_owned(), n.parent_id.clone()))
+            .collect();
+        for parent_id in parent_ids.into_values() {
+            self.renumber(&parent_id);
+        }
+        Ok(self.guard.nodes.len() as u64)
+    }
+
+    async fn update_prev_id(&mut self, id: &NodeId, prev_id: &MagicNodeId) -> anyhow::Result<u64> {
+        Ok(match self.node_mut(id) {
+            Some(node) => {
+                node.prev_sliding_id = prev_id.clone();
+                1
+            }
+            None => 0,
+        })
+    }
+
+    async fn insert_node_row(&mut self, node: &Node) -> anyhow::Result<u64> {
+        if self.guard.nodes.contains_key(&node.id) {
+            return log_and_err!("node {:?} is already existed", node.id);
+        }
+        self.put_node(
+            &node.id,
+            Some(Node {
+                todo_status: None,
+                parsed_info: ContentParsedInfo::default(),
+                ..node.clone()
+            }),
+        );
+        Ok(1)
+    }
+
+    async fn update_node_row(&mut self, node: &Node) -> anyhow::Result<u64> {
+        Ok(match self.node_mut(&node.id) {
+            Some(old) => {
+                old.name = node.name.clone();
+                old.content = node.content.clone();
+                old.node_type = node.node_type.clone();
+                old.domain = node.domain.clone();
+                old.readonly = node.readonly;
+                old.version_time = node.version_time;
+                1
+            }
+            None => 0,
+        })
+    }
+
+    async fn update_todo_status(
+        &mut self,
+        id: &NodeId,
+        todo_status: &Option<TodoEvent>,
+    ) -> anyhow::Result<u64> {
+        Ok(match self.node_mut(id) {
+            Some(node) => {
+                node.todo_status = todo_status.clone();
+                1
+            }
+            None => 0,
+        })
+    }
+
+    async fn copy_node_to_history(&mut self, id: &NodeId) -> anyhow::Result<u64> {
+        Ok(match self.guard.nodes.get(id).cloned() {
+            Some(node) => {
+                self.guard.nodes_history.push(node);
+                self.undo.push(Undo::HistoryPush);
+                1
+            }
+            None => 0,
+        })
+    }
+
+    async fn query_version(
+        &mut self,
+        id: &NodeId,
+        version_time: &DateTime<Utc>,
+    ) -> anyhow::Result<Option<Node>> {
+        Ok(self.guard.version(id, version_time))
+    }
+
+    async fn query_history_times(&mut self) -> anyhow::Result<Vec<(NodeId, DateTime<Utc>)>> {
+        Ok(self
+            .guard
+            .nodes_history
+            .iter()
+            .map(|n| (n.id.clone(), n.version_time))
+            .collect())
+    }
+
+    async fn delete_history(
+        &mut self,
+        id: &NodeId,
+        version_times: &[DateTime<Utc>],
+    ) -> anyhow::Result<u64> {
+        let count =
+            self.retain_history(|n| &n.id != id || !version_times.contains(&n.version_time));
+        Ok(count as u64)
+    }
+
+    async fn mark_deleted(&mut self, ids: &[NodeId], time: &DateTime<Utc>) -> anyhow::Result<u64> {
+        let mut count = 0;
+        for id in ids {
+            if let Some(node) = self.node_mut(id) {
+                node.delete_time = Some(*time);
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn clear_deleted(&mut self, ids: &[NodeId]) -> anyhow::Result<u64> {
+        let mut count = 0;
+        for id in ids {
+            if let Some(node) = self.node_mut(id) {
+                node.delete_time = None;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn replace_recycle_origin(
+        &mut self,
+        id: &NodeId,
+        origin: &RecycleOrigin,
+    ) -> anyhow::Result<()> {
+        let old = put(
+            &mut self.guard.recycle_bin,
+            id.clone(),
+            Some(origin.clone()),
+        );
+        self.undo.push(Undo::RecycleOrigin(id.clone(), old));
+        Ok(())
+    }
+
+    async fn query_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<Option<RecycleOrigin>> {
+        Ok(self.guard.recycle_bin.get(id).cloned())
+    }
+
+    async fn delete_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<u64> {
+        let old = put(&mut self.guard.recycle_bin, id.clone(), None);
+        let count = old.as_ref().map_or(0, |_| 1);
+        self.undo.push(Undo::RecycleOrigin(id.clone(), old));
+        Ok(count)
+    }
+
+    async fn purge_node_rows(&mut self, ids: &[NodeId]) -> anyhow::Result<u64> {
+        self.retain_history(|n| !ids.contains(&n.id));
+        let mut count = 0;
+        for id in ids {
+            let store = &mut *self.guard;
+            let tags = put(&mut store.tags, id.clone(), None);
+            let links = put(&mut store.links, id.clone(), None);
+            let search = put(&mut store.search, id.clone(), None);
+            let origin = put(&mut store.recycle_bin, id.clone(), None);
+            self.undo.extend([
+                Undo::Tags(id.clone(), tags),
+                Undo::Links(id.clone(), links),
+                Undo::Search(id.clone(), search),
+                Undo::RecycleOrigin(id.clone(), origin),
+            ]);
+            if self.put_node(id, None) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn query_contents(&mut self, ids: &[NodeId]) -> anyhow::Result<Vec<String>> {
+        let store = &self.guard;
+        Ok(ids
+            .iter()
+            .filter_map(|id| store.nodes.get(id))
+            .chain(store.nodes_history.iter().filter(|n| ids.contains(&n.id)))
+            .map(|n| n.content.clone())
+            .collect())
+    }
+
+    async fn is_asset_referenced(&mut self, asset_id: &str) -> anyhow::Result<bool> {
+        let url = format!("/api/download/{}", asset_id);
+        let store = &self.guard;
+        Ok(store
+            .nodes
+            .values()
+            .chain(store.nodes_history.iter())
+            .any(|n| n.content.contains(&url)))
+    }
+
+    async fn delete_asset_row(&mut self, asset_id: &str) -> anyhow::Result<u64> {
+        let old = put(&mut self.guard.assets, asset_id.to_owned(), None);
+        let count = old.as_ref().map_or(0, |_| 1);
+        self.undo.push(Undo::Asset(asset_id.to_owned(), old));
+        Ok(count)
+    }
+
+    async fn copy_asset_row(&mut self, asset_id: &str, new_id: &str) -> anyhow::Result<u64> {
+        let asset = match self.guard.assets.get(asset_id) {
+            Some(asset) => Asset {
+                id: new_id.to_owned(),
+                create_time: Utc::now(),
+                ..asset.clone()
+            },
+            None => return Ok(0),
+        };
+        let old = put(&mut self.guard.assets, new_id.to_owned(), Some(asset));
+        self.undo.push(Undo::Asset(new_id.to_owned(), old));
+        Ok(1)
+    }
+
+    async fn find_descendant_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        Ok(self.guard.descendant_ids(id))
+    }
+
+    async fn find_ancestor_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        Ok(self.guard.ancestor_ids(id))
+    }
+
+    async fn replace_tags(&mut self, id: &NodeId, tags: &[String]) -> anyhow::Result<()> {
+        let tags = (!tags.is_empty()).then(|| tags.to_vec());
+        let old = put(&mut self.guard.tags, id.clone(), tags);
+        self.undo.push(Undo::Tags(id.clone(), old));
+        Ok(())
+    }
+
+    async fn replace_links(&mut self, id: &NodeId, node_refs: &[NodeRef]) -> anyhow::Result<()> {
+        let node_refs = (!node_refs.is_empty()).then(|| node_refs.to_vec());
+        let old = put(&mut self.guard.links, id.clone(), node_refs);
+        self.undo.push(Undo::Links(id.clone(), old));
+        Ok(())
+    }
+
+    async fn replace_search_text(
+        &mut self,
+        id: &NodeId,
+        _name: &str,
+        text: &str,
+    ) -> anyhow::Result<()> {
+        let old = put(&mut self.guard.search, id.clone(), Some(text.to_owned()));
+        self.undo.push(Undo::Search(id.clone(), old));
+        Ok(())
+    }
+
+    async fn query_tagged_ids(&mut self, tags: &[String]) -> anyhow::Result<Vec<NodeId>> {
+        let mut ids: Vec<NodeId> = self
+            .guard
+            .tags
+            .iter()
+            .filter(|(_, node_tags)| node_tags.iter().any(|t| tags.contains(t)))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+        Ok(ids)
+    }
+
+    async fn commit(mut self: Box<Self>) -> anyhow::Result<()> {
+        // Nothing is left to put back once dropped.
+        self.undo.clear();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TagMapper for MemoryMapper {
+    async fn list_tags(&self) -> anyhow::Result<Vec<TagStat>> {
+        let store = self.store.lock().await;
+
+        let mut counts: HashMap<&str, i64> = HashMap::new();
+        for (id, tags) in store.tags.iter() {
+            if store.nodes.get(id).is_some_and(|n| n.delete_time.is_none()) {
+                for tag in tags {
+                    *counts.entry(tag.as_str()).or_default() += 1;
+                }
+            }
+        }
+
+        let mut tags: Vec<TagStat> = counts
+            .into_iter()
+            .map(|(name, count)| TagStat {
+                name: name.to_owned(),
+                count,
+            })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        Ok(tags)
+    }
+}
+
+#[async_trait]
+impl RecycleMapper for MemoryMapper {
+    async fn query_recycle_origins(&self) -> anyhow::Result<HashMap<NodeId, RecycleOrigin>> {
+        Ok(self.store.lock().await.recycle_bin.clone())
+    }
+}
+
+#[async_trait]
+impl SearchMapper for MemoryMapper {
+    async fn search_index(&self, req: &SearchReq) -> anyhow::Result<Vec<SearchRow>> {
+        let store = self.store.lock().await;
+
+        let mut hits: Vec<(f64, &Node)> = store
+            .nodes
+            .values()
+            .filter(|n| n.delete_time.is_none())
+            .filter_map(|n| store.search_rank(&req.query, n).map(|rank| (rank, n)))
+            .collect();
+        hits.sort_by(|a, b| {
+            b.0.total_cmp(&a.0)
+                .then_with(|| b.1.version_time.cmp(&a.1.version_time))
+        });
+
+        Ok(hits
+            .into_iter()
+            .skip(req.offset() as usize)
+            .take(req.limit() as usize)
+            .map(|(rank, n)| SearchRow {
+                id: n.id.clone(),
+                name: n.name.clone(),
+                body: store.search.get(&n.id).cloned().unwrap_or_default(),
+                rank,
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl TodoMapper for MemoryMapper {
+    async fn insert_todo_and_update(&self, req: &TodoCreateReq) -> anyhow::Result<()> {
+        let mut store = self.store.lock().await;
+
+        // Only the status is kept, nothing reads the log of todo events here.
+        match store.nodes.get_mut(&req.id) {
+            Some(node) => node.todo_status = req.todo_event.clone(),
+            None => return log_and_err!("there are no node with id: {:?}", req.id),
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BackupHandlerV1 for MemoryMapper {
+    async fn fetch_table(
+        &self,
+        backup_type: &BackupContent,
+        _time_field: &str,
+        start_time: &DateTime<Utc>,
+        end_time: &DateTime<Utc>,
+    ) -> anyhow::Result<Vec<TableRow>> {
+        let store = self.store.lock().await;
+        let in_range = |t: &DateTime<Utc>| t <= end_time && t >= start_time;
+
+        let rows = match backup_type {
+            BackupContent::Nodes => store
+                .nodes
+                .values()
+                .filter(|n| in_range(&n.version_time))
+                .map(|n| {
+                    TableRow::NodesRow(Nodes {
+                        id: n.id.clone(),
+                        delete_time: n.delete_time,
+                        name: n.name.clone(),
+                        content: n.content.clone(),
+                        node_type: n.node_type.clone(),
+                        domain: n.domain.clone(),
+                        parent_id: n.parent_id.clone(),
+                        prev_sliding_id: n.prev_sliding_id.clone(),
+                        version_time: n.version_time,
+                        initial_time: n.initial_time.with_timezone(&Utc),
+                    })
+                })
+                .collect(),
+            BackupContent::NodesHistory => store
+                .nodes_history
+                .iter()
+                .filter(|n| in_range(&n.version_time))
+                .map(|n| {
+                    TableRow::NodesHistoryRow(NodesHistory {
+                        id: n.id.clone(),
+                        delete_time: n.delete_time,
+                        name: n.name.clone(),
+                        content: n.content.clone(),
+                        node_type: n.node_type.clone(),
+                        domain: n.domain.clone(),
+                        version_time: n.version_time,
+                        initial_time: n.initial_time.with_timezone(&Utc),
+                    })
+                })
+                .collect(),
+            BackupContent::Assets => store
+                .assets
+                .values()
+                .filter(|a| in_range(&a.create_time))
+                .map(|a| {
+                    TableRow::AssetsRow(Assets {
+                        id: a.id.clone(),
+                        ori_file_name: a.ori_file_name.clone(),
+                        domain: a.domain.clone().unwrap_or_default(),
+                        create_time: a.create_time,
+                        content_type: a.content_type.clone(),
+                    })
+                })
+                .collect(),
+            _ => {
+                anyhow::bail!("unable map thie row to table")
+            }
+        };
+
+        Ok(rows)
+    }
+}
+
+#[async_trait]
+impl Mapper for MemoryMapper {
+    fn migrations(&self) -> &'static [Migration] {
+        &[]
+    }
+
+    async fn init(&mut self) -> anyhow::Result<()> {
+        self.migrate().await
+    }
+
+    async fn ensure_table_schema_migrations(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn query_schema_version(&self) -> anyhow::Result<Option<i64>> {
+        Ok(None)
+    }
+
+    async fn apply_migration(&self, _migration: &Migration) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn get_table_fields(&self, table_name: &str) -> anyhow::Result<Vec<String>> {
+        match table_name {
+            "nodes" => Ok(NODE_FIELDS.iter().map(|e| e.to_string()).collect()),
+            _ => log_and_err!("table {} has no fields.", table_name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use chrono::{DateTime, Duration, Utc};
+
+    use crate::{
+        mapper::{
+            asset::AssetMapper,
+            error::MapperError,
+            history::RetentionPolicy,
+            node::{
+                NodeCopyReq, NodeDeleteMode, NodeDeleteReq, NodeDiffReq, NodeMapper, NodeMoveReq,
+                NodeRenameReq, NodeUpdateContentReq, NodeUpdateReadonlyReq, NodeVersionReq,
+            },
+            nodefilter::{NodeFetchReq, NodeFilter, NodeSelection, TimeField, TimeRange},
+            page::{NodeSort, NodeSortKey},
+            path::NodePathReq,
+            recycle::{RecycleEmptyReq, RecycleMapper, RecyclePurgeReq, RecycleRestoreReq},
+            reorder::{NodeMoveBatchReq, NodeReorderReq, NodeSortChildrenReq},
+            search::{SearchMapper, SearchReq},
+            tag::{TagMapper, TagMergeReq, TagRenameReq},
+            testutils::{fetch, id, insert_tree, node},
+            todo::{TodoCreateReq, TodoMapper},
+        },
+        model::{
+            node::{MagicNodeId, Node, NodeId},
+            todo::TodoEvent,
+        },
+        parser::toent::todoevent::TodoCreateType,
+        utils::diffutils::DiffOp,
+    };
+
+    use super::MemoryMapper;
+
+    async fn tree() -> MemoryMapper {
+        let mapper = MemoryMapper::new();
+        insert_tree(&mapper).await;
+        mapper
+    }
+
+    /// Ids of the children of `parent_id` in their linked order.
+    async fn children(mapper: &MemoryMapper, parent_id: &str) -> Vec<String> {
+        let store = mapper.store.lock().await;
+        let mut ids = vec![];
+        let mut prev_id = MagicNodeId::Empty;
+        while let MagicNodeId::Id(child_id) = store.next_of(&id(parent_id), &prev_id) {
+            ids.push(child_id.as_str().to_owned());
+            prev_id = child_id.into();
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_move() {
+        let mapper = tree().await;
+
+        assert_eq!(fetch(&mapper, "b").await.prev_sliding_id.as_ref(), "c");
+        assert_eq!(
+            fetch(&mapper, "c").await.prev_sliding_id.as_ref(),
+            "##Empty##"
+        );
+
+        let rsp = mapper
+            .move_nodes(&NodeMoveReq {
+                id: "c".into(),
+                parent_id: id("a"),
+                prev_sliding_id: id("b"),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(rsp.old.next_id.as_ref(), "b");
+        assert_eq!(
+            fetch(&mapper, "b").await.prev_sliding_id.as_ref(),
+            "##Empty##"
+        );
+        assert_eq!(fetch(&mapper, "c").await.prev_sliding_id.as_ref(), "b");
+
+        let descendants = mapper.find_descendant_ids(&"a".into()).await.unwrap();
+        assert_eq!(descendants.len(), 3);
+        let ancestors = mapper.find_ancestor_ids(&"d".into()).await.unwrap();
+        assert_eq!(ancestors.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_history() {
+        let mapper = tree().await;
+
+        mapper
+            .update_node_content(&NodeUpdateContentReq {
+                id: "a".into(),
+                content: "something completely different".to_owned(),
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(mapper.store.lock().await.nodes_history.len(), 1);
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+
+        let c = fetch(&mapper, "c").await;
+        assert!(c.delete_time.is_some());
+        assert!(matches!(c.parent_id, MagicNodeId::RecycleBin));
+        assert!(fetch(&mapper, "d").await.delete_time.is_some());
+        assert_eq!(
+            fetch(&mapper, "b").await.prev_sliding_id.as_ref(),
+            "##Empty##"
+        );
+
+        let live = mapper
+            .query_nodes(&NodeFetchReq {
+                selection: None,
+                filter: Some(NodeFilter::All),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(live.len(), 2);
+        assert!(live.iter().all(|n| n.content.is_empty()));
+    }
+
+    #[tokio::test]
+    async fn test_delete_promote_children() {
+        let mapper = tree().await;
+        mapper
+            .insert_and_move(&node("e", id("c"), id("d")))
+            .await
+            .unwrap();
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::PromoteChildren,
+            })
+            .await
+            .unwrap();
+
+        // a
+        //   d
+        //   e
+        //   b
+        let c = fetch(&mapper, "c").await;
+        assert!(c.delete_time.is_some());
+        assert!(matches!(c.parent_id, MagicNodeId::RecycleBin));
+        for (child, prev) in [("d", "##Empty##"), ("e", "d"), ("b", "e")] {
+            let child = fetch(&mapper, child).await;
+            assert!(child.delete_time.is_none());
+            assert_eq!(child.parent_id.as_ref(), "a");
+            assert_eq!(child.prev_sliding_id.as_ref(), prev);
+        }
+
+        // Back as a leaf, at its old place.
+        mapper
+            .restore_node(&RecycleRestoreReq {
+                id: "c".into(),
+                parent_id: None,
+                prev_sliding_id: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            fetch(&mapper, "c").await.prev_sliding_id.as_ref(),
+            "##Empty##"
+        );
+        assert_eq!(fetch(&mapper, "d").await.prev_sliding_id.as_ref(), "c");
+        assert!(mapper
+            .find_descendant_ids(&"c".into())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_versions() {
+        let mapper = tree().await;
+        let doc = |lines: &[&str]| {
+            let paragraphs: Vec<String> = lines
+                .iter()
+                .map(|e| {
+                    format!(
+                        r#"{{"type":"paragraph","content":[{{"type":"text","text":"{}"}}]}}"#,
+                        e
+                    )
+                })
+                .collect();
+            format!(r#"{{"type":"doc","content":[{}]}}"#, paragraphs.join(","))
+        };
+
+        let first = fetch(&mapper, "a").await.version_time;
+        mapper
+            .update_node_content(&NodeUpdateContentReq {
+                id: "a".into(),
+                content: doc(&["title", "old line", "end"]),
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+        let second = fetch(&mapper, "a").await.version_time;
+        mapper
+            .update_node_content(&NodeUpdateContentReq {
+                id: "a".into(),
+                content: doc(&["title", "completely new line", "end"]),
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+        let third = fetch(&mapper, "a").await.version_time;
+
+        let versions = mapper.query_versions(&"a".into()).await.unwrap();
+        let times: Vec<_> = versions.iter().map(|e| e.version_time).collect();
+        assert_eq!(times, vec![third, second, first]);
+        assert!(versions[0].current && !versions[1].current);
+        assert_eq!(versions[2].size, "content of a".len() as i64);
+
+        let diff = mapper
+            .diff_versions(&NodeDiffReq {
+                id: "a".into(),
+                from: second,
+                to: third,
+            })
+            .await
+            .unwrap();
+        let ops: Vec<(DiffOp, &str)> = diff.lines.iter().map(|e| (e.op, e.text.as_str())).collect();
+        assert_eq!(
+            ops,
+            vec![
+                (DiffOp::Equal, "title"),
+                (DiffOp::Delete, "old line"),
+                (DiffOp::Insert, "completely new line"),
+                (DiffOp::Equal, "end"),
+            ]
+        );
+
+        // Restoring is an edit of its own, the replaced content is kept.
+        mapper
+            .restore_version(&NodeVersionReq {
+                id: "a".into(),
+                version_time: second,
+            })
+            .await
+            .unwrap();
+        let a = fetch(&mapper, "a").await;
+        assert_eq!(a.content, doc(&["title", "old line", "end"]));
+        let versions = mapper.query_versions(&"a".into()).await.unwrap();
+        assert_eq!(versions.len(), 4);
+        assert_eq!(versions[1].version_time, third);
+
+        assert!(mapper
+            .restore_version(&NodeVersionReq {
+                id: "a".into(),
+                version_time: a.version_time,
+            })
+            .await
+            .is_err());
+        assert!(mapper
+            .query_version(&NodeVersionReq {
+                id: "a".into(),
+                version_time: Utc::now(),
+            })
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rollback() {
+        let mapper = tree().await;
+
+        {
+            let mut tx = mapper.begin().await.unwrap();
+            tx.move_node(&NodeMoveReq {
+                id: "b".into(),
+                parent_id: id("c"),
+                prev_sliding_id: id("d"),
+            })
+            .await
+            .unwrap();
+            tx.update_node_content(&NodeUpdateContentReq {
+                id: "a".into(),
+                content: tagged(&["rolled back"]),
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+            tx.insert_and_move(&node("e", id("a"), MagicNodeId::Empty))
+                .await
+                .unwrap();
+            tx.delete_node(&NodeDeleteReq {
+                id: "x".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap_err();
+        }
+
+        let b = fetch(&mapper, "b").await;
+        assert_eq!(b.parent_id.as_ref(), "a");
+        assert_eq!(b.position, 1);
+        assert_eq!(fetch(&mapper, "a").await.content, "content of a");
+        let versions = mapper.query_versions(&"a".into()).await.unwrap();
+        assert!(versions.iter().all(|v| v.current), "{:?}", versions);
+        assert!(tagged_ids(&mapper, "rolled back").await.is_empty());
+        assert!(mapper
+            .query_nodes(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::WithHistory]),
+                filter: Some(NodeFilter::Id("e".into())),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    fn tagged(tags: &[&str]) -> String {
+        let content: Vec<String> = tags
+            .iter()
+            .map(|t| {
+                format!(
+                    r#"{{"type":"text","text":"{}","marks":[{{"type":"hashtag"}}]}}"#,
+                    t
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"type":"doc","content":[{{"type":"paragraph","content":[{}]}}]}}"#,
+            content.join(",")
+        )
+    }
+
+    async fn tagged_ids(mapper: &MemoryMapper, tag: &str) -> Vec<String> {
+        let mut ids: Vec<String> = mapper
+            .query_nodes(&NodeFetchReq {
+                selection: None,
+                filter: Some(NodeFilter::Tag(tag.to_owned())),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|n| n.id.as_str().to_owned())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    fn linked(refs: &[(&str, &str)]) -> String {
+        let content: Vec<String> = refs
+            .iter()
+            .map(|(id, text)| {
+                format!(
+                    r#"{{"type":"text","text":"&{}","marks":[{{"type":"backlink","attrs":{{"chnothref":"{}"}}}}]}}"#,
+                    text, id
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"type":"doc","content":[{{"type":"paragraph","content":[{{"type":"text","text":"see "}},{}]}}]}}"#,
+            content.join(",")
+        )
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let mapper = tree().await;
+        for (id, refs) in [
+            ("a", vec![("c", "C"), ("x", "X")]),
+            ("b", vec![("c", "C")]),
+            ("d", vec![("a", "A")]),
+        ] {
+            mapper
+                .update_node_content(&NodeUpdateContentReq {
+                    id: id.into(),
+                    content: linked(&refs),
+                    version_time: Utc::now(),
+                    base_version_time: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let backlinks = mapper.query_backlinks(&"c".into()).await.unwrap();
+        let sources: Vec<&str> = backlinks.iter().map(|l| l.source_id.as_str()).collect();
+        assert_eq!(sources, vec!["b", "a"]);
+        assert_eq!(backlinks[0].name.as_deref(), Some("b"));
+        assert_eq!(backlinks[0].snippet, "see &C");
+
+        let outgoing = mapper.query_outgoing_links(&"a".into()).await.unwrap();
+        let targets: Vec<(&str, Option<&str>)> = outgoing
+            .iter()
+            .map(|l| (l.target_id.as_str(), l.name.as_deref()))
+            .collect();
+        assert_eq!(targets, vec![("c", Some("c")), ("x", None)]);
+
+        // d is deleted with c.
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        assert!(mapper
+            .query_backlinks(&"a".into())
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            mapper.query_outgoing_links(&"a".into()).await.unwrap()[0].name,
+            None
+        );
+
+        mapper
+            .update_node_content(&NodeUpdateContentReq {
+                id: "b".into(),
+                content: linked(&[]),
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+        assert!(mapper
+            .query_outgoing_links(&"b".into())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    fn paragraph(text: &str) -> String {
+        format!(
+            r#"{{"type":"doc","content":[{{"type":"paragraph","content":[{{"type":"text","text":"{}"}}]}}]}}"#,
+            text
+        )
+    }
+
+    async fn pages(mapper: &MemoryMapper, sort: NodeSort, limit: i32) -> Vec<Vec<String>> {
+        let mut pages = vec![];
+        let mut req = NodeFetchReq {
+            selection: Some(vec![NodeSelection::Limit(limit)]),
+            sort: Some(sort),
+            ..Default::default()
+        };
+        loop {
+            let page = mapper.query_page(&req).await.unwrap();
+            pages.push(
+                page.nodes
+                    .iter()
+                    .map(|n| n.id.as_str().to_owned())
+                    .collect(),
+            );
+            match page.next_cursor {
+                Some(cursor) => req.cursor = Some(cursor),
+                None => return pages,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_page() {
+        let mapper = tree().await;
+        let sort = |key, desc| NodeSort { key, desc };
+
+        assert_eq!(
+            pages(&mapper, sort(NodeSortKey::TreeOrder, false), 2).await,
+            vec![vec!["a", "c"], vec!["d", "b"]]
+        );
+        assert_eq!(
+            pages(&mapper, sort(NodeSortKey::Name, true), 3).await,
+            vec![vec!["d", "c", "b"], vec!["a"]]
+        );
+        assert_eq!(
+            pages(&mapper, sort(NodeSortKey::VersionTime, false), 4).await,
+            vec![vec!["a", "b", "c", "d"]]
+        );
+
+        // The page goes on from the position of a deleted node.
+        let first = mapper
+            .query_page(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::Limit(2)]),
+                sort: Some(sort(NodeSortKey::TreeOrder, false)),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let second = mapper
+            .query_page(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::Limit(2)]),
+                cursor: first.next_cursor,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let ids: Vec<&str> = second.nodes.iter().map(|n| n.id.as_str()).collect();
+        assert_eq!(ids, vec!["b"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_filters() {
+        let mapper = tree().await;
+        mapper
+            .update_node_readonly(&NodeUpdateReadonlyReq {
+                id: "d".into(),
+                readonly: true,
+            })
+            .await
+            .unwrap();
+        let ids = |filter: NodeFilter| {
+            let mapper = &mapper;
+            async move {
+                let mut ids: Vec<String> = mapper
+                    .query_nodes(&NodeFetchReq {
+                        selection: None,
+                        filter: Some(filter),
+                        ..Default::default()
+                    })
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|n| n.id.as_str().to_owned())
+                    .collect();
+                ids.sort();
+                ids
+            }
+        };
+
+        assert_eq!(
+            ids(NodeFilter::Descendant("a".into())).await,
+            vec!["b", "c", "d"]
+        );
+        assert_eq!(
+            ids(NodeFilter::DescendantWithin("a".into(), 1)).await,
+            vec!["b", "c"]
+        );
+        assert_eq!(
+            ids(NodeFilter::DescendantWithin("a".into(), 2)).await,
+            vec!["b", "c", "d"]
+        );
+        assert!(ids(NodeFilter::DescendantWithin("a".into(), 0))
+            .await
+            .is_empty());
+        assert_eq!(ids(NodeFilter::Ancestor("d".into())).await, vec!["a", "c"]);
+        assert_eq!(ids(NodeFilter::Readonly(true)).await, vec!["d"]);
+        assert_eq!(
+            ids(NodeFilter::Not(Box::new(NodeFilter::Readonly(true)))).await,
+            vec!["a", "b", "c"]
+        );
+        assert!(ids(NodeFilter::Not(Box::new(NodeFilter::All)))
+            .await
+            .is_empty());
+        // Like the `like` of the SQL backends.
+        assert_eq!(
+            ids(NodeFilter::Contains("Content OF c".to_owned())).await,
+            vec!["c"]
+        );
+
+        let range = TimeRange {
+            since: None,
+            until: Some(Utc::now() + Duration::days(1)),
+        };
+        assert_eq!(
+            ids(NodeFilter::Time(TimeField::VersionTime, range.clone()))
+                .await
+                .len(),
+            4
+        );
+        assert!(ids(NodeFilter::Time(TimeField::DeleteTime, range))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_child_counts() {
+        let mapper = tree().await;
+        let ids: Vec<NodeId> = vec!["a".into(), "c".into(), "d".into()];
+        let counts = mapper.query_child_counts(&ids).await.unwrap();
+        assert_eq!(counts, HashMap::from([("a".into(), 2), ("c".into(), 1)]));
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "d".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let counts = mapper.query_child_counts(&ids).await.unwrap();
+        assert_eq!(counts, HashMap::from([("a".into(), 2)]));
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let mapper = tree().await;
+        for (id, text) in [("b", "nothing here"), ("d", "Learning <Rust>, more rust")] {
+            mapper
+                .update_node_content(&NodeUpdateContentReq {
+                    id: id.into(),
+                    content: paragraph(text),
+                    version_time: Utc::now(),
+                    base_version_time: None,
+                })
+                .await
+                .unwrap();
+        }
+        mapper
+            .update_node_name(&NodeRenameReq {
+                id: "b".into(),
+                name: "Rust book".to_owned(),
+            })
+            .await
+            .unwrap();
+
+        let req = |query: &str| SearchReq {
+            query: query.to_owned(),
+            limit: None,
+            offset: None,
+        };
+
+        let hits = mapper.search(&req("rust")).await.unwrap();
+        let ids: Vec<&str> = hits.iter().map(|h| h.id.as_str()).collect();
+        assert_eq!(ids, vec!["b", "d"]);
+        let path: Vec<&str> = hits[1].path.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(path, vec!["a", "c"]);
+        assert_eq!(
+            hits[1].snippet,
+            "Learning &lt;<mark>Rust</mark>&gt;, more <mark>rust</mark>"
+        );
+
+        assert!(mapper.search(&req("rust book")).await.unwrap().len() == 1);
+        assert!(mapper.search(&req(" * ")).await.is_err());
+
+        let filtered = mapper
+            .query_nodes(&NodeFetchReq {
+                selection: None,
+                filter: Some(NodeFilter::Search("LEARNING".to_owned())),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].id.as_str(), "d");
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let hits = mapper.search(&req("rust")).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id.as_str(), "b");
+    }
+
+    #[tokio::test]
+    async fn test_tags() {
+        let mapper = tree().await;
+        for (id, tags) in [
+            ("a", vec!["#rust", "#db"]),
+            ("b", vec!["#rust"]),
+            ("c", vec!["#go"]),
+        ] {
+            mapper
+                .update_node_content(&NodeUpdateContentReq {
+                    id: id.into(),
+                    content: tagged(&tags),
+                    version_time: Utc::now(),
+                    base_version_time: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(tagged_ids(&mapper, "rust").await, vec!["a", "b"]);
+        let tags = mapper.list_tags().await.unwrap();
+        assert_eq!((tags[0].name.as_str(), tags[0].count), ("rust", 2));
+        assert_eq!(tags.len(), 3);
+
+        mapper
+            .rename_tag(&TagRenameReq {
+                from: "rust".to_owned(),
+                to: "#db".to_owned(),
+            })
+            .await
+            .unwrap_err();
+        let rsp = mapper
+            .rename_tag(&TagRenameReq {
+                from: "rust".to_owned(),
+                to: "lang".to_owned(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(rsp.node_ids.len(), 2);
+        assert!(tagged_ids(&mapper, "rust").await.is_empty());
+        assert!(fetch(&mapper, "b").await.content.contains("#lang"));
+
+        mapper
+            .merge_tags(&TagMergeReq {
+                from: vec!["go".to_owned(), "db".to_owned()],
+                to: "lang".to_owned(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(tagged_ids(&mapper, "lang").await, vec!["a", "b", "c"]);
+        let tags = mapper.list_tags().await.unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].count, 3);
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        assert_eq!(mapper.list_tags().await.unwrap()[0].count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_compact_history() {
+        let mut mapper = tree().await;
+        mapper.snapshot_distance = 0;
+
+        // Three edits in the same week, long ago.
+        let now = Utc::now();
+        let week = 7 * 24 * 3600;
+        let base = (now - Duration::days(10)).timestamp().div_euclid(week) * week;
+        for i in 0..4 {
+            mapper
+                .update_node_content(&NodeUpdateContentReq {
+                    id: "a".into(),
+                    content: format!("edit {}", i),
+                    version_time: if i < 3 {
+                        DateTime::from_timestamp(base + i * 60, 0).unwrap()
+                    } else {
+                        now
+                    },
+                    base_version_time: None,
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(mapper.query_versions(&"a".into()).await.unwrap().len(), 5);
+
+        let policy = RetentionPolicy {
+            keep_all_days: 1,
+            hourly_days: 2,
+            daily_days: 7,
+            max_days: None,
+        };
+        assert_eq!(mapper.compact_history(&policy).await.unwrap(), 2);
+        let times: Vec<_> = mapper
+            .query_versions(&"a".into())
+            .await
+            .unwrap()
+            .iter()
+            .map(|e| e.version_time)
+            .collect();
+        assert_eq!(times.len(), 3);
+        assert_eq!(times[0], now);
+        assert_eq!(times[2], DateTime::from_timestamp(base + 120, 0).unwrap());
+        assert_eq!(mapper.compact_history(&policy).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_conflict() {
+        let mapper = tree().await;
+        let base = fetch(&mapper, "a").await.version_time;
+        let edit = |content: &str, base_version_time| NodeUpdateContentReq {
+            id: "a".into(),
+            content: content.to_owned(),
+            version_time: Utc::now(),
+            base_version_time,
+        };
+
+        mapper
+            .update_node_content(&edit("saved on the first device", Some(base)))
+            .await
+            .unwrap();
+        let err = mapper
+            .update_node_content(&edit("saved on the second device", Some(base)))
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<MapperError>() {
+            Some(MapperError::Conflict(conflict)) => {
+                assert_eq!(conflict.base.as_ref().unwrap().content, "content of a");
+                assert_eq!(conflict.current.content, "saved on the first device");
+            }
+            _ => panic!("not a conflict, {}", err),
+        }
+        assert_eq!(
+            fetch(&mapper, "a").await.content,
+            "saved on the first device"
+        );
+
+        // Merged by the client, or sent without a base at all.
+        let current = fetch(&mapper, "a").await.version_time;
+        mapper
+            .update_node_content(&edit("merged on the second device", Some(current)))
+            .await
+            .unwrap();
+        mapper
+            .update_node_content(&edit("saved by an old client", None))
+            .await
+            .unwrap();
+
+        // Far too small an edit for a snapshot, the conflict comes without
+        // a base.
+        let base = fetch(&mapper, "a").await.version_time;
+        mapper
+            .update_node_content(&edit("saved by an old client!", Some(base)))
+            .await
+            .unwrap();
+        let err = mapper
+            .update_node_content(&edit("saved by an old client?", Some(base)))
+            .await
+            .unwrap_err();
+        match err.downcast_ref::<MapperError>() {
+            Some(MapperError::Conflict(conflict)) => {
+                assert!(conflict.base.is_none());
+                assert_eq!(conflict.current.content, "saved by an old client!");
+            }
+            _ => panic!("not a conflict, {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recycle_bin() {
+        let mapper = tree().await;
+        let download = |ids: &[&str]| {
+            ids.iter()
+                .map(|e| format!("http://host/api/download/{}", e))
+                .collect::<Vec<String>>()
+                .join(" ")
+        };
+        for (id, assets) in [("d", vec!["asset-1", "asset-2"]), ("b", vec!["asset-2"])] {
+            mapper
+                .update_node_content(&NodeUpdateContentReq {
+                    id: id.into(),
+                    content: download(&assets),
+                    version_time: Utc::now(),
+                    base_version_time: None,
+                })
+                .await
+                .unwrap();
+        }
+        for asset_id in ["asset-1", "asset-2"] {
+            mapper
+                .insert_asset("file", asset_id.to_owned(), "text/plain".to_owned(), None)
+                .await
+                .unwrap();
+        }
+
+        // Back to where it was, the whole subtree.
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let entries = mapper.list_recycle_bin().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].node.id.as_str(), "c");
+        assert_eq!(entries[0].origin.as_ref().unwrap().parent_id.as_ref(), "a");
+        assert!(mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree
+            })
+            .await
+            .is_err());
+
+        mapper
+            .restore_node(&RecycleRestoreReq {
+                id: "c".into(),
+                parent_id: None,
+                prev_sliding_id: None,
+            })
+            .await
+            .unwrap();
+        let c = fetch(&mapper, "c").await;
+        assert_eq!(c.parent_id.as_ref(), "a");
+        assert!(c.delete_time.is_none());
+        assert!(fetch(&mapper, "d").await.delete_time.is_none());
+        assert_eq!(fetch(&mapper, "b").await.prev_sliding_id.as_ref(), "c");
+        assert!(mapper.list_recycle_bin().await.unwrap().is_empty());
+
+        // Gone for good, with the asset nobody else uses.
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let purged = mapper
+            .purge_node(&RecyclePurgeReq { id: "c".into() })
+            .await
+            .unwrap();
+        let mut node_ids: Vec<&str> = purged.node_ids.iter().map(|e| e.as_str()).collect();
+        node_ids.sort();
+        assert_eq!(node_ids, vec!["c", "d"]);
+        assert_eq!(purged.asset_ids, vec!["asset-1"]);
+        assert!(mapper.query_asset_by_id("asset-1").await.is_err());
+        assert!(mapper.query_asset_by_id("asset-2").await.is_ok());
+        let store = mapper.store.lock().await;
+        assert!(!store.nodes.contains_key(&"d".into()));
+        assert!(store.nodes_history.iter().all(|n| n.id.as_str() != "d"));
+        drop(store);
+
+        // The chosen parent must be alive.
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "b".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        assert!(mapper
+            .restore_node(&RecycleRestoreReq {
+                id: "b".into(),
+                parent_id: Some(id("c")),
+                prev_sliding_id: None,
+            })
+            .await
+            .is_err());
+
+        let req = RecycleEmptyReq {
+            older_than_days: Some(1),
+        };
+        assert!(mapper
+            .empty_recycle_bin(&req)
+            .await
+            .unwrap()
+            .node_ids
+            .is_empty());
+        let purged = mapper
+            .empty_recycle_bin(&RecycleEmptyReq::default())
+            .await
+            .unwrap();
+        assert_eq!(purged.node_ids.len(), 1);
+        assert_eq!(purged.asset_ids, vec!["asset-2"]);
+        assert!(mapper.list_recycle_bin().await.unwrap().is_empty());
+    }
+    #[tokio::test]
+    async fn test_copy_node() {
+        let mapper = tree().await;
+        let backlink = |id: &str| {
+            format!(
+                r#"{{"type":"text","text":"&{}","marks":[{{"type":"backlink","attrs":{{"chnothref":"{}"}}}}]}}"#,
+                id, id
+            )
+        };
+        let content = format!(
+            r#"{{"type":"doc","content":[{{"type":"paragraph","content":[{},{}]}},{{"type":"image","attrs":{{"src":"/api/download/asset-1"}}}}]}}"#,
+            backlink("c"),
+            backlink("b")
+        );
+        mapper
+            .update_node_content(&NodeUpdateContentReq {
+                id: "d".into(),
+                content,
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+        mapper
+            .insert_and_move(&node("e", id("c"), id("d")))
+            .await
+            .unwrap();
+        mapper
+            .insert_asset("file", "asset-1".to_owned(), "text/plain".to_owned(), None)
+            .await
+            .unwrap();
+        mapper
+            .insert_todo_and_update(&TodoCreateReq {
+                id: "e".into(),
+                todo_event: Some(TodoEvent::Todo),
+                create_type: TodoCreateType::Manual,
+            })
+            .await
+            .unwrap();
+
+        let rsp = mapper
+            .copy_node(&NodeCopyReq {
+                id: "c".into(),
+                parent_id: id("a"),
+                prev_sliding_id: id("b"),
+                copy_assets: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(rsp.node_ids.len(), 3);
+        let copied = |id: &str| rsp.node_ids[&id.into()].as_str().to_owned();
+
+        // a
+        //   c
+        //     d
+        //     e
+        //   b
+        //   c'
+        //     d'
+        //     e'
+        assert_eq!(rsp.id.as_str(), copied("c"));
+        let c = fetch(&mapper, &copied("c")).await;
+        assert_eq!(
+            (c.parent_id.as_ref(), c.prev_sliding_id.as_ref()),
+            ("a", "b")
+        );
+        let d = fetch(&mapper, &copied("d")).await;
+        assert_eq!(d.parent_id.as_ref(), copied("c"));
+        assert_eq!(d.prev_sliding_id.as_ref(), "##Empty##");
+        let e = fetch(&mapper, &copied("e")).await;
+        assert_eq!(e.prev_sliding_id.as_ref(), copied("d"));
+        assert_eq!(e.todo_status, Some(TodoEvent::Todo));
+        assert_eq!(d.todo_status, None);
+        assert_eq!(
+            mapper.find_descendant_ids(&"c".into()).await.unwrap().len(),
+            2
+        );
+
+        // Inside the copy the backlink follows, outside it stays.
+        let targets: Vec<String> = mapper
+            .query_outgoing_links(&d.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.target_id.as_str().to_owned())
+            .collect();
+        assert_eq!(targets, vec![copied("c"), "b".to_owned()]);
+        let asset_id = &rsp.asset_ids["asset-1"];
+        assert!(d.content.contains(&format!("/api/download/{}", asset_id)));
+        assert!(mapper.query_asset_by_id(asset_id).await.is_ok());
+        assert!(fetch(&mapper, "d").await.content.contains("asset-1"));
+
+        assert!(mapper
+            .copy_node(&NodeCopyReq {
+                id: "c".into(),
+                parent_id: id("a"),
+                prev_sliding_id: id("d"),
+                copy_assets: false,
+            })
+            .await
+            .is_err());
+    }
+    #[tokio::test]
+    async fn test_move_validation() {
+        let mapper = tree().await;
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "b".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+
+        let move_to = |id: &str, parent_id: MagicNodeId, prev_sliding_id: MagicNodeId| {
+            let req = NodeMoveReq {
+                id: id.into(),
+                parent_id,
+                prev_sliding_id,
+            };
+            let mapper = &mapper;
+            async move { mapper.move_nodes(&req).await.unwrap_err() }
+        };
+        let cases = [
+            (move_to("a", id("d"), MagicNodeId::Empty).await, "into"),
+            (move_to("c", id("c"), MagicNodeId::Empty).await, "into"),
+            (move_to("c", id("a"), id("d")).await, "child"),
+            (move_to("c", id("a"), id("c")).await, "child"),
+            (move_to("c", id("b"), MagicNodeId::Empty).await, "parent"),
+            (
+                move_to("c", MagicNodeId::RecycleBin, MagicNodeId::Empty).await,
+                "parent",
+            ),
+            (move_to("b", id("a"), MagicNodeId::Empty).await, "deleted"),
+        ];
+        for (err, expected) in cases {
+            let kind = match err.downcast_ref::<MapperError>() {
+                Some(MapperError::IntoDescendant { .. }) => "into",
+                Some(MapperError::NotAChild { .. }) => "child",
+                Some(MapperError::InvalidParent(_)) => "parent",
+                Some(MapperError::Deleted(_)) => "deleted",
+                _ => panic!("unexpected error: {}", err),
+            };
+            assert_eq!(kind, expected, "{}", err);
+        }
+
+        // Nothing is touched by the rejected moves.
+        let c = fetch(&mapper, "c").await;
+        assert_eq!(
+            (c.parent_id.as_ref(), c.prev_sliding_id.as_ref()),
+            ("a", "##Empty##")
+        );
+        assert_eq!(fetch(&mapper, "d").await.parent_id.as_ref(), "c");
+
+        mapper
+            .move_nodes(&NodeMoveReq {
+                id: "d".into(),
+                parent_id: MagicNodeId::Empty,
+                prev_sliding_id: id("a"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(fetch(&mapper, "d").await.prev_sliding_id.as_ref(), "a");
+    }
+    #[tokio::test]
+    async fn test_reorder() {
+        let mapper = tree().await;
+        for (child, prev) in [("f", "b"), ("e", "b")] {
+            mapper
+                .insert_and_move(&node(child, id("a"), id(prev)))
+                .await
+                .unwrap();
+        }
+        assert_eq!(children(&mapper, "a").await, vec!["c", "b", "e", "f"]);
+
+        let relations = mapper
+            .reorder_children(&NodeReorderReq {
+                parent_id: id("a"),
+                child_ids: vec!["f".into(), "e".into(), "b".into(), "c".into()],
+            })
+            .await
+            .unwrap();
+        assert_eq!(children(&mapper, "a").await, vec!["f", "e", "b", "c"]);
+        assert_eq!(relations[1].prev_id.as_ref(), "f");
+        assert_eq!(relations[1].next_id.as_ref(), "b");
+
+        let err = mapper
+            .reorder_children(&NodeReorderReq {
+                parent_id: id("a"),
+                child_ids: vec!["f".into(), "e".into(), "b".into(), "d".into()],
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MapperError>(),
+            Some(MapperError::InvalidRequest(_))
+        ));
+
+        mapper
+            .sort_children(&NodeSortChildrenReq {
+                parent_id: id("a"),
+                sort: NodeSort {
+                    key: NodeSortKey::Name,
+                    desc: false,
+                },
+            })
+            .await
+            .unwrap();
+        assert_eq!(children(&mapper, "a").await, vec!["b", "c", "e", "f"]);
+
+        // Each move sees the previous ones.
+        let relations = mapper
+            .move_nodes_batch(&NodeMoveBatchReq {
+                moves: vec![
+                    NodeMoveReq {
+                        id: "d".into(),
+                        parent_id: id("a"),
+                        prev_sliding_id: MagicNodeId::Empty,
+                    },
+                    NodeMoveReq {
+                        id: "b".into(),
+                        parent_id: id("a"),
+                        prev_sliding_id: id("f"),
+                    },
+                ],
+            })
+            .await
+            .unwrap();
+        assert_eq!(children(&mapper, "a").await, vec!["d", "c", "e", "f", "b"]);
+        assert_eq!(relations[0].next_id.as_ref(), "c");
+        assert_eq!(relations[1].prev_id.as_ref(), "f");
+
+        // All or nothing.
+        assert!(mapper
+            .move_nodes_batch(&NodeMoveBatchReq {
+                moves: vec![
+                    NodeMoveReq {
+                        id: "b".into(),
+                        parent_id: id("a"),
+                        prev_sliding_id: MagicNodeId::Empty,
+                    },
+                    NodeMoveReq {
+                        id: "a".into(),
+                        parent_id: id("c"),
+                        prev_sliding_id: MagicNodeId::Empty,
+                    },
+                ],
+            })
+            .await
+            .is_err());
+        assert_eq!(children(&mapper, "a").await, vec!["d", "c", "e", "f", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_positions() {
+        async fn by_position(mapper: &MemoryMapper, parent_id: &str) -> Vec<String> {
+            mapper
+                .query_nodes(&NodeFetchReq {
+                    selection: None,
+                    filter: Some(NodeFilter::Children(parent_id.into())),
+                    sort: Some(NodeSort {
+                        key: NodeSortKey::Position,
+                        desc: false,
+                    }),
+                    ..Default::default()
+                })
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|e| e.id.as_str().to_owned())
+                .collect()
+        }
+
+        let mapper = tree().await;
+        for (child, prev) in [("e", "b"), ("f", "c")] {
+            mapper
+                .insert_and_move(&node(child, id("a"), id(prev)))
+                .await
+                .unwrap();
+        }
+        assert_eq!(by_position(&mapper, "a").await, vec!["c", "f", "b", "e"]);
+
+        mapper
+            .move_nodes(&NodeMoveReq {
+                id: "d".into(),
+                parent_id: id("a"),
+                prev_sliding_id: id("f"),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            by_position(&mapper, "a").await,
+            children(&mapper, "a").await
+        );
+
+        // Only some of the links change, the keys still follow all of them.
+        mapper
+            .reorder_children(&NodeReorderReq {
+                parent_id: id("a"),
+                child_ids: vec!["b".into(), "e".into(), "c".into(), "f".into(), "d".into()],
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            by_position(&mapper, "a").await,
+            vec!["b", "e", "c", "f", "d"]
+        );
+
+        mapper
+            .store
+            .lock()
+            .await
+            .nodes
+            .values_mut()
+            .for_each(|e| e.position = 9);
+        assert_eq!(mapper.rebuild_tree().await.unwrap(), 6);
+        assert_eq!(
+            by_position(&mapper, "a").await,
+            vec!["b", "e", "c", "f", "d"]
+        );
+        assert_eq!(fetch(&mapper, "d").await.position, 4);
+    }
+
+    #[tokio::test]
+    async fn test_paths() {
+        async fn resolve(mapper: &MemoryMapper, path: &str) -> anyhow::Result<NodeId> {
+            mapper
+                .resolve_path(&NodePathReq {
+                    path: path.to_owned(),
+                })
+                .await
+        }
+        fn no_such_path(err: anyhow::Error) -> String {
+            match err.downcast_ref::<MapperError>() {
+                Some(MapperError::NoSuchPath(path)) => path.clone(),
+                _ => panic!("unexpected error: {}", err),
+            }
+        }
+
+        let mapper = tree().await;
+        for (child, parent_id, prev_id, name) in [
+            ("x1", id("a"), MagicNodeId::Empty, "x"),
+            ("x2", id("a"), id("c"), "x"),
+            ("s", id("c"), id("d"), "p/q"),
+        ] {
+            mapper
+                .insert_and_move(&Node {
+                    name: name.to_owned(),
+                    ..node(child, parent_id, prev_id)
+                })
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(resolve(&mapper, "/a/c/d").await.unwrap().as_str(), "d");
+        assert_eq!(resolve(&mapper, "a/x/").await.unwrap().as_str(), "x1");
+        assert_eq!(resolve(&mapper, "/a/x~2").await.unwrap().as_str(), "x2");
+        let err = resolve(&mapper, "/a/x~3").await.unwrap_err();
+        assert_eq!(no_such_path(err), "/a/x~3");
+        let err = resolve(&mapper, "/a/zz/d").await.unwrap_err();
+        assert_eq!(no_such_path(err), "/a/zz");
+        assert!(resolve(&mapper, "/").await.is_err());
+
+        let breadcrumb = mapper.query_breadcrumb(&"x2".into()).await.unwrap();
+        assert_eq!(breadcrumb.path, "/a/x~2");
+        let ids: Vec<&str> = breadcrumb.crumbs.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "x2"]);
+        let breadcrumb = mapper.query_breadcrumb(&"s".into()).await.unwrap();
+        assert_eq!(breadcrumb.path, "/a/c/p%2Fq");
+        assert_eq!(breadcrumb.crumbs[2].name, "p/q");
+        assert_eq!(
+            resolve(&mapper, &breadcrumb.path).await.unwrap().as_str(),
+            "s"
+        );
+
+        // The sibling order decides which one is first.
+        mapper
+            .move_nodes(&NodeMoveReq {
+                id: "x2".into(),
+                parent_id: id("a"),
+                prev_sliding_id: MagicNodeId::Empty,
+            })
+            .await
+            .unwrap();
+        assert_eq!(resolve(&mapper, "/a/x").await.unwrap().as_str(), "x2");
+        let breadcrumb = mapper.query_breadcrumb(&"x1".into()).await.unwrap();
+        assert_eq!(breadcrumb.path, "/a/x~2");
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let err = resolve(&mapper, "/a/c/d").await.unwrap_err();
+        assert_eq!(no_such_path(err), "/a/c");
+        let err = mapper.query_breadcrumb(&"d".into()).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<MapperError>(),
+            Some(MapperError::Deleted(_))
+        ));
+    }
+}
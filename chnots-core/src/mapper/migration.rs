@@ -0,0 +1,92 @@
+use chin_tools::log_and_err;
+
+/// One numbered step of a backend's schema.
+///
+/// Versions start from 1 and must be strictly increasing inside a backend's
+/// list. A migration is never edited once released, add a new one instead.
+#[derive(Debug, Clone)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// The migration adds tables derived from node content, fill them by
+    /// parsing every node once it is applied.
+    pub reindex: bool,
+    /// The migration adds tables derived from the relations, fill them from
+    /// the whole tree once it is applied.
+    pub rebuild_tree: bool,
+}
+
+pub fn latest_version(migrations: &[Migration]) -> i64 {
+    migrations.last().map_or(0, |m| m.version)
+}
+
+/// Return the migrations which are newer than `current`.
+///
+/// Refuses to continue when the database was migrated by a newer release than
+/// this one, running on an unknown schema may corrupt data silently.
+pub fn pending_migrations(
+    migrations: &[Migration],
+    current: Option<i64>,
+) -> anyhow::Result<&[Migration]> {
+    if let Some(w) = migrations.windows(2).find(|w| w[0].version >= w[1].version) {
+        return log_and_err!(
+            "migrations are not ordered: {} is followed by {}",
+            w[0].version,
+            w[1].version
+        );
+    }
+
+    let current = current.unwrap_or(0);
+    let latest = latest_version(migrations);
+    if current > latest {
+        return log_and_err!(
+            "database schema version {} is newer than the latest known version {}, refuse to start",
+            current,
+            latest
+        );
+    }
+
+    let start = migrations
+        .iter()
+        .position(|m| m.version > current)
+        .unwrap_or(migrations.len());
+
+    Ok(&migrations[start..])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{pending_migrations, Migration};
+
+    const MIGRATIONS: &[Migration] = &[
+        Migration {
+            version: 1,
+            name: "init",
+            sql: "",
+            reindex: false,
+            rebuild_tree: false,
+        },
+        Migration {
+            version: 2,
+            name: "second",
+            sql: "",
+            reindex: false,
+            rebuild_tree: false,
+        },
+    ];
+
+    #[test]
+    fn test_pending() {
+        assert_eq!(pending_migrations(MIGRATIONS, None).unwrap().len(), 2);
+        assert_eq!(pending_migrations(MIGRATIONS, Some(1)).unwrap()[0].version, 2);
+        assert!(pending_migrations(MIGRATIONS, Some(2)).unwrap().is_empty());
+        assert!(pending_migrations(MIGRATIONS, Some(3)).is_err());
+    }
+
+    #[test]
+    fn test_unordered() {
+        let migrations = [MIGRATIONS[1].clone(), MIGRATIONS[0].clone()];
+        assert!(pending_migrations(&migrations, None).is_err());
+    }
+}
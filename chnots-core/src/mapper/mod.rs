@@ -1,39 +1,86 @@
 use async_trait::async_trait;
+use tracing::info;
 
 use crate::backup::v1::BackupHandlerV1;
 
-use self::{asset::AssetMapper, node::NodeMapper, todo::TodoMapper};
+use self::{
+    asset::AssetMapper, migration::Migration, node::NodeMapper, recycle::RecycleMapper,
+    search::SearchMapper, tag::TagMapper, todo::TodoMapper,
+};
 
 #[cfg(feature = "postgres")]
 pub mod postgres_mapper;
 
 pub mod asset;
+pub mod error;
+pub mod fsck;
+pub mod history;
+pub mod memory_mapper;
+pub mod migration;
 pub mod node;
 pub mod nodefilter;
+pub mod page;
+pub mod path;
+pub mod recycle;
+pub mod reorder;
+pub mod search;
 #[cfg(feature = "sqlite")]
 pub mod sqlite_mapper;
+pub mod tag;
+#[cfg(test)]
+mod testutils;
 pub mod todo;
+pub mod tx;
 
 #[async_trait]
-pub trait Mapper: Sync + Send + NodeMapper + AssetMapper + BackupHandlerV1 + TodoMapper {
-    async fn ensure_table_nodes(&self) -> anyhow::Result<()>;
-    async fn ensure_table_tags(&self) -> anyhow::Result<()>;
-    async fn ensure_table_todos(&self) -> anyhow::Result<()>;
-    async fn ensure_table_alarm_instances(&self) -> anyhow::Result<()>;
-    async fn ensure_table_alarm_definations(&self) -> anyhow::Result<()>;
+pub trait Mapper:
+    Sync
+    + Send
+    + NodeMapper
+    + AssetMapper
+    + BackupHandlerV1
+    + TodoMapper
+    + TagMapper
+    + SearchMapper
+    + RecycleMapper
+{
+    /// All migrations of this backend, ordered by version.
+    fn migrations(&self) -> &'static [Migration];
 
-    async fn ensure_table_assets(&self) -> anyhow::Result<()>;
+    async fn ensure_table_schema_migrations(&self) -> anyhow::Result<()>;
+
+    /// The newest applied migration version, `None` for a fresh database.
+    async fn query_schema_version(&self) -> anyhow::Result<Option<i64>>;
+
+    /// Run the migration and record it in `schema_migrations` atomically.
+    async fn apply_migration(&self, migration: &Migration) -> anyhow::Result<()>;
 
     async fn get_table_fields(&self, table_name: &str) -> anyhow::Result<Vec<String>>;
 
     async fn init(&mut self) -> anyhow::Result<()>;
 
-    async fn ensure_tables(&self) -> anyhow::Result<()> {
-        self.ensure_table_nodes().await?;
-        self.ensure_table_tags().await?;
-        self.ensure_table_alarm_definations().await?;
-        self.ensure_table_alarm_instances().await?;
-        self.ensure_table_assets().await?;
+    async fn migrate(&self) -> anyhow::Result<()> {
+        self.ensure_table_schema_migrations().await?;
+
+        let current = self.query_schema_version().await?;
+        let pending = migration::pending_migrations(self.migrations(), current)?;
+        for migration in pending {
+            info!(
+                "applying migration {:04}_{}",
+                migration.version, migration.name
+            );
+            self.apply_migration(migration).await?;
+        }
+
+        if pending.iter().any(|m| m.reindex) {
+            let count = self.reindex().await?;
+            info!("reindexed content of {} nodes", count);
+        }
+
+        if pending.iter().any(|m| m.rebuild_tree) {
+            let count = self.rebuild_tree().await?;
+            info!("rebuilt the tree of {} nodes", count);
+        }
 
         Ok(())
     }
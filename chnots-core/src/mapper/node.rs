@@ -4,11 +4,22 @@ use async_trait::async_trait;
 use chin_tools::log_and_err;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use tracing::error;
 
-use crate::model::node::{ContentParsedInfo, MagicNodeId, Node, NodeId};
+use crate::{
+    model::node::{ContentParsedInfo, MagicNodeId, Node, NodeId},
+    parser,
+    utils::diffutils::{self, DiffLine},
+};
 
-use super::nodefilter::{NodeFetchReq, NodeFilter};
+use super::{
+    fsck::{self, FsckReport, FsckReq},
+    history::RetentionPolicy,
+    nodefilter::NodeFetchReq,
+    page::{self, NodePage},
+    path::{self, NodeBreadcrumb, NodePathReq},
+    reorder::{self, NodeMoveBatchReq, NodeReorderReq, NodeSortChildrenReq},
+    tx::NodeTx,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NodeMoveRsp {
@@ -30,6 +41,26 @@ pub struct NodeMoveReq {
     pub prev_sliding_id: MagicNodeId,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeCopyReq {
+    pub id: NodeId,
+    pub parent_id: MagicNodeId,
+    pub prev_sliding_id: MagicNodeId,
+    /// Give the copies their own assets instead of sharing the originals.
+    #[serde(default)]
+    pub copy_assets: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeCopyRsp {
+    /// The copy of the requested node.
+    pub id: NodeId,
+    /// Copied node id to the id of its copy.
+    pub node_ids: HashMap<NodeId, NodeId>,
+    /// Copied asset id to the id of its copy, their files are left to the caller.
+    pub asset_ids: HashMap<String, String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum NodeInsertResult {
     ParsedInfo(ContentParsedInfo),
@@ -38,6 +69,19 @@ pub enum NodeInsertResult {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NodeDeleteReq {
     pub id: NodeId,
+    #[serde(default)]
+    pub mode: NodeDeleteMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeDeleteMode {
+    /// The node goes to the recycle bin with all its descendants.
+    #[default]
+    Subtree,
+    /// Only the node goes to the recycle bin, its children take its place
+    /// among its siblings, in their order.
+    PromoteChildren,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -51,6 +95,10 @@ pub struct NodeUpdateContentReq {
     pub id: NodeId,
     pub content: String,
     pub version_time: DateTime<Utc>,
+    /// `version_time` of the node the edit started from. The update fails
+    /// with `MapperError::Conflict` when the node is saved after it.
+    #[serde(default)]
+    pub base_version_time: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -59,73 +107,116 @@ pub struct NodeUpdateReadonlyReq {
     pub readonly: bool,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeLinksReq {
+    pub id: NodeId,
+}
+
+/// A reference from the content of `source_id` to `target_id`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeLink {
+    pub source_id: NodeId,
+    pub target_id: NodeId,
+    /// Name of the node on the other end, `None` when it is deleted or missing.
+    pub name: Option<String>,
+    pub text: String,
+    pub snippet: String,
+}
+
+/// A version of a node, the current row or a snapshot in `nodes_history`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeVersion {
+    pub version_time: DateTime<Utc>,
+    pub name: String,
+    /// Chars of the content.
+    pub size: i64,
+    pub current: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeVersionsReq {
+    pub id: NodeId,
+}
+
+/// Versions are told apart by their `version_time`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeVersionReq {
+    pub id: NodeId,
+    pub version_time: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeDiffReq {
+    pub id: NodeId,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+/// Line diff of the plain text of two versions.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeDiff {
+    pub id: NodeId,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub lines: Vec<DiffLine>,
+}
+
 #[async_trait]
 pub trait NodeMapper {
-    async fn insert_and_move(&self, node: &Node) -> anyhow::Result<NodeInsertResult> {
-        let insert_result = self
-            .insert_node_only(&Node {
-                parent_id: MagicNodeId::Never,
-                prev_sliding_id: MagicNodeId::Never,
-                ..node.clone()
-            })
-            .await?;
-
-        self._insert_relation(&node.id, &node.parent_id, &node.prev_sliding_id)
-            .await?;
+    /// Start a transaction, every tree mutation below is composed on it.
+    async fn begin(&self) -> anyhow::Result<Box<dyn NodeTx>>;
 
-        Ok(insert_result)
+    async fn insert_and_move(&self, node: &Node) -> anyhow::Result<NodeInsertResult> {
+        let mut tx = self.begin().await?;
+        let result = tx.insert_and_move(node).await?;
+        tx.commit().await?;
+        Ok(result)
     }
 
     /// Just insert a node into nodes table, do not care about nodes relations.  
     /// So do not use this method directly.
-    async fn insert_node_only(&self, node: &Node) -> anyhow::Result<NodeInsertResult>;
+    async fn insert_node_only(&self, node: &Node) -> anyhow::Result<NodeInsertResult> {
+        let mut tx = self.begin().await?;
+        let result = tx.insert_node_only(node).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
 
     /// Delete node (logical or physical).
     /// 1. Delete node and its descentants.  
     ///    a. find all its descentants and mark them(both in nodes and node_history table)  
     ///    b. make its next slibing connect to its prev slibing.
-    /// 2. Delete node but level its descentants(TODO).
-    async fn delete_node(&self, req: &NodeDeleteReq) -> anyhow::Result<()>;
+    /// 2. Delete node but level its descentants, see `NodeDeleteMode::PromoteChildren`.
+    async fn delete_node(&self, req: &NodeDeleteReq) -> anyhow::Result<()> {
+        let mut tx = self.begin().await?;
+        tx.delete_node(req).await?;
+        tx.commit().await
+    }
+
     async fn update_node_name(&self, req: &NodeRenameReq) -> anyhow::Result<u64>;
     async fn update_node_content(
         &self,
         req: &NodeUpdateContentReq,
     ) -> anyhow::Result<NodeInsertResult> {
-        let node = self
-            .query_nodes(&NodeFetchReq {
-                selection: None,
-                filter: Some(NodeFilter::Id(req.id.clone())),
-            })
-            .await;
-
-        match node.as_ref().map(|e| e.get(0)) {
-            Ok(Some(node)) => {
-                if !node.readonly {
-                    self.insert_node_only(&Node {
-                        content: req.content.clone(),
-                        version_time: req.version_time,
-                        ..node.clone()
-                    })
-                    .await
-                } else {
-                    return log_and_err!("node is readonly, {:?}", node);
-                }
-            }
-            Ok(None) => {
-                error!("Unable to fetch node, {:?}", req);
-                anyhow::bail!("Unable to fetch node, {:?}", req)
-            }
-            Err(err) => {
-                error!("Unable to fetch node, err {:?}", req);
-                anyhow::bail!("Unable to fetch node, {:?}, err {:?}", req, err)
-            }
-        }
+        let mut tx = self.begin().await?;
+        let result = tx.update_node_content(req).await?;
+        tx.commit().await?;
+        Ok(result)
     }
 
     async fn update_node_readonly(&self, req: &NodeUpdateReadonlyReq) -> anyhow::Result<u64>;
 
     async fn query_nodes(&self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>>;
 
+    /// The number of live children of each node, nodes without any are left out.
+    async fn query_child_counts(&self, ids: &[NodeId]) -> anyhow::Result<HashMap<NodeId, u64>>;
+
+    /// One page of `query_nodes` in a stable order, at most `Limit` nodes,
+    /// the next page starts at `NodePage::next_cursor`.
+    async fn query_page(&self, node_filter: &NodeFetchReq) -> anyhow::Result<NodePage> {
+        page::query_page(self, node_filter).await
+    }
+
     /// Move Node.
     ///   
     /// ```
@@ -145,21 +236,128 @@ pub trait NodeMapper {
     /// 2. find the `prev_slibing` D's next `new_next` E(record)  
     /// 3. set F's prev as P  
     /// 4. set X's parent as A and X' prev as D and E's prev as X  
-    async fn move_nodes(&self, node_move_req: &NodeMoveReq) -> anyhow::Result<NodeMoveRsp>;
+    ///
+    /// A `MapperError` is returned when X would end up under itself, or D is
+    /// no child of A.
+    async fn move_nodes(&self, node_move_req: &NodeMoveReq) -> anyhow::Result<NodeMoveRsp> {
+        let mut tx = self.begin().await?;
+        tx.check_move(node_move_req).await?;
+        let rsp = tx.move_node(node_move_req).await?;
+        tx.commit().await?;
+        Ok(rsp)
+    }
 
-    async fn _delete_relation(
-        &self,
-        node_id: &NodeId,
-        to_history: bool,
-    ) -> anyhow::Result<NodeRelation>;
-    async fn _insert_relation(
-        &self,
-        node_id: &NodeId,
-        parent_id: &MagicNodeId,
-        prev_id: &MagicNodeId,
-    ) -> anyhow::Result<NodeRelation>;
+    /// Several `move_nodes` in one transaction, see `reorder::move_batch`.
+    async fn move_nodes_batch(&self, req: &NodeMoveBatchReq) -> anyhow::Result<Vec<NodeRelation>> {
+        let mut tx = self.begin().await?;
+        let relations = reorder::move_batch(tx.as_mut(), req).await?;
+        tx.commit().await?;
+        Ok(relations)
+    }
+
+    async fn reorder_children(&self, req: &NodeReorderReq) -> anyhow::Result<Vec<NodeRelation>> {
+        let mut tx = self.begin().await?;
+        let relations = reorder::reorder_children(tx.as_mut(), req).await?;
+        tx.commit().await?;
+        Ok(relations)
+    }
+
+    async fn sort_children(&self, req: &NodeSortChildrenReq) -> anyhow::Result<Vec<NodeRelation>> {
+        let mut tx = self.begin().await?;
+        let relations = reorder::sort_children(tx.as_mut(), req).await?;
+        tx.commit().await?;
+        Ok(relations)
+    }
+
+    /// Copy a node with its descendants, see `NodeTx::copy_node`.
+    async fn copy_node(&self, req: &NodeCopyReq) -> anyhow::Result<NodeCopyRsp> {
+        let mut tx = self.begin().await?;
+        let rsp = tx.copy_node(req).await?;
+        tx.commit().await?;
+        Ok(rsp)
+    }
+
+    /// Check the sibling lists and parents of the whole tree, and repair them
+    /// when asked to.
+    async fn fsck(&self, req: &FsckReq) -> anyhow::Result<FsckReport> {
+        let mut tx = self.begin().await?;
+        let report = fsck::fsck(tx.as_mut(), req).await?;
+        tx.commit().await?;
+        Ok(report)
+    }
+
+    /// Parse the content of every node again, return the number of nodes.
+    async fn reindex(&self) -> anyhow::Result<u64> {
+        let mut tx = self.begin().await?;
+        let count = tx.reindex().await?;
+        tx.commit().await?;
+        Ok(count)
+    }
+
+    /// Derive the ancestry and the order keys from the relations again,
+    /// return the number of nodes.
+    async fn rebuild_tree(&self) -> anyhow::Result<u64> {
+        let mut tx = self.begin().await?;
+        tx.lock_tree().await?;
+        let count = tx.rebuild_tree().await?;
+        tx.commit().await?;
+        Ok(count)
+    }
+
+    /// All versions of a node, the current one first, then the older ones
+    /// from the newest.
+    async fn query_versions(&self, id: &NodeId) -> anyhow::Result<Vec<NodeVersion>>;
+
+    /// A version with its content, see `NodeTx::query_version`.
+    async fn query_version(&self, req: &NodeVersionReq) -> anyhow::Result<Option<Node>>;
+
+    async fn diff_versions(&self, req: &NodeDiffReq) -> anyhow::Result<NodeDiff> {
+        let mut texts = vec![];
+        for version_time in [req.from, req.to] {
+            let version = self
+                .query_version(&NodeVersionReq {
+                    id: req.id.clone(),
+                    version_time,
+                })
+                .await?;
+            match version {
+                Some(node) => texts.push(parser::parse_content(&node).text.unwrap_or_default()),
+                None => {
+                    return log_and_err!("node {:?} has no version at {}", req.id, version_time)
+                }
+            }
+        }
 
-    async fn _move_node_to_history(&self, node_id: &NodeId) -> anyhow::Result<u64>;
+        Ok(NodeDiff {
+            id: req.id.clone(),
+            from: req.from,
+            to: req.to,
+            lines: diffutils::diff_lines(&texts[0], &texts[1]),
+        })
+    }
+
+    /// Make the content of a version current again, the replaced content is
+    /// kept in history like any other edit.
+    async fn restore_version(&self, req: &NodeVersionReq) -> anyhow::Result<NodeInsertResult> {
+        let mut tx = self.begin().await?;
+        let result = tx.restore_version(req).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Thin out the old versions of all nodes, return how many are removed.
+    async fn compact_history(&self, policy: &RetentionPolicy) -> anyhow::Result<u64> {
+        let mut tx = self.begin().await?;
+        let count = tx.compact_history(policy, Utc::now()).await?;
+        tx.commit().await?;
+        Ok(count)
+    }
+
+    /// Live nodes whose content refers to `id`, what links here.
+    async fn query_backlinks(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>>;
+
+    /// Nodes referred by the content of `id`, deleted or missing ones included.
+    async fn query_outgoing_links(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>>;
 
     /// Find descendants recursively.  
     ///
@@ -173,4 +371,16 @@ pub trait NodeMapper {
     ///
     /// Return a HashMap which child_id points to its parent.
     async fn find_ancestor_ids(&self, id: &NodeId) -> anyhow::Result<HashMap<NodeId, MagicNodeId>>;
+
+    /// The live node at a path of names like `/Work/Projects`, see `path` for
+    /// siblings sharing a name.
+    async fn resolve_path(&self, req: &NodePathReq) -> anyhow::Result<NodeId> {
+        path::resolve_path(self, &req.path).await
+    }
+
+    /// The names from the top level down to a live node, with the path which
+    /// resolves to it.
+    async fn query_breadcrumb(&self, id: &NodeId) -> anyhow::Result<NodeBreadcrumb> {
+        path::query_breadcrumb(self, id).await
+    }
 }
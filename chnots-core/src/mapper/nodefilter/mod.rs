@@ -0,0 +1,782 @@
+pub mod query;
+
+use std::{any::Any, vec};
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{de, Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    model::{
+        node::{NodeId, NodeType},
+        todo::TodoEvent,
+    },
+    parser::tiptap_v1_parser::normalize_tag,
+};
+
+use super::{
+    page::{NodeCursor, NodeSort},
+    search::{fts5_query, search_terms, tsquery_text},
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct NodeFetchReq {
+    pub selection: Option<Vec<NodeSelection>>,
+    pub filter: Option<NodeFilter>,
+    /// Unordered when it is missing, but pages are always ordered, by
+    /// `version_time` unless told otherwise.
+    #[serde(default)]
+    pub sort: Option<NodeSort>,
+    /// Continue after the last page, its sort wins over `sort`.
+    #[serde(default)]
+    pub cursor: Option<NodeCursor>,
+}
+
+impl NodeFetchReq {
+    pub(crate) fn with_selection(&self, selection: &NodeSelection, def: bool) -> bool {
+        if self.selection.is_none() {
+            def
+        } else {
+            self.selection
+                .as_ref()
+                .unwrap()
+                .iter()
+                .any(|e| e == selection)
+        }
+    }
+
+    pub(crate) fn with_limit(&self) -> Option<i32> {
+        if self.selection.is_none() {
+        } else {
+            for ele in self.selection.as_ref().unwrap() {
+                match ele {
+                    NodeSelection::Limit(c) => return Some(c.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        None
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: i32) {
+        let selection = self.selection.get_or_insert_with(Vec::new);
+        selection.retain(|e| !matches!(e, NodeSelection::Limit(_)));
+        selection.push(NodeSelection::Limit(limit));
+    }
+
+    /// The order of the nodes, the sort of the cursor wins.
+    pub(crate) fn sql_sort(&self) -> Option<NodeSort> {
+        self.cursor.as_ref().map(|c| c.sort).or(self.sort)
+    }
+
+    pub fn to_sql(&self, fields: &Vec<String>, dialect: SqlDialect) -> SqlQuery {
+        let with_content = self.with_selection(&NodeSelection::WithContent, false);
+
+        let with_history = self.with_selection(&NodeSelection::WithHistory, false);
+
+        let with_limit = self.with_limit();
+
+        let selection = if with_content {
+            "*".to_owned()
+        } else {
+            fields
+                .into_iter()
+                .filter(|e| e.to_lowercase().as_str() != "content")
+                .map(|e| e.to_owned())
+                .collect::<Vec<String>>()
+                .join(", ")
+        };
+
+        let mut query = SqlQuery::new(dialect);
+        query.push_str(&format!("select {} from nodes n", selection));
+
+        let mut has_where = false;
+
+        if let Some(f) = self.filter.as_ref() {
+            let part = f.to_sql(&mut query);
+            if !part.is_empty() {
+                query.push_str(" where ");
+                query.push_str(&part);
+                has_where = true;
+            }
+        }
+
+        if !with_history {
+            if has_where {
+                query.push_str(" and ");
+            } else {
+                query.push_str(" where ");
+            }
+            query.push_str("(n.delete_time is null)");
+            has_where = true;
+        }
+
+        let sort = self.sql_sort();
+        if let (Some(sort), Some(cursor)) = (sort, self.cursor.as_ref()) {
+            let column = sort.key.column(query.dialect);
+            let value = query.bind(cursor.value.to_param());
+            let id = query.bind(SqlParam::Text(cursor.id.as_str().to_owned()));
+            query.push_str(if has_where { " and " } else { " where " });
+            query.push_str(&format!(
+                "(({}, n.id) {} ({}, {}))",
+                column,
+                if sort.desc { "<" } else { ">" },
+                value,
+                id
+            ));
+        }
+
+        if let Some(sort) = sort {
+            let direction = if sort.desc { "desc" } else { "asc" };
+            query.push_str(&format!(
+                " order by {} {}, n.id {}",
+                sort.key.column(query.dialect),
+                direction,
+                direction
+            ));
+        }
+
+        if let Some(limit) = with_limit {
+            let ph = query.bind(SqlParam::Int(limit as i64));
+            query.push_str(&format!(" limit {}", ph));
+        }
+
+        info!("Query sql is: {}, params: {:?}", query.sql, query.params);
+
+        query
+    }
+}
+
+/// Placeholder style of a backend.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SqlDialect {
+    Postgres,
+    Sqlite,
+}
+
+/// A typed bind parameter, each backend knows how to pass it to its driver.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SqlParam {
+    Text(String),
+    Int(i64),
+    Bool(bool),
+    Time(DateTime<Utc>),
+}
+
+/// Generated sql text and the parameters bound to its placeholders.
+///
+/// User input never goes into `sql`, it is always passed through `bind`.
+#[derive(Clone, Debug)]
+pub struct SqlQuery {
+    pub dialect: SqlDialect,
+    pub sql: String,
+    pub params: Vec<SqlParam>,
+}
+
+impl SqlQuery {
+    pub fn new(dialect: SqlDialect) -> Self {
+        Self {
+            dialect,
+            sql: String::new(),
+            params: vec![],
+        }
+    }
+
+    pub fn push_str(&mut self, sql: &str) {
+        self.sql.push_str(sql);
+    }
+
+    /// Add a parameter and return the placeholder referring to it.
+    pub fn bind(&mut self, param: SqlParam) -> String {
+        self.params.push(param);
+        match self.dialect {
+            SqlDialect::Postgres => format!("${}", self.params.len()),
+            SqlDialect::Sqlite => format!("?{}", self.params.len()),
+        }
+    }
+}
+
+/// Escape the wildcards of a `like` pattern, to be used with `escape '\'`.
+pub fn escape_like(part: &str) -> String {
+    let mut escaped = String::with_capacity(part.len());
+    for c in part.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq, Eq)]
+pub enum NodeSelection {
+    WithContent,
+    WithHistory,
+    Limit(i32),
+}
+
+impl TryFrom<&str> for NodeSelection {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if value.starts_with("lim") {
+            let re = Regex::new(r"\d+$").unwrap();
+
+            if let Some(captures) = re.captures(value) {
+                if let Some(num_str) = captures.get(0) {
+                    if let Ok(num) = num_str.as_str().parse::<i32>() {
+                        return Ok(Self::Limit(num));
+                    }
+                }
+            } else {
+                return Ok(Self::Limit(6));
+            }
+        }
+
+        let pairs = vec![
+            (
+                vec!["cont", "content", "with_content", "withcontent"],
+                Self::WithContent,
+            ),
+            (
+                vec!["his", "hist", "history", "with_history", "withhistory"],
+                Self::WithHistory,
+            ),
+        ];
+
+        for (cond, v) in pairs {
+            if cond.contains(&value) {
+                return Ok(v);
+            }
+        }
+
+        Err(format!("Unknown key: {}", value))
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeSelection {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        Self::try_from(value.as_str().unwrap()).map_err(|err| de::Error::custom(err))
+    }
+}
+
+/// Half open, `since <= t < until`, a missing end is unbounded.
+#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct TimeRange {
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: &DateTime<Utc>) -> bool {
+        self.since.is_none_or(|since| &since <= time)
+            && self.until.is_none_or(|until| time < &until)
+    }
+}
+
+/// The value of a depth limited `descendant` filter.
+#[derive(Deserialize)]
+struct DepthLimit {
+    id: NodeId,
+    depth: u32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum TimeField {
+    VersionTime,
+    InitialTime,
+    /// Only deleted nodes have it, so it needs `NodeSelection::WithHistory`.
+    DeleteTime,
+}
+
+impl TimeField {
+    fn column(&self) -> &'static str {
+        match self {
+            TimeField::VersionTime => "n.version_time",
+            TimeField::InitialTime => "n.initial_time",
+            TimeField::DeleteTime => "n.delete_time",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub enum NodeFilter {
+    All,
+    Children(NodeId),
+    Id(NodeId),
+    Tag(String),
+    And(Box<Vec<NodeFilter>>),
+    Not(Box<NodeFilter>),
+    Or(Box<Vec<NodeFilter>>),
+    /// A part of the content or name, letters in any case.
+    Contains(String),
+    /// Match the words of the full text index, see `search::SearchMapper`.
+    Search(String),
+    Time(TimeField, TimeRange),
+    /// Any of these statuses.
+    TodoStatus(Vec<TodoEvent>),
+    Domain(String),
+    Readonly(bool),
+    NodeType(NodeType),
+    /// Below the node at any depth.
+    Descendant(NodeId),
+    /// Below the node at most this many levels down, depth 1 are the children.
+    DescendantWithin(NodeId, u32),
+    /// Above the node up to the top level.
+    Ancestor(NodeId),
+}
+
+impl NodeFilter {
+    fn from_json(jn: &Value) -> Result<NodeFilter, String> {
+        let filter = match jn.get("filter") {
+            Some(Value::String(v)) => Ok(v),
+            Some(v) => Err(format!(
+                "filter should be String, but it is {:?}",
+                v.type_id(),
+            )),
+            None => Err("filter is missing".to_string()),
+        }?;
+
+        let value = match jn.get("value") {
+            Some(v) => v,
+            None => &Value::Null,
+        };
+
+        let filter = filter.to_ascii_lowercase();
+        let filter = filter.as_str();
+
+        let text = || match value.as_str() {
+            Some(v) => Ok(v.to_owned()),
+            None => Err(format!(
+                "NodeFilter: {} expects a string: {}",
+                filter, value
+            )),
+        };
+
+        match filter {
+            "all" => Ok(NodeFilter::All),
+            "children" => Ok(NodeFilter::Children(text()?.into())),
+            "id" => Ok(NodeFilter::Id(text()?.into())),
+            "tag" => match value.as_str().and_then(normalize_tag) {
+                Some(tag) => Ok(NodeFilter::Tag(tag)),
+                None => Err(format!("NodeFilter: invalid tag: {}", value)),
+            },
+            "and" | "or" => {
+                let vec = match value.as_array() {
+                    Some(vec) => vec
+                        .iter()
+                        .map(Self::from_json)
+                        .collect::<Result<Vec<NodeFilter>, String>>()?,
+                    None => {
+                        return Err(format!(
+                            "NodeFilter: {} expects an array: {}",
+                            filter, value
+                        ))
+                    }
+                };
+                match filter {
+                    "and" => Ok(NodeFilter::And(Box::new(vec))),
+                    _ => Ok(NodeFilter::Or(Box::new(vec))),
+                }
+            }
+            "not" => Ok(NodeFilter::Not(Box::new(Self::from_json(value)?))),
+            "contains" | "like" => Ok(NodeFilter::Contains(text()?)),
+            "search" => match value.as_str() {
+                Some(v) if !search_terms(v).is_empty() => Ok(NodeFilter::Search(v.to_owned())),
+                _ => Err(format!("NodeFilter: invalid search: {}", value)),
+            },
+            "version_time" | "initial_time" | "delete_time" => {
+                let field = match filter {
+                    "version_time" => TimeField::VersionTime,
+                    "initial_time" => TimeField::InitialTime,
+                    _ => TimeField::DeleteTime,
+                };
+                serde_json::from_value(value.clone())
+                    .map(|range| NodeFilter::Time(field, range))
+                    .map_err(|err| format!("NodeFilter: invalid time range: {}, {}", value, err))
+            }
+            "todo_status" => {
+                let statuses = match value {
+                    Value::Array(_) => serde_json::from_value(value.clone()),
+                    _ => serde_json::from_value(value.clone()).map(|e| vec![e]),
+                };
+                match statuses {
+                    Ok(statuses) if !statuses.is_empty() => Ok(NodeFilter::TodoStatus(statuses)),
+                    _ => Err(format!("NodeFilter: invalid todo status: {}", value)),
+                }
+            }
+            "domain" => Ok(NodeFilter::Domain(text()?)),
+            "readonly" => match value.as_bool() {
+                Some(v) => Ok(NodeFilter::Readonly(v)),
+                None => Err(format!("NodeFilter: readonly expects a bool: {}", value)),
+            },
+            "node_type" => serde_json::from_value(value.clone())
+                .map(NodeFilter::NodeType)
+                .map_err(|_| format!("NodeFilter: invalid node type: {}", value)),
+            "query" => Self::parse_query(&text()?).map_err(|err| format!("NodeFilter: {}", err)),
+            "descendant" => match value {
+                Value::Object(_) => serde_json::from_value::<DepthLimit>(value.clone())
+                    .map(|e| NodeFilter::DescendantWithin(e.id, e.depth))
+                    .map_err(|err| format!("NodeFilter: invalid descendant: {}, {}", value, err)),
+                _ => Ok(NodeFilter::Descendant(text()?.into())),
+            },
+            "ancestor" => Ok(NodeFilter::Ancestor(text()?.into())),
+            key => Err(format!("NodeFilter: unknown filter: `{}'", key)),
+        }
+    }
+
+    /// Compile to a sql condition, parameters are bound into `query`.
+    ///
+    /// An empty condition matches every node.
+    pub fn to_sql(&self, query: &mut SqlQuery) -> String {
+        let inner = match &self {
+            NodeFilter::All => "".to_string(),
+            NodeFilter::Children(id) => {
+                let ph = query.bind(SqlParam::Text(id.as_str().to_owned()));
+                format!("n.parent_id = {}", ph)
+            }
+            NodeFilter::Id(id) => {
+                let ph = query.bind(SqlParam::Text(id.as_str().to_owned()));
+                format!("n.id = {}", ph)
+            }
+            NodeFilter::Tag(tag) => {
+                let ph = query.bind(SqlParam::Text(tag.clone()));
+                format!("n.id in (select node_id from tags t where t.tag = {})", ph)
+            }
+            NodeFilter::And(nf) => nf
+                .iter()
+                .map(|e| e.to_sql(query))
+                .filter(|e| !e.is_empty())
+                .collect::<Vec<String>>()
+                .join(" and "),
+            NodeFilter::Not(nf) => {
+                let p = nf.to_sql(query);
+                if p.is_empty() {
+                    "1 = 0".to_string()
+                } else {
+                    format!("not {}", p)
+                }
+            }
+            NodeFilter::Or(nf) => {
+                let bound = query.params.len();
+                let parts: Vec<String> = nf.iter().map(|e| e.to_sql(query)).collect();
+                // One part matching everything makes the whole matching everything,
+                // drop the parameters of the others as nothing refers to them.
+                if parts.iter().any(|e| e.is_empty()) {
+                    query.params.truncate(bound);
+                    "".to_string()
+                } else {
+                    parts.join(" or ")
+                }
+            }
+            NodeFilter::Contains(part) => {
+                let ph = query.bind(SqlParam::Text(format!("%{}%", escape_like(part))));
+                // The `like` of SQLite ignores the case already.
+                let like = match query.dialect {
+                    SqlDialect::Postgres => "ilike",
+                    SqlDialect::Sqlite => "like",
+                };
+                format!(
+                    "n.content {} {} escape '\\' or n.name {} {} escape '\\'",
+                    like, ph, like, ph
+                )
+            }
+            NodeFilter::Search(input) => match query.dialect {
+                SqlDialect::Postgres => {
+                    let ph = query.bind(SqlParam::Text(tsquery_text(input)));
+                    format!(
+                        "n.id in (select s.node_id from node_search s where s.tsv @@ plainto_tsquery('simple', {}))",
+                        ph
+                    )
+                }
+                SqlDialect::Sqlite => {
+                    let ph = query.bind(SqlParam::Text(fts5_query(input)));
+                    format!(
+                        "n.id in (select d.node_id from node_search join node_search_docs d on d.id = node_search.rowid where node_search match {})",
+                        ph
+                    )
+                }
+            },
+            NodeFilter::Time(field, range) => {
+                let mut parts = vec![format!("{} is not null", field.column())];
+                if let Some(since) = range.since {
+                    let ph = query.bind(SqlParam::Time(since));
+                    parts.push(format!("{} >= {}", field.column(), ph));
+                }
+                if let Some(until) = range.until {
+                    let ph = query.bind(SqlParam::Time(until));
+                    parts.push(format!("{} < {}", field.column(), ph));
+                }
+                parts.join(" and ")
+            }
+            NodeFilter::TodoStatus(statuses) => {
+                if statuses.is_empty() {
+                    "1 = 0".to_string()
+                } else {
+                    let phs: Vec<String> = statuses
+                        .iter()
+                        .map(|e| query.bind(SqlParam::Text(e.as_ref().to_owned())))
+                        .collect();
+                    format!("n.todo_status in ({})", phs.join(", "))
+                }
+            }
+            NodeFilter::Domain(domain) => {
+                let ph = query.bind(SqlParam::Text(domain.clone()));
+                format!("n.domain = {}", ph)
+            }
+            NodeFilter::Readonly(readonly) => {
+                let ph = query.bind(SqlParam::Bool(*readonly));
+                format!("n.readonly = {}", ph)
+            }
+            NodeFilter::NodeType(node_type) => {
+                let ph = query.bind(SqlParam::Text(node_type.as_ref().to_owned()));
+                format!("n.node_type = {}", ph)
+            }
+            NodeFilter::Descendant(id) => {
+                let ph = query.bind(SqlParam::Text(id.as_str().to_owned()));
+                format!(
+                    "n.id in (select p.node_id from node_paths p where p.ancestor_id = {})",
+                    ph
+                )
+            }
+            NodeFilter::DescendantWithin(id, depth) => {
+                let ph = query.bind(SqlParam::Text(id.as_str().to_owned()));
+                let depth_ph = query.bind(SqlParam::Int(*depth as i64));
+                format!(
+                    "n.id in (select p.node_id from node_paths p where p.ancestor_id = {} and p.depth <= {})",
+                    ph, depth_ph
+                )
+            }
+            NodeFilter::Ancestor(id) => {
+                let ph = query.bind(SqlParam::Text(id.as_str().to_owned()));
+                format!(
+                    "n.id in (select p.ancestor_id from node_paths p where p.node_id = {})",
+                    ph
+                )
+            }
+        };
+
+        if inner.is_empty() {
+            inner
+        } else {
+            format!("({})", inner)
+        }
+    }
+}
+
+/// `(id, parent_id)` of every node below the node bound at `ph`.
+pub(crate) fn sql_descendants(ph: &str) -> String {
+    format!(
+        "select n.id, n.parent_id from node_paths p join nodes n on n.id = p.node_id where p.ancestor_id = {}",
+        ph
+    )
+}
+
+/// `(id, parent_id)` of the node bound at `ph` and every node above it.
+pub(crate) fn sql_ancestors(ph: &str) -> String {
+    format!(
+        "select n.id, n.parent_id from nodes n where n.id = {0}
+or n.id in (select p.ancestor_id from node_paths p where p.node_id = {0})",
+        ph
+    )
+}
+
+impl<'de> Deserialize<'de> for NodeFilter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let value = serde_json::Value::deserialize(deserializer)?;
+
+        // A query in the text syntax, see `query`.
+        if let Value::String(query) = &value {
+            return Self::parse_query(query).map_err(de::Error::custom);
+        }
+
+        Self::from_json(&value)
+            .map(|f| f)
+            .map_err(|err| de::Error::custom(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::nodefilter::NodeFilter;
+
+    use super::{NodeFetchReq, NodeSelection, SqlDialect, SqlParam, SqlQuery, TimeField};
+    use crate::mapper::page::{CursorValue, NodeCursor, NodeSort, NodeSortKey};
+
+    #[test]
+    fn test() {
+        let s = r#"
+        {
+            "filter": "and",
+            "value": [
+                {"filter": "all"},
+                {"filter": "children",
+                "value": "asdasd"}
+            ]
+        }
+        "#;
+
+        let j: Result<NodeFilter, serde_json::Error> = serde_json::from_str(s);
+        println!("{:?}", j);
+
+        let s = r#"
+        {
+            "selection": ["cont"],
+        "filter":{
+            "filter": "and",
+            "value": [
+                {"filter": "all"},
+                {"filter": "children",
+                "value": "asdasd"},
+                {"filter": "and", "value": []}
+            ]
+        }
+        }
+        "#;
+
+        let j: Result<NodeFetchReq, serde_json::Error> = serde_json::from_str(s);
+        println!(
+            "{:?}, {:?}",
+            &j,
+            &j.as_ref().unwrap().to_sql(&vec![], SqlDialect::Postgres)
+        );
+    }
+
+    #[test]
+    fn test_params() {
+        let req = NodeFetchReq {
+            selection: Some(vec![NodeSelection::Limit(3)]),
+            filter: Some(NodeFilter::And(Box::new(vec![
+                NodeFilter::Children("a' or 1=1 --".into()),
+                NodeFilter::Contains("100%_off\\".to_owned()),
+            ]))),
+            ..Default::default()
+        };
+
+        let query = req.to_sql(&vec!["id".to_owned()], SqlDialect::Sqlite);
+        assert!(query.sql.contains("n.parent_id = ?1"));
+        assert!(query.sql.contains("n.content like ?2 escape '\\'"));
+        assert!(query.sql.ends_with("limit ?3"));
+        assert_eq!(
+            query.params,
+            vec![
+                SqlParam::Text("a' or 1=1 --".to_owned()),
+                SqlParam::Text("%100\\%\\_off\\\\%".to_owned()),
+                SqlParam::Int(3),
+            ]
+        );
+
+        let query = req.to_sql(&vec!["id".to_owned()], SqlDialect::Postgres);
+        assert!(query.sql.contains("n.parent_id = $1"));
+        assert!(query.sql.contains("n.content ilike $2 escape '\\'"));
+        assert!(query.sql.ends_with("limit $3"));
+    }
+
+    #[test]
+    fn test_sort() {
+        let sort = NodeSort {
+            key: NodeSortKey::Name,
+            desc: true,
+        };
+        let mut req = NodeFetchReq {
+            selection: Some(vec![NodeSelection::Limit(3)]),
+            sort: Some(NodeSort::default()),
+            cursor: Some(NodeCursor {
+                sort,
+                value: CursorValue::Text("b".to_owned()),
+                id: "x".into(),
+            }),
+            ..Default::default()
+        };
+
+        // The sort of the cursor wins.
+        let query = req.to_sql(&vec!["id".to_owned()], SqlDialect::Sqlite);
+        assert!(query
+            .sql
+            .ends_with("where (n.delete_time is null) and ((n.name, n.id) < (?1, ?2)) order by n.name desc, n.id desc limit ?3"));
+        assert_eq!(query.params[0], SqlParam::Text("b".to_owned()));
+
+        req.cursor = None;
+        req.sort = Some(NodeSort {
+            key: NodeSortKey::TreeOrder,
+            desc: false,
+        });
+        let query = req.to_sql(&vec!["id".to_owned()], SqlDialect::Sqlite);
+        assert!(query.sql.contains("from node_paths p"));
+        assert!(query
+            .sql
+            .ends_with("printf('%010d', n.position)) asc, n.id asc limit ?1"));
+    }
+
+    #[test]
+    fn test_filters() {
+        let parse = |s: &str| serde_json::from_str::<NodeFilter>(s);
+
+        let filter = parse(
+            r#"{"filter": "and", "value": [
+                {"filter": "not", "value": {"filter": "readonly", "value": true}},
+                {"filter": "version_time", "value": {"since": "2024-01-01T00:00:00Z"}},
+                {"filter": "todo_status", "value": ["todo", "DONE"]},
+                {"filter": "descendant", "value": "a"}
+            ]}"#,
+        )
+        .unwrap();
+        match &filter {
+            NodeFilter::And(nf) => {
+                assert!(matches!(nf[1], NodeFilter::Time(TimeField::VersionTime, _)));
+                assert!(matches!(&nf[2], NodeFilter::TodoStatus(e) if e.len() == 2));
+            }
+            _ => panic!("{:?}", filter),
+        }
+
+        let mut query = SqlQuery::new(SqlDialect::Postgres);
+        let sql = filter.to_sql(&mut query);
+        assert_eq!(sql, "((not (n.readonly = $1)) and (n.version_time is not null and n.version_time >= $2) and (n.todo_status in ($3, $4)) and (n.id in (select p.node_id from node_paths p where p.ancestor_id = $5)))");
+        assert_eq!(query.params[0], SqlParam::Bool(true));
+
+        let filter =
+            parse(r#"{"filter": "descendant", "value": {"id": "a", "depth": 2}}"#).unwrap();
+        assert_eq!(filter, NodeFilter::DescendantWithin("a".into(), 2));
+        let mut query = SqlQuery::new(SqlDialect::Sqlite);
+        let sql = filter.to_sql(&mut query);
+        assert!(sql.contains("p.depth <= ?2"), "{}", sql);
+        assert_eq!(query.params[1], SqlParam::Int(2));
+
+        // Matching nothing, not everything.
+        let mut query = SqlQuery::new(SqlDialect::Sqlite);
+        let sql = NodeFilter::Not(Box::new(NodeFilter::All)).to_sql(&mut query);
+        assert_eq!(sql, "(1 = 0)");
+
+        let mut query = SqlQuery::new(SqlDialect::Sqlite);
+        let sql = NodeFilter::Or(Box::new(vec![
+            NodeFilter::Domain("x".to_owned()),
+            NodeFilter::All,
+        ]))
+        .to_sql(&mut query);
+        assert_eq!(sql, "");
+        assert!(query.params.is_empty());
+
+        for s in [
+            r#"{"filter": "children"}"#,
+            r#"{"filter": "and", "value": [{"filter": "nope"}]}"#,
+            r#"{"filter": "readonly", "value": "yes"}"#,
+            r#"{"filter": "descendant", "value": {"id": "a"}}"#,
+            r#"{"filter": "todo_status", "value": []}"#,
+            r#"{"filter": "initial_time", "value": {"since": "yesterday"}}"#,
+        ] {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+    }
+}
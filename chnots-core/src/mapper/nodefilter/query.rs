@@ -0,0 +1,557 @@
+//! A text syntax for `NodeFilter`, e.g.
+//!
+//! ```text
+//! tag:work and todo:TODO and -tag:archived "release notes" under:<id> updated:>2024-05-01
+//! ```
+//!
+//! - Terms next to each other must all match, `and` may be written out too.
+//!   `or` binds looser than `and`, parentheses group.
+//! - `-term` and `not term` negate.
+//! - A bare word matches like `NodeFilter::Search`, a quoted phrase like
+//!   `NodeFilter::Contains`. Keywords are case insensitive, quote them to look
+//!   for the word itself.
+//! - `key:value` terms, the value may be quoted:
+//!   `tag:`, `todo:` (statuses separated by commas), `id:`, `parent:`,
+//!   `under:` (any depth), `above:`, `domain:`, `type:`, `readonly:true|false`,
+//!   `updated:`, `created:` and `deleted:`.
+//! - A time is a date like `2024-05-01`, which stands for the whole day in
+//!   UTC, or a RFC 3339 time. It takes one of `>`, `>=`, `<`, `<=`, `=` in
+//!   front, or is a range `2024-05-01..2024-05-31` including both ends, where
+//!   either end may be left out.
+
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveTime, Utc};
+use serde::Serialize;
+
+use crate::{
+    mapper::search::search_terms,
+    model::{node::NodeType, todo::TodoEvent},
+    parser::tiptap_v1_parser::normalize_tag,
+};
+
+use super::{NodeFilter, TimeField, TimeRange};
+
+const KEYS: [&str; 12] = [
+    "tag", "todo", "id", "parent", "under", "above", "domain", "type", "readonly", "updated",
+    "created", "deleted",
+];
+
+/// Why a query is invalid, `position` counts chars from 0.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct QueryError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl QueryError {
+    fn new(position: usize, message: String) -> Self {
+        Self { position, message }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid query at {}: {}", self.position, self.message)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl NodeFilter {
+    /// Compile a query, a blank one matches every node.
+    pub fn parse_query(query: &str) -> Result<NodeFilter, QueryError> {
+        let tokens = tokenize(query)?;
+        if tokens.is_empty() {
+            return Ok(NodeFilter::All);
+        }
+
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            end: query.chars().count(),
+        };
+        let filter = parser.parse_or()?;
+        match parser.tokens.get(parser.pos) {
+            Some((position, _)) => Err(QueryError::new(*position, "unexpected `)`".to_owned())),
+            None => Ok(filter),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Minus,
+    Word(String),
+    Quoted(String),
+    Pair {
+        key: String,
+        value: String,
+        value_pos: usize,
+    },
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, QueryError> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = vec![];
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let start = pos;
+        match chars[pos] {
+            c if c.is_whitespace() => pos += 1,
+            '(' => {
+                tokens.push((start, Token::Open));
+                pos += 1;
+            }
+            ')' => {
+                tokens.push((start, Token::Close));
+                pos += 1;
+            }
+            '-' => {
+                tokens.push((start, Token::Minus));
+                pos += 1;
+            }
+            '"' => {
+                let (text, next) = quoted(&chars, pos)?;
+                tokens.push((start, Token::Quoted(text)));
+                pos = next;
+            }
+            _ => {
+                while pos < chars.len() && !is_delimiter(chars[pos]) {
+                    pos += 1;
+                }
+                let word: String = chars[start..pos].iter().collect();
+                let (key, value) = match word.split_once(':') {
+                    Some(pair) => pair,
+                    None => {
+                        tokens.push((start, Token::Word(word)));
+                        continue;
+                    }
+                };
+                if key.is_empty() {
+                    return Err(QueryError::new(
+                        start,
+                        "a key is missing before `:`".to_owned(),
+                    ));
+                }
+
+                let value_pos = start + key.chars().count() + 1;
+                let value = if value.is_empty() && chars.get(pos) == Some(&'"') {
+                    let (text, next) = quoted(&chars, pos)?;
+                    pos = next;
+                    text
+                } else {
+                    value.to_owned()
+                };
+                tokens.push((
+                    start,
+                    Token::Pair {
+                        key: key.to_owned(),
+                        value,
+                        value_pos,
+                    },
+                ));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '"'
+}
+
+/// The text of the quote starting at `start`, and where the rest begins.
+fn quoted(chars: &[char], start: usize) -> Result<(String, usize), QueryError> {
+    let mut text = String::new();
+    let mut pos = start + 1;
+    while pos < chars.len() {
+        match chars[pos] {
+            '"' => return Ok((text, pos + 1)),
+            '\\' if pos + 1 < chars.len() => {
+                text.push(chars[pos + 1]);
+                pos += 2;
+            }
+            c => {
+                text.push(c);
+                pos += 1;
+            }
+        }
+    }
+    Err(QueryError::new(start, "the quote is not closed".to_owned()))
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    /// Chars of the query, where errors at the end point to.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|e| &e.1)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<NodeFilter, QueryError> {
+        let mut parts = vec![self.parse_and()?];
+        while self.keyword("or") {
+            parts.push(self.parse_and()?);
+        }
+        Ok(match parts.len() {
+            1 => parts.remove(0),
+            _ => NodeFilter::Or(Box::new(parts)),
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<NodeFilter, QueryError> {
+        let mut parts = vec![self.parse_unary()?];
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) => break,
+                Some(Token::Word(word)) if word.eq_ignore_ascii_case("or") => break,
+                _ => {}
+            }
+            self.keyword("and");
+            parts.push(self.parse_unary()?);
+        }
+        Ok(match parts.len() {
+            1 => parts.remove(0),
+            _ => NodeFilter::And(Box::new(parts)),
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<NodeFilter, QueryError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.pos += 1;
+            return Ok(NodeFilter::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.keyword("not") {
+            return Ok(NodeFilter::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_term()
+    }
+
+    fn parse_term(&mut self) -> Result<NodeFilter, QueryError> {
+        let (position, token) = match self.tokens.get(self.pos) {
+            Some(e) => e.clone(),
+            None => {
+                return Err(QueryError::new(
+                    self.end,
+                    "the query ends where a term is expected".to_owned(),
+                ))
+            }
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Open => {
+                let filter = self.parse_or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(filter)
+                    }
+                    _ => Err(QueryError::new(
+                        position,
+                        "the parenthesis is not closed".to_owned(),
+                    )),
+                }
+            }
+            Token::Close => Err(QueryError::new(
+                position,
+                "unexpected `)`, a term is expected".to_owned(),
+            )),
+            Token::Minus => Err(QueryError::new(
+                position,
+                "unexpected `-`, a term is expected".to_owned(),
+            )),
+            Token::Word(word) => {
+                if ["and", "or", "not"]
+                    .iter()
+                    .any(|e| word.eq_ignore_ascii_case(e))
+                {
+                    Err(QueryError::new(
+                        position,
+                        format!("unexpected `{}`, quote it to look for the word", word),
+                    ))
+                } else if search_terms(&word).is_empty() {
+                    Err(QueryError::new(
+                        position,
+                        format!("`{}` has no word to search", word),
+                    ))
+                } else {
+                    Ok(NodeFilter::Search(word))
+                }
+            }
+            Token::Quoted(text) => {
+                if text.is_empty() {
+                    Err(QueryError::new(position, "the phrase is empty".to_owned()))
+                } else {
+                    Ok(NodeFilter::Contains(text))
+                }
+            }
+            Token::Pair {
+                key,
+                value,
+                value_pos,
+            } => pair(&key, &value, position, value_pos),
+        }
+    }
+}
+
+fn pair(
+    key: &str,
+    value: &str,
+    position: usize,
+    value_pos: usize,
+) -> Result<NodeFilter, QueryError> {
+    let key = key.to_ascii_lowercase();
+    if !KEYS.contains(&key.as_str()) {
+        return Err(QueryError::new(
+            position,
+            format!("unknown key `{}`, expected one of {}", key, KEYS.join(", ")),
+        ));
+    }
+    if value.is_empty() {
+        return Err(QueryError::new(
+            value_pos,
+            format!("`{}:` needs a value", key),
+        ));
+    }
+    let invalid = |what: &str| {
+        Err(QueryError::new(
+            value_pos,
+            format!("invalid {} `{}`", what, value),
+        ))
+    };
+
+    match key.as_str() {
+        "tag" => match normalize_tag(value) {
+            Some(tag) => Ok(NodeFilter::Tag(tag)),
+            None => invalid("tag"),
+        },
+        "todo" => {
+            let mut statuses = vec![];
+            let mut pos = value_pos;
+            for part in value.split(',') {
+                match TodoEvent::from_str(&part.to_ascii_uppercase()) {
+                    Ok(status) => statuses.push(status),
+                    Err(_) => {
+                        return Err(QueryError::new(
+                            pos,
+                            format!("invalid todo status `{}`", part),
+                        ))
+                    }
+                }
+                pos += part.chars().count() + 1;
+            }
+            Ok(NodeFilter::TodoStatus(statuses))
+        }
+        "id" => Ok(NodeFilter::Id(value.into())),
+        "parent" => Ok(NodeFilter::Children(value.into())),
+        "under" => Ok(NodeFilter::Descendant(value.into())),
+        "above" => Ok(NodeFilter::Ancestor(value.into())),
+        "domain" => Ok(NodeFilter::Domain(value.to_owned())),
+        "type" => match NodeType::from_str(value) {
+            Ok(node_type) => Ok(NodeFilter::NodeType(node_type)),
+            Err(_) => invalid("node type"),
+        },
+        "readonly" => match value.to_ascii_lowercase().as_str() {
+            "true" => Ok(NodeFilter::Readonly(true)),
+            "false" => Ok(NodeFilter::Readonly(false)),
+            _ => invalid("bool"),
+        },
+        _ => {
+            let field = match key.as_str() {
+                "updated" => TimeField::VersionTime,
+                "created" => TimeField::InitialTime,
+                _ => TimeField::DeleteTime,
+            };
+            Ok(NodeFilter::Time(field, time_range(value, value_pos)?))
+        }
+    }
+}
+
+fn time_range(value: &str, position: usize) -> Result<TimeRange, QueryError> {
+    if let Some((since, until)) = value.split_once("..") {
+        if since.is_empty() && until.is_empty() {
+            return Err(QueryError::new(position, "the range is empty".to_owned()));
+        }
+        let until_pos = position + since.chars().count() + 2;
+        return Ok(TimeRange {
+            since: match since {
+                "" => None,
+                _ => Some(time_span(since, position)?.0),
+            },
+            until: match until {
+                "" => None,
+                _ => Some(time_span(until, until_pos)?.1),
+            },
+        });
+    }
+
+    let (op, time) = [">=", "<=", ">", "<", "="]
+        .iter()
+        .find_map(|op| value.strip_prefix(op).map(|rest| (*op, rest)))
+        .unwrap_or(("=", value));
+    let (start, end) = time_span(time, position + value.len() - time.len())?;
+
+    Ok(match op {
+        ">" => TimeRange {
+            since: Some(end),
+            until: None,
+        },
+        ">=" => TimeRange {
+            since: Some(start),
+            until: None,
+        },
+        "<" => TimeRange {
+            since: None,
+            until: Some(start),
+        },
+        "<=" => TimeRange {
+            since: None,
+            until: Some(end),
+        },
+        _ => TimeRange {
+            since: Some(start),
+            until: Some(end),
+        },
+    })
+}
+
+/// The half open span a time stands for, a whole day for a date.
+fn time_span(time: &str, position: usize) -> Result<(DateTime<Utc>, DateTime<Utc>), QueryError> {
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        let start = date.and_time(NaiveTime::MIN).and_utc();
+        return Ok((start, start + Duration::days(1)));
+    }
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(time) => {
+            let time = time.to_utc();
+            Ok((time, time + Duration::nanoseconds(1)))
+        }
+        Err(_) => Err(QueryError::new(
+            position,
+            format!(
+                "invalid time `{}`, expected a date like 2024-05-01 or a RFC 3339 time",
+                time
+            ),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use crate::{
+        mapper::nodefilter::{NodeFilter, TimeField, TimeRange},
+        model::todo::TodoEvent,
+    };
+
+    use super::QueryError;
+
+    fn error(query: &str) -> (usize, String) {
+        let QueryError { position, message } = NodeFilter::parse_query(query).unwrap_err();
+        (position, message)
+    }
+
+    #[test]
+    fn test_parse() {
+        let filter = NodeFilter::parse_query(
+            r#"tag:work and todo:TODO,doing -tag:archived "release notes" under:n1 updated:>2024-05-01"#,
+        )
+        .unwrap();
+        assert_eq!(
+            filter,
+            NodeFilter::And(Box::new(vec![
+                NodeFilter::Tag("work".to_owned()),
+                NodeFilter::TodoStatus(vec![TodoEvent::Todo, TodoEvent::Doing]),
+                NodeFilter::Not(Box::new(NodeFilter::Tag("archived".to_owned()))),
+                NodeFilter::Contains("release notes".to_owned()),
+                NodeFilter::Descendant("n1".into()),
+                NodeFilter::Time(
+                    TimeField::VersionTime,
+                    TimeRange {
+                        since: Some(Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap()),
+                        until: None,
+                    }
+                ),
+            ]))
+        );
+
+        // `or` binds looser than `and`.
+        assert_eq!(
+            NodeFilter::parse_query("a b OR not (c or domain:\"x y\")").unwrap(),
+            NodeFilter::Or(Box::new(vec![
+                NodeFilter::And(Box::new(vec![
+                    NodeFilter::Search("a".to_owned()),
+                    NodeFilter::Search("b".to_owned()),
+                ])),
+                NodeFilter::Not(Box::new(NodeFilter::Or(Box::new(vec![
+                    NodeFilter::Search("c".to_owned()),
+                    NodeFilter::Domain("x y".to_owned()),
+                ])))),
+            ]))
+        );
+
+        assert_eq!(
+            NodeFilter::parse_query("created:2024-01-01..2024-01-31").unwrap(),
+            NodeFilter::Time(
+                TimeField::InitialTime,
+                TimeRange {
+                    since: Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+                    until: Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap()),
+                }
+            )
+        );
+        assert_eq!(
+            NodeFilter::parse_query("updated:<2024-01-01T08:00:00+08:00").unwrap(),
+            NodeFilter::Time(
+                TimeField::VersionTime,
+                TimeRange {
+                    since: None,
+                    until: Some(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()),
+                }
+            )
+        );
+        assert_eq!(NodeFilter::parse_query("  ").unwrap(), NodeFilter::All);
+        assert_eq!(
+            NodeFilter::parse_query(r#""and""#).unwrap(),
+            NodeFilter::Contains("and".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error("tag:a (b or c").0, 6);
+        assert_eq!(error("a b)").0, 3);
+        assert_eq!(error("a and").0, 5);
+        assert_eq!(error("or a").0, 0);
+        assert_eq!(error(r#"a "b c"#).0, 2);
+        assert_eq!(error("a -").0, 3);
+        assert_eq!(error("a color:red").0, 2);
+        assert_eq!(error("todo:todo,dong").0, 10);
+        assert_eq!(error("updated:>=2024-13-01").0, 10);
+        assert_eq!(error("created:2024-01-01..nope").0, 20);
+        assert_eq!(error("readonly:").0, 9);
+        assert_eq!(error("été :x").0, 4);
+        assert!(error("a +++").1.contains("no word"));
+    }
+}
@@ -0,0 +1,344 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use chin_tools::log_and_err;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de, Deserialize, Serialize};
+
+use crate::model::node::{MagicNodeId, Node, NodeId};
+
+use super::{
+    node::NodeMapper,
+    nodefilter::{NodeFetchReq, NodeFilter, NodeSelection, SqlDialect, SqlParam},
+};
+
+/// Keys the nodes can be ordered by, ties are broken by the id.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeSortKey {
+    #[default]
+    VersionTime,
+    InitialTime,
+    Name,
+    /// Depth first, siblings by their order keys, see `tree_key`.
+    TreeOrder,
+    /// The order key, which orders siblings, e.g. the nodes of a `Children` filter.
+    Position,
+}
+
+impl NodeSortKey {
+    /// The sql expression of a key.
+    pub(crate) fn column(&self, dialect: SqlDialect) -> &'static str {
+        match (self, dialect) {
+            (NodeSortKey::VersionTime, _) => "n.version_time",
+            (NodeSortKey::InitialTime, _) => "n.initial_time",
+            (NodeSortKey::Name, _) => "n.name",
+            (NodeSortKey::TreeOrder, SqlDialect::Postgres) => {
+                "(coalesce((select string_agg(case when a.id is null then p.ancestor_id || '/' else lpad(a.position::text, 10, '0') end, '' order by p.depth desc)
+from node_paths p left join nodes a on a.id = p.ancestor_id where p.node_id = n.id), n.parent_id || '/')
+|| lpad(n.position::text, 10, '0') collate \"C\")"
+            }
+            (NodeSortKey::TreeOrder, SqlDialect::Sqlite) => {
+                "(coalesce((select group_concat(k, '') from (select case when a.id is null then p.ancestor_id || '/' else printf('%010d', a.position) end as k
+from node_paths p left join nodes a on a.id = p.ancestor_id where p.node_id = n.id order by p.depth desc)), n.parent_id || '/')
+|| printf('%010d', n.position))"
+            }
+            (NodeSortKey::Position, _) => "n.position",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct NodeSort {
+    #[serde(default)]
+    pub key: NodeSortKey,
+    #[serde(default)]
+    pub desc: bool,
+}
+
+impl NodeSort {
+    fn direct(&self, ordering: Ordering) -> Ordering {
+        if self.desc {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    /// Order of two nodes by a key of their own, any but `TreeOrder`.
+    pub(crate) fn compare(&self, a: &Node, b: &Node) -> Ordering {
+        self.compare_values(
+            (&sort_value(self.key, a), &a.id),
+            (&sort_value(self.key, b), &b.id),
+        )
+    }
+
+    /// Order of two nodes by their values of the key, e.g. their tree keys.
+    pub(crate) fn compare_values(
+        &self,
+        a: (&Option<CursorValue>, &NodeId),
+        b: (&Option<CursorValue>, &NodeId),
+    ) -> Ordering {
+        self.direct(a.0.cmp(b.0).then_with(|| a.1.as_str().cmp(b.1.as_str())))
+    }
+}
+
+/// The sort key of the last node of a page.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CursorValue {
+    Time(DateTime<Utc>),
+    /// A name, or a tree key.
+    Text(String),
+    Position(i64),
+}
+
+impl CursorValue {
+    pub(crate) fn to_param(&self) -> SqlParam {
+        match self {
+            CursorValue::Time(time) => SqlParam::Time(*time),
+            CursorValue::Text(text) => SqlParam::Text(text.clone()),
+            CursorValue::Position(position) => SqlParam::Int(*position),
+        }
+    }
+}
+
+pub(crate) fn sort_value(key: NodeSortKey, node: &Node) -> Option<CursorValue> {
+    match key {
+        NodeSortKey::VersionTime => Some(CursorValue::Time(node.version_time)),
+        NodeSortKey::InitialTime => Some(CursorValue::Time(node.initial_time.with_timezone(&Utc))),
+        NodeSortKey::Name => Some(CursorValue::Text(node.name.clone())),
+        NodeSortKey::TreeOrder => None,
+        NodeSortKey::Position => Some(CursorValue::Position(node.position)),
+    }
+}
+
+/// Where the next page starts, right after the node `id`.
+///
+/// Clients get it as an opaque string and send it back unchanged, it carries
+/// its own sort, so the following pages keep the order of the first one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeCursor {
+    pub sort: NodeSort,
+    pub value: CursorValue,
+    pub id: NodeId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawCursor {
+    key: NodeSortKey,
+    desc: bool,
+    value: String,
+    id: String,
+}
+
+impl NodeCursor {
+    fn after(sort: NodeSort, node: &Node) -> Option<Self> {
+        sort_value(sort.key, node).map(|value| NodeCursor {
+            sort,
+            value,
+            id: node.id.clone(),
+        })
+    }
+
+    /// Whether a node with the value `value` of the key comes after the cursor.
+    pub(crate) fn is_before(&self, value: &Option<CursorValue>, id: &NodeId) -> bool {
+        self.sort
+            .compare_values((&Some(self.value.clone()), &self.id), (value, id))
+            == Ordering::Less
+    }
+
+    pub fn encode(&self) -> String {
+        let value = match &self.value {
+            CursorValue::Time(time) => time.to_rfc3339_opts(SecondsFormat::Nanos, true),
+            CursorValue::Text(text) => text.clone(),
+            CursorValue::Position(position) => position.to_string(),
+        };
+        let raw = serde_json::to_string(&RawCursor {
+            key: self.sort.key,
+            desc: self.sort.desc,
+            value,
+            id: self.id.as_str().to_owned(),
+        })
+        .unwrap_or_default();
+        raw.bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    pub fn decode(cursor: &str) -> anyhow::Result<Self> {
+        let bytes = (0..cursor.len())
+            .step_by(2)
+            .map(|i| {
+                cursor
+                    .get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+            })
+            .collect::<Option<Vec<u8>>>();
+        let raw: RawCursor = match bytes.and_then(|b| serde_json::from_slice(&b).ok()) {
+            Some(raw) => raw,
+            None => return log_and_err!("invalid cursor: {}", cursor),
+        };
+
+        let value = match raw.key {
+            NodeSortKey::VersionTime | NodeSortKey::InitialTime => {
+                DateTime::parse_from_rfc3339(&raw.value)
+                    .map(|t| CursorValue::Time(t.with_timezone(&Utc)))
+                    .ok()
+            }
+            NodeSortKey::Name | NodeSortKey::TreeOrder => Some(CursorValue::Text(raw.value)),
+            NodeSortKey::Position => raw.value.parse().map(CursorValue::Position).ok(),
+        };
+        match value {
+            Some(value) => Ok(NodeCursor {
+                sort: NodeSort {
+                    key: raw.key,
+                    desc: raw.desc,
+                },
+                value,
+                id: raw.id.into(),
+            }),
+            None => log_and_err!("invalid cursor: {}", cursor),
+        }
+    }
+}
+
+impl Serialize for NodeCursor {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(&self.encode())
+    }
+}
+
+impl<'de> Deserialize<'de> for NodeCursor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let cursor = String::deserialize(deserializer)?;
+        NodeCursor::decode(&cursor).map_err(de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodePage {
+    pub nodes: Vec<Node>,
+    /// Pass it as `NodeFetchReq::cursor` for the next page, `None` on the
+    /// last page.
+    pub next_cursor: Option<NodeCursor>,
+}
+
+/// Fetch one page, see `NodeMapper::query_page`.
+pub(crate) async fn query_page<M>(mapper: &M, req: &NodeFetchReq) -> anyhow::Result<NodePage>
+where
+    M: NodeMapper + Sync + ?Sized,
+{
+    let sort = req
+        .cursor
+        .as_ref()
+        .map(|c| c.sort)
+        .or(req.sort)
+        .unwrap_or_default();
+    let limit = req.with_limit().map(|l| l.max(0) as usize);
+
+    let mut fetch = NodeFetchReq {
+        sort: Some(sort),
+        ..req.clone()
+    };
+    // One more node tells whether there is a next page.
+    if let Some(limit) = limit {
+        fetch.set_limit(limit.saturating_add(1).min(i32::MAX as usize) as i32);
+    }
+    let mut nodes = mapper.query_nodes(&fetch).await?;
+
+    let next_cursor = match limit {
+        Some(limit) if nodes.len() > limit => {
+            nodes.truncate(limit);
+            match nodes.last() {
+                Some(node) if sort.key == NodeSortKey::TreeOrder => Some(NodeCursor {
+                    sort,
+                    value: CursorValue::Text(query_tree_key(mapper, node).await?),
+                    id: node.id.clone(),
+                }),
+                Some(node) => NodeCursor::after(sort, node),
+                None => None,
+            }
+        }
+        _ => None,
+    };
+
+    Ok(NodePage { nodes, next_cursor })
+}
+
+/// The place of `node` in the tree order as text, which the sql backends
+/// build from `node_paths` alike: the parent of the top level ancestor, then
+/// the order keys from that ancestor down to the node, zero padded.
+///
+/// `parent` looks up the node a parent id refers to.
+pub(crate) fn tree_key<'a, F>(node: &'a Node, parent: F) -> String
+where
+    F: Fn(&MagicNodeId) -> Option<&'a Node>,
+{
+    let mut positions = vec![node.position];
+    let mut top = &node.parent_id;
+    let mut seen = vec![node.id.as_str()];
+    while let Some(ancestor) = parent(top) {
+        if seen.contains(&ancestor.id.as_str()) {
+            break;
+        }
+        seen.push(ancestor.id.as_str());
+        positions.push(ancestor.position);
+        top = &ancestor.parent_id;
+    }
+
+    let mut key = format!("{}/", top.as_ref());
+    for position in positions.iter().rev() {
+        key.push_str(&format!("{:010}", position));
+    }
+    key
+}
+
+/// The tree key of `node`, read from its ancestors.
+async fn query_tree_key<M>(mapper: &M, node: &Node) -> anyhow::Result<String>
+where
+    M: NodeMapper + Sync + ?Sized,
+{
+    let ancestors: HashMap<String, Node> = mapper
+        .query_nodes(&NodeFetchReq {
+            selection: Some(vec![NodeSelection::WithHistory]),
+            filter: Some(NodeFilter::Ancestor(node.id.clone())),
+            ..Default::default()
+        })
+        .await?
+        .into_iter()
+        .map(|n| (n.id.as_str().to_owned(), n))
+        .collect();
+    Ok(tree_key(node, |parent_id| {
+        ancestors.get(parent_id.as_ref())
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+
+    use super::{CursorValue, NodeCursor, NodeSort, NodeSortKey};
+
+    #[test]
+    fn test_cursor() {
+        let cursor = NodeCursor {
+            sort: NodeSort {
+                key: NodeSortKey::VersionTime,
+                desc: true,
+            },
+            value: CursorValue::Time(Utc.timestamp_opt(1700000000, 123456789).unwrap()),
+            id: "a\"b".into(),
+        };
+        assert_eq!(NodeCursor::decode(&cursor.encode()).unwrap(), cursor);
+
+        let json = serde_json::to_string(&cursor).unwrap();
+        let back: NodeCursor = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, cursor);
+
+        assert!(NodeCursor::decode("zz").is_err());
+        assert!(NodeCursor::decode("7b7d").is_err());
+    }
+}
@@ -0,0 +1,261 @@
+//! Address nodes by their names from the root down, e.g. `/Work/Projects/chnots`.
+//!
+//! A segment is the name of a live child, with `%`, `/` and `~` percent
+//! encoded. Siblings may share a name: `Ideas~2` is the second of them in the
+//! sibling order, a bare `Ideas` the first one. An empty name always needs the
+//! suffix, as empty segments are skipped. Breadcrumbs write the suffix only when
+//! it is needed, so their paths resolve to the same node again.
+
+use chin_tools::log_and_err;
+use serde::{Deserialize, Serialize};
+
+use crate::model::node::{MagicNodeId, Node, NodeId};
+
+use super::{
+    error::MapperError,
+    node::NodeMapper,
+    nodefilter::{NodeFetchReq, NodeFilter},
+    page::{NodeSort, NodeSortKey},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodePathReq {
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeBreadcrumbReq {
+    pub id: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeCrumb {
+    pub id: NodeId,
+    pub name: String,
+    /// The name as it is written in a path.
+    pub segment: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeBreadcrumb {
+    /// From the top level node down to the node itself.
+    pub crumbs: Vec<NodeCrumb>,
+    pub path: String,
+}
+
+/// One segment of a path, the `nth` sibling named `name`, counted from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathSegment {
+    pub name: String,
+    pub nth: usize,
+}
+
+impl PathSegment {
+    pub fn encode(&self) -> String {
+        let mut segment = String::with_capacity(self.name.len());
+        for c in self.name.chars() {
+            match c {
+                '%' => segment.push_str("%25"),
+                '/' => segment.push_str("%2F"),
+                '~' => segment.push_str("%7E"),
+                _ => segment.push(c),
+            }
+        }
+        if self.nth > 1 || self.name.is_empty() {
+            segment.push_str(&format!("~{}", self.nth));
+        }
+        segment
+    }
+
+    fn decode(segment: &str) -> Option<PathSegment> {
+        let (name, nth) = match segment.split_once('~') {
+            Some((name, nth)) => (name, nth.parse().ok().filter(|nth| *nth > 0)?),
+            None => (segment, 1),
+        };
+
+        let mut bytes = Vec::with_capacity(name.len());
+        let mut rest = name.as_bytes();
+        while let Some((&b, tail)) = rest.split_first() {
+            if b == b'%' {
+                let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+                rest = &tail[2..];
+            } else {
+                bytes.push(b);
+                rest = tail;
+            }
+        }
+
+        Some(PathSegment {
+            name: String::from_utf8(bytes).ok()?,
+            nth,
+        })
+    }
+}
+
+/// Split a path into its segments, a leading or trailing `/` is optional.
+pub fn parse_path(path: &str) -> anyhow::Result<Vec<PathSegment>> {
+    let mut segments = vec![];
+    for segment in path.split('/').filter(|e| !e.is_empty()) {
+        match PathSegment::decode(segment) {
+            Some(segment) => segments.push(segment),
+            None => {
+                return Err(MapperError::InvalidRequest(format!(
+                    "{:?} is no valid segment of path {:?}",
+                    segment, path
+                ))
+                .into())
+            }
+        }
+    }
+    if segments.is_empty() {
+        return Err(MapperError::InvalidRequest(format!("path {:?} names no node", path)).into());
+    }
+    Ok(segments)
+}
+
+pub fn format_path<'a>(segments: impl IntoIterator<Item = &'a PathSegment>) -> String {
+    segments
+        .into_iter()
+        .map(|e| format!("/{}", e.encode()))
+        .collect()
+}
+
+/// Walk the names of `path` from the root, see `NodeMapper::resolve_path`.
+pub(crate) async fn resolve_path<M>(mapper: &M, path: &str) -> anyhow::Result<NodeId>
+where
+    M: NodeMapper + Sync + ?Sized,
+{
+    let segments = parse_path(path)?;
+
+    let mut parent_id = MagicNodeId::Empty;
+    for (index, segment) in segments.iter().enumerate() {
+        let children = live_children(mapper, &parent_id).await?;
+        match children
+            .into_iter()
+            .filter(|e| e.name == segment.name)
+            .nth(segment.nth - 1)
+        {
+            Some(child) => parent_id = child.id.into(),
+            None => return Err(MapperError::NoSuchPath(format_path(&segments[..=index])).into()),
+        }
+    }
+    Ok(parent_id.into())
+}
+
+/// The names from the top level down to `id`, see `NodeMapper::query_breadcrumb`.
+pub(crate) async fn query_breadcrumb<M>(mapper: &M, id: &NodeId) -> anyhow::Result<NodeBreadcrumb>
+where
+    M: NodeMapper + Sync + ?Sized,
+{
+    let ancestors = mapper.find_ancestor_ids(id).await?;
+
+    // From the node up, each with its parent.
+    let mut chain: Vec<(NodeId, MagicNodeId)> = vec![];
+    let mut cursor = id.clone();
+    loop {
+        let parent_id = match ancestors.get(&cursor) {
+            Some(parent_id) => parent_id.clone(),
+            None => return log_and_err!("there are no node with id: {:?}", cursor),
+        };
+        if chain.len() > ancestors.len() {
+            return log_and_err!("the ancestors of {:?} form a cycle", id);
+        }
+        chain.push((cursor, parent_id.clone()));
+        match parent_id {
+            MagicNodeId::Id(parent_id) => cursor = parent_id,
+            MagicNodeId::Empty => break,
+            MagicNodeId::RecycleBin => return Err(MapperError::Deleted(id.clone()).into()),
+            MagicNodeId::Never => return log_and_err!("node {:?} is not linked into the tree", id),
+        }
+    }
+
+    let mut crumbs = vec![];
+    for (id, parent_id) in chain.into_iter().rev() {
+        let children = live_children(mapper, &parent_id).await?;
+        let node = match children.iter().find(|e| e.id == id) {
+            Some(node) => node,
+            None => return Err(MapperError::Deleted(id).into()),
+        };
+        let nth = children
+            .iter()
+            .take_while(|e| e.id != id)
+            .filter(|e| e.name == node.name)
+            .count()
+            + 1;
+        let segment = PathSegment {
+            name: node.name.clone(),
+            nth,
+        };
+        crumbs.push(NodeCrumb {
+            name: node.name.clone(),
+            segment: segment.encode(),
+            id,
+        });
+    }
+
+    Ok(NodeBreadcrumb {
+        path: crumbs.iter().map(|e| format!("/{}", e.segment)).collect(),
+        crumbs,
+    })
+}
+
+/// The live children of `parent_id` in their sibling order.
+async fn live_children<M>(mapper: &M, parent_id: &MagicNodeId) -> anyhow::Result<Vec<Node>>
+where
+    M: NodeMapper + Sync + ?Sized,
+{
+    mapper
+        .query_nodes(&NodeFetchReq {
+            selection: None,
+            filter: Some(NodeFilter::Children(parent_id.clone().into())),
+            sort: Some(NodeSort {
+                key: NodeSortKey::Position,
+                desc: false,
+            }),
+            ..Default::default()
+        })
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_path, parse_path, PathSegment};
+
+    fn segment(name: &str, nth: usize) -> PathSegment {
+        PathSegment {
+            name: name.to_owned(),
+            nth,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse_path("/Work/Projects~2/").unwrap(),
+            vec![segment("Work", 1), segment("Projects", 2)]
+        );
+        assert_eq!(
+            parse_path("a%2Fb%7E1%25//~3").unwrap(),
+            vec![segment("a/b~1%", 1), segment("", 3)]
+        );
+        assert_eq!(parse_path("Ideas~1").unwrap(), vec![segment("Ideas", 1)]);
+
+        for path in ["", "/", "a~0", "a~x", "a~1~2", "a%2", "a%zz", "%ff"] {
+            assert!(parse_path(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_format() {
+        let segments = vec![
+            segment("Work", 1),
+            segment("a/b~c%", 2),
+            segment("", 1),
+            segment("日记", 1),
+        ];
+        let path = format_path(&segments);
+        assert_eq!(path, "/Work/a%2Fb%7Ec%25~2/~1/日记");
+        assert_eq!(parse_path(&path).unwrap(), segments);
+    }
+}
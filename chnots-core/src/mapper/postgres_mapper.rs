@@ -1,36 +1,39 @@
-use std::{
-    collections::{HashMap, HashSet},
-    str::FromStr,
-};
+use std::{collections::HashMap, str::FromStr};
 
 use async_trait::async_trait;
 use bytes::BytesMut;
 use chin_tools::log_and_err;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use deadpool_postgres::{Client, GenericClient, Pool};
 use num_traits::ToPrimitive;
 use postgres_types::{to_sql_checked, ToSql};
 use serde::Deserialize;
 use tokio_postgres::Row;
-use tracing::{error, info};
+use tracing::info;
 
 use crate::{
     constants,
-    mapper::node::NodeInsertResult,
     model::{
         asset::Asset,
-        node::{ContentParsedInfo, MagicNodeId, Node, NodeId, NodeType},
+        node::{ContentParsedInfo, MagicNodeId, Node, NodeId, NodeRef, NodeType},
+        tag::TagStat,
+        todo::TodoEvent,
     },
 };
 
 use super::{
     asset::AssetMapper,
+    history::HistoryConfig,
+    migration::Migration,
     node::{
-        NodeDeleteReq, NodeMapper, NodeMoveReq, NodeMoveRsp, NodeRelation, NodeRenameReq,
-        NodeUpdateReadonlyReq,
+        NodeLink, NodeMapper, NodeRenameReq, NodeUpdateReadonlyReq, NodeVersion, NodeVersionReq,
     },
-    nodefilter::NodeFetchReq,
+    nodefilter::{sql_ancestors, sql_descendants, NodeFetchReq, SqlDialect, SqlParam, SqlQuery},
+    recycle::{RecycleMapper, RecycleOrigin},
+    search::{tsquery_text, SearchMapper, SearchReq, SearchRow},
+    tag::TagMapper,
     todo::{TodoCreateReq, TodoMapper},
+    tx::NodeTx,
     Mapper,
 };
 
@@ -58,6 +61,7 @@ impl Into<deadpool_postgres::Config> for PostgresConfig {
 pub struct PostgresMapper {
     pub pool: Pool,
     pub node_fields: Option<Vec<String>>,
+    pub snapshot_distance: usize,
 }
 
 impl PostgresMapper {
@@ -68,6 +72,7 @@ impl PostgresMapper {
         Ok(PostgresMapper {
             pool,
             node_fields: None,
+            snapshot_distance: HistoryConfig::default().snapshot_distance,
         })
     }
 
@@ -75,34 +80,6 @@ impl PostgresMapper {
         self.pool.get().await.map_err(anyhow::Error::new)
     }
 
-    async fn check_if_table_not_exists(&self, table_name: &str) -> anyhow::Result<bool> {
-        let client = self.get_client().await?;
-        client
-            .query(
-                "SELECT 1 FROM pg_tables WHERE  schemaname = 'public' AND tablename = $1",
-                &[&table_name.to_string()],
-            )
-            .await
-            .map(|e| e.is_empty())
-            .map_err(anyhow::Error::new)
-    }
-
-    async fn create_table(&self, table_name: &str, create_sql: &str) -> anyhow::Result<()> {
-        info!("begin to create table `{}'", table_name);
-        if self.check_if_table_not_exists(table_name).await? {
-            info!("table `{}' is not existed.", table_name);
-            let client = self.get_client().await?;
-            client
-                .execute(create_sql, &[])
-                .await
-                .map(|_| ())
-                .map_err(anyhow::Error::new)?;
-        } else {
-            info!("table {} is already created.", table_name);
-        }
-        Ok(())
-    }
-
     fn map_row_node(row: &Row) -> Node {
         let id = row.get("id");
         Node {
@@ -113,6 +90,7 @@ impl PostgresMapper {
             parsed_info: ContentParsedInfo::default(),
             parent_id: row.get("parent_id"),
             prev_sliding_id: row.get("prev_sliding_id"),
+            position: row.get("position"),
             delete_time: row.get("delete_time"),
             version_time: row.get("version_time"),
             initial_time: row.get("initial_time"),
@@ -121,6 +99,16 @@ impl PostgresMapper {
             todo_status: super::todo::to_todo_status(row.get("todo_status")),
         }
     }
+
+    fn map_row_link(row: &Row) -> NodeLink {
+        NodeLink {
+            source_id: row.get("source_id"),
+            target_id: row.get("target_id"),
+            name: row.get("name"),
+            text: row.get("text"),
+            snippet: row.get("snippet"),
+        }
+    }
 }
 
 #[async_trait]
@@ -169,426 +157,1007 @@ impl AssetMapper for PostgresMapper {
 
 #[async_trait]
 impl NodeMapper for PostgresMapper {
-    async fn insert_node_only(&self, node: &Node) -> anyhow::Result<NodeInsertResult> {
-        let stmt = self.pool.get().await?;
-
-        let content: Result<String, tokio_postgres::Error> = stmt
-            .query_one("select content from nodes where id = $1", &[&node.id])
-            .await
-            .map(|row| row.get("content"));
-
-        let changed = match content {
-            Ok(content) => distance::levenshtein(&content, &node.content) > 8,
-            Err(_) => true,
-        };
+    async fn begin(&self) -> anyhow::Result<Box<dyn NodeTx>> {
+        let client = self.get_client().await?;
+        client.batch_execute("BEGIN").await?;
+        Ok(Box::new(PostgresTx {
+            client: Some(client),
+            node_fields: self.node_fields.clone().unwrap_or_default(),
+            snapshot_distance: self.snapshot_distance,
+        }))
+    }
 
-        if !changed {
-            stmt.execute(
-                "update nodes set content = $1, version_time = $2 where id = $3",
-                &[&node.content, &node.version_time, &node.id],
+    async fn update_node_name(&self, req: &NodeRenameReq) -> anyhow::Result<u64> {
+        let mut stmt = self.pool.get().await?;
+        let tx = stmt.transaction().await?;
+        let count = tx
+            .execute(
+                "update nodes set name = $1 where id = $2",
+                &[&req.name, &req.id],
             )
             .await?;
-        } else {
-            self._move_node_to_history(&node.id).await?;
-            info!("begin to really insert");
-
-            stmt
-                .execute(
-                    "insert into nodes(id, name, content, node_type, domain, parent_id, prev_sliding_id, readonly, version_time, initial_time, delete_time) values ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)",
-                    &[
-                        &node.id,
-                        &node.name,
-                        &node.content,
-                        &node.node_type.as_ref(),
-                        &node.domain,
-                        &node.parent_id,
-                        &node.prev_sliding_id,
-                        &node.readonly,
-                        &node.version_time,
-                        &node.initial_time,
-                        &node.delete_time,
-                    ]
-                ).await
-                .map_err(|e| { anyhow::Error::new(e) })
-                .map(|_| ())?;
-        }
+        tx.execute(
+            "update node_search set name = $1 where node_id = $2",
+            &[&req.name, &req.id],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(count)
+    }
 
-        Ok(NodeInsertResult::ParsedInfo(ContentParsedInfo::default()))
+    async fn update_node_readonly(&self, req: &NodeUpdateReadonlyReq) -> anyhow::Result<u64> {
+        let stmt = self.pool.get().await?;
+        stmt.execute(
+            "update nodes set readonly = $1 where id = $2",
+            &[&req.readonly, &req.id],
+        )
+        .await
+        .map_err(|e| anyhow::Error::new(e))
     }
 
-    async fn delete_node(&self, req: &NodeDeleteReq) -> anyhow::Result<()> {
+    async fn query_nodes(&self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
         let stmt = self.pool.get().await?;
 
-        let descendants = self.find_descendant_ids(&req.id).await?;
+        let query = node_filter.to_sql(self.node_fields.as_ref().unwrap(), SqlDialect::Postgres);
+        query_nodes_with(&stmt, &query).await
+    }
 
-        let mut all_ids: HashSet<&NodeId> = descendants.keys().collect();
-        descendants.iter().for_each(|(_, v)| {
-            if let MagicNodeId::Id(key) = v {
-                all_ids.insert(key);
-            }
-        });
+    async fn query_child_counts(&self, ids: &[NodeId]) -> anyhow::Result<HashMap<NodeId, u64>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select parent_id, count(*) as count from nodes
+where parent_id = any($1) and delete_time is null group by parent_id",
+                &[&ids],
+            )
+            .await?
+            .iter()
+            .map(|row| (row.get("parent_id"), row.get::<_, i64>("count") as u64))
+            .collect())
+    }
 
-        all_ids.insert(&req.id);
+    async fn query_backlinks(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select l.source_id, l.target_id, n.name, l.text, l.snippet from node_links l
+join nodes n on n.id = l.source_id
+where l.target_id = $1 and n.delete_time is null
+order by n.version_time desc",
+                &[&id],
+            )
+            .await?
+            .iter()
+            .map(PostgresMapper::map_row_link)
+            .collect())
+    }
 
-        let phs: String = all_ids
+    async fn query_outgoing_links(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select l.source_id, l.target_id, n.name, l.text, l.snippet from node_links l
+left join nodes n on n.id = l.target_id and n.delete_time is null
+where l.source_id = $1
+order by l.position",
+                &[&id],
+            )
+            .await?
             .iter()
-            .enumerate()
-            .map(|(i, _)| format!("${}", i + 1))
-            .collect::<Vec<String>>()
-            .join(",");
+            .map(PostgresMapper::map_row_link)
+            .collect())
+    }
 
-        let params = all_ids
-            .into_iter()
-            .map(|x| &*x as &(dyn ToSql + Sync))
-            .collect::<Vec<&(dyn ToSql + Sync)>>();
-        let params_slice = params.as_slice();
+    async fn query_versions(&self, id: &NodeId) -> anyhow::Result<Vec<NodeVersion>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select version_time, name, length(content)::bigint as size, true as current from nodes where id = $1
+union all
+select version_time, name, length(content)::bigint as size, false as current from nodes_history where id = $1
+order by current desc, version_time desc",
+                &[&id],
+            )
+            .await?
+            .iter()
+            .map(|row| NodeVersion {
+                version_time: row.get("version_time"),
+                name: row.get("name"),
+                size: row.get("size"),
+                current: row.get("current"),
+            })
+            .collect())
+    }
 
-        info!("delete flags: {:?}, {}", params_slice, phs);
+    async fn query_version(&self, req: &NodeVersionReq) -> anyhow::Result<Option<Node>> {
+        let stmt = self.pool.get().await?;
+        query_version_with(&stmt, &req.id, &req.version_time).await
+    }
 
-        stmt.execute(
-            &format!(
-                "update nodes set delete_time = CURRENT_TIMESTAMP where id in ({})",
-                phs
-            ),
-            params_slice,
-        )
-        .await
-        .map(|_| ())
-        .map_err(|e| anyhow::Error::new(e))?;
+    async fn find_descendant_ids(
+        &self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        let stmt = self.pool.get().await?;
 
-        self.move_nodes(&NodeMoveReq {
-            id: req.id.clone(),
-            parent_id: MagicNodeId::RecycleBin,
-            prev_sliding_id: MagicNodeId::Empty,
-        })
-        .await
-        .map(|_| ())
+        let map = stmt
+            .query(sql_descendants("$1").as_str(), &[&id])
+            .await?
+            .iter()
+            .map(|row| (row.get("id"), row.get("parent_id")))
+            .collect();
+        Ok(map)
     }
 
-    async fn update_node_name(&self, req: &NodeRenameReq) -> anyhow::Result<u64> {
+    async fn find_ancestor_ids(&self, id: &NodeId) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
         let stmt = self.pool.get().await?;
-        stmt.execute(
-            "update nodes set name = $1 where id = $2",
-            &[&req.name, &req.id],
-        )
-        .await
-        .map_err(|e| anyhow::Error::new(e))
+
+        let map = stmt
+            .query(sql_ancestors("$1").as_str(), &[&id])
+            .await?
+            .iter()
+            .map(|row| (row.get("id"), row.get("parent_id")))
+            .collect();
+        Ok(map)
     }
+}
 
-    async fn update_node_readonly(&self, req: &NodeUpdateReadonlyReq) -> anyhow::Result<u64> {
-        let stmt = self.pool.get().await?;
-        stmt.execute(
-            "update nodes set readonly = $1 where id = $2",
-            &[&req.readonly, &req.id],
+async fn query_version_with(
+    client: &Client,
+    id: &NodeId,
+    version_time: &DateTime<Utc>,
+) -> anyhow::Result<Option<Node>> {
+    let row = client
+        .query_opt(
+            "select id, name, content, node_type, domain, todo_status, delete_time, readonly, version_time, initial_time, parent_id, prev_sliding_id, position, 0 as history
+from nodes where id = $1 and version_time = $2
+union all
+select h.id, h.name, h.content, h.node_type, h.domain, h.todo_status, h.delete_time, h.readonly, h.version_time, h.initial_time, n.parent_id, n.prev_sliding_id, n.position, 1 as history
+from nodes_history h join nodes n on n.id = h.id where h.id = $1 and h.version_time = $2
+order by history limit 1",
+            &[&id, &version_time],
         )
-        .await
-        .map_err(|e| anyhow::Error::new(e))
+        .await?;
+    Ok(row.map(|row| PostgresMapper::map_row_node(&row)))
+}
+
+async fn query_nodes_with(client: &Client, query: &SqlQuery) -> anyhow::Result<Vec<Node>> {
+    let params: Vec<&(dyn ToSql + Sync)> = query
+        .params
+        .iter()
+        .map(|e| e as &(dyn ToSql + Sync))
+        .collect();
+    let nodes = client
+        .query(&query.sql, &params)
+        .await?
+        .iter()
+        .map(PostgresMapper::map_row_node)
+        .collect();
+    Ok(nodes)
+}
+
+/// Hang the subtree of `id` under the ancestry of `parent_id` in `node_paths`,
+/// an unlinked subtree has no ancestors at all.
+async fn move_paths(client: &Client, id: &NodeId, parent_id: &MagicNodeId) -> anyhow::Result<()> {
+    client
+        .execute(
+            "delete from node_paths where ancestor_id in (select ancestor_id from node_paths where node_id = $1)
+and (node_id = $1 or node_id in (select node_id from node_paths where ancestor_id = $1))",
+            &[&id],
+        )
+        .await?;
+    if matches!(parent_id, MagicNodeId::Never) {
+        return Ok(());
     }
+    client
+        .execute(
+            "insert into node_paths(ancestor_id, node_id, depth)
+select a.ancestor_id, s.node_id, a.depth + s.depth
+from (select cast($2 as varchar(40)) as ancestor_id, cast(1 as bigint) as depth
+union all select ancestor_id, depth + 1 from node_paths where node_id = $2) a,
+(select cast($1 as varchar(40)) as node_id, cast(0 as bigint) as depth
+union all select node_id, depth from node_paths where ancestor_id = $1) s",
+            &[&id, &parent_id],
+        )
+        .await?;
+    Ok(())
+}
 
-    async fn query_nodes(&self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
-        let stmt = self.pool.get().await?;
+/// A `NodeTx` holding one pooled connection.
+///
+/// An unfinished transaction takes its connection out of the pool when
+/// dropped, closing the connection makes the server roll it back.
+struct PostgresTx {
+    client: Option<Client>,
+    node_fields: Vec<String>,
+    snapshot_distance: usize,
+}
 
-        let sql = node_filter.to_sql(self.node_fields.as_ref().unwrap());
-        let nodes = stmt
-            .query(&sql, &[])
-            .await
-            .map(|rows| rows.iter().map(|row| Self::map_row_node(&row)).collect())?;
+impl PostgresTx {
+    fn client(&self) -> &Client {
+        self.client
+            .as_ref()
+            .expect("transaction is already finished")
+    }
+}
 
-        Ok(nodes)
+impl Drop for PostgresTx {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            drop(deadpool_postgres::Object::take(client));
+        }
     }
+}
 
-    async fn move_nodes(&self, node_move_req: &NodeMoveReq) -> anyhow::Result<NodeMoveRsp> {
-        let node_id = &node_move_req.id;
-        let parent_id = &node_move_req.parent_id;
-        let prev_slibing = &node_move_req.prev_sliding_id;
+#[async_trait]
+impl NodeTx for PostgresTx {
+    fn snapshot_distance(&self) -> usize {
+        self.snapshot_distance
+    }
 
-        //info!("move {:?} to {:?}.{:?}|^", node_id, parent_id, prev_slibing);
+    async fn lock_parents(&mut self, parent_ids: &[&MagicNodeId]) -> anyhow::Result<()> {
+        // Always lock in the same order, so two movers never wait for each other.
+        let mut keys: Vec<&str> = parent_ids.iter().map(|e| e.as_ref()).collect();
+        keys.sort();
+        keys.dedup();
 
-        let old = self._delete_relation(node_id, false).await?;
+        for key in keys {
+            self.client()
+                .execute("select pg_advisory_xact_lock(hashtext($1))", &[&key])
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn lock_tree(&mut self) -> anyhow::Result<()> {
+        // Plain reads go on, every writer waits until this transaction ends.
+        self.client()
+            .batch_execute("LOCK TABLE nodes IN EXCLUSIVE MODE")
+            .await?;
+        Ok(())
+    }
 
-        /*        info!(
-            "delete old {:?} to {:?}.{:?}|^",
-            node_id, parent_id, prev_slibing
-        ); */
+    async fn query_nodes(&mut self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
+        let query = node_filter.to_sql(&self.node_fields, SqlDialect::Postgres);
+        query_nodes_with(self.client(), &query).await
+    }
 
-        let new = self
-            ._insert_relation(node_id, parent_id, prev_slibing)
+    async fn query_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        let row = self
+            .client()
+            .query_opt("select * from nodes where id = $1", &[&id])
             .await?;
+        Ok(row.map(|row| PostgresMapper::map_row_node(&row)))
+    }
 
-        Ok(NodeMoveRsp { new, old })
+    async fn lock_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        let row = self
+            .client()
+            .query_opt("select * from nodes where id = $1 for update", &[&id])
+            .await?;
+        Ok(row.map(|row| PostgresMapper::map_row_node(&row)))
     }
 
-    async fn _delete_relation(
-        &self,
-        node_id: &NodeId,
-        to_history: bool,
-    ) -> anyhow::Result<NodeRelation> {
-        let stmt = self.pool.get().await?;
+    async fn query_next_id(
+        &mut self,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<MagicNodeId> {
+        let row = self
+            .client()
+            .query_opt(
+                "select id from nodes where parent_id = $1 and prev_sliding_id = $2",
+                &[&parent_id, &prev_id],
+            )
+            .await?;
+        Ok(row
+            .map(|row| row.get::<_, NodeId>("id").into())
+            .unwrap_or_default())
+    }
 
-        let rows = stmt
-            .query(
-                "select id, prev_sliding_id, parent_id from nodes where (id = $1 or prev_sliding_id = $2) and parent_id in (select parent_id from nodes where id = $3)", & [&node_id, &node_id, &node_id],
+    async fn update_relation(
+        &mut self,
+        id: &NodeId,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<u64> {
+        let client = self.client();
+        let row = client
+            .query_opt(
+                "select coalesce((select p.position + 1 from nodes p where p.id = $2), 0) as position
+from nodes n where n.id = $1",
+                &[&id, &prev_id],
             )
             .await?;
+        let position: i64 = match row {
+            Some(row) => row.get("position"),
+            None => return Ok(0),
+        };
 
-        let mut parent_id = Default::default();
-        let mut prev_id = Default::default();
-        let mut next_id: MagicNodeId = Default::default();
-
-        for row in rows {
-            let id: NodeId = row.get("id");
-            let prev: MagicNodeId = row.get("prev_sliding_id");
-            let parent: MagicNodeId = row.get("parent_id");
-            if id.as_str() == node_id.as_str() {
-                parent_id = parent;
-                prev_id = prev;
-            } else if prev.as_ref() == node_id.as_str() {
-                next_id = id.into();
-            }
-        }
+        client
+            .execute(
+                "update nodes set position = position + 1 where parent_id = $1 and position >= $2 and id <> $3",
+                &[&parent_id, &position, &id],
+            )
+            .await?;
+        let count = client
+            .execute(
+                "update nodes set prev_sliding_id = $1, parent_id = $2, position = $3 where id = $4",
+                &[&prev_id, &parent_id, &position, &id],
+            )
+            .await?;
+        Ok(count)
+    }
 
-        if to_history {
-            self._move_node_to_history(node_id).await?;
-        }
+    async fn move_paths(&mut self, id: &NodeId, parent_id: &MagicNodeId) -> anyhow::Result<()> {
+        move_paths(self.client(), id, parent_id).await
+    }
+
+    async fn update_prev_id(&mut self, id: &NodeId, prev_id: &MagicNodeId) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "update nodes set prev_sliding_id = $1 where id = $2",
+                &[&prev_id, &id],
+            )
+            .await?)
+    }
 
-        if let MagicNodeId::Id(_next_id) = &next_id {
-            if stmt
+    async fn insert_node_row(&mut self, node: &Node) -> anyhow::Result<u64> {
+        let count = self
+            .client()
+            .execute(
+                "insert into nodes(id, name, content, node_type, domain, parent_id, prev_sliding_id, readonly, version_time, initial_time, delete_time, position) values ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12)",
+                &[
+                    &node.id,
+                    &node.name,
+                    &node.content,
+                    &node.node_type.as_ref(),
+                    &node.domain,
+                    &node.parent_id,
+                    &node.prev_sliding_id,
+                    &node.readonly,
+                    &node.version_time,
+                    &node.initial_time,
+                    &node.delete_time,
+                    &node.position,
+                ],
+            )
+            .await?;
+
+        if !matches!(node.parent_id, MagicNodeId::Never) {
+            self.client()
                 .execute(
-                    "update nodes set prev_sliding_id = $1 where id = $2",
-                    &[&prev_id, &_next_id],
+                    "insert into node_paths(ancestor_id, node_id, depth)
+select cast($1 as varchar(40)), cast($2 as varchar(40)), cast(1 as bigint)
+union all select ancestor_id, $2, depth + 1 from node_paths where node_id = $1",
+                    &[&node.parent_id, &node.id],
                 )
-                .await?
-                <= 0
-            {
-                return log_and_err!("_delete_relation, there are no node with id: {:?}", next_id);
-            }
+                .await?;
         }
+        Ok(count)
+    }
 
-        if stmt
+    async fn renumber_children(&mut self, parent_id: &MagicNodeId) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
             .execute(
-                "update nodes set prev_sliding_id = $1, parent_id = $2 where id = $3",
-                &[&MagicNodeId::Never, &MagicNodeId::Never, &node_id],
+                "with recursive chain(id, position) as (
+select id, cast(0 as bigint) from nodes where parent_id = $1 and prev_sliding_id = $2
+union all
+select n.id, c.position + 1 from chain c join nodes n on n.parent_id = $1 and n.prev_sliding_id = c.id
+)
+update nodes set position = c.position from chain c where nodes.id = c.id and nodes.position <> c.position",
+                &[&parent_id, &MagicNodeId::Empty],
             )
-            .await?
-            <= 0
-        {
-            return log_and_err!("_delete_relation, there are no node with id: {:?}", next_id);
-        }
+            .await?)
+    }
 
-        Ok(NodeRelation {
-            parent_id,
-            prev_id,
-            next_id,
-        })
+    async fn rebuild_tree(&mut self) -> anyhow::Result<u64> {
+        let client = self.client();
+        client.execute("delete from node_paths", &[]).await?;
+        // A parent cycle ends the walk down when it comes back to the ancestor.
+        client
+            .execute(
+                "insert into node_paths(ancestor_id, node_id, depth)
+with recursive paths(ancestor_id, node_id, depth) as (
+select parent_id, id, cast(1 as bigint) from nodes where parent_id <> $1
+union all
+select p.ancestor_id, n.id, p.depth + 1 from paths p join nodes n on n.parent_id = p.node_id where n.id <> p.ancestor_id
+)
+select ancestor_id, node_id, depth from paths",
+                &[&MagicNodeId::Never],
+            )
+            .await?;
+        client
+            .execute(
+                "with recursive chain(id, parent_id, position) as (
+select id, parent_id, cast(0 as bigint) from nodes where prev_sliding_id = $1 and parent_id <> $2
+union all
+select n.id, n.parent_id, c.position + 1 from chain c join nodes n on n.parent_id = c.parent_id and n.prev_sliding_id = c.id
+)
+update nodes set position = c.position from chain c where nodes.id = c.id and nodes.position <> c.position",
+                &[&MagicNodeId::Empty, &MagicNodeId::Never],
+            )
+            .await?;
+
+        let row = client
+            .query_one("select count(*) as count from nodes", &[])
+            .await?;
+        Ok(row.get::<_, i64>("count") as u64)
     }
 
-    async fn _insert_relation(
-        &self,
-        node_id: &NodeId,
-        new_parent_id: &MagicNodeId,
-        new_prev_id: &MagicNodeId,
-    ) -> anyhow::Result<NodeRelation> {
-        let stmt = self.pool.get().await?;
-        error!("n;ew relation: {:?}/{:?}", new_parent_id, new_prev_id);
+    async fn update_node_row(&mut self, node: &Node) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "update nodes set name = $1, content = $2, node_type = $3, domain = $4, readonly = $5, version_time = $6 where id = $7",
+                &[
+                    &node.name,
+                    &node.content,
+                    &node.node_type.as_ref(),
+                    &node.domain,
+                    &node.readonly,
+                    &node.version_time,
+                    &node.id,
+                ],
+            )
+            .await?)
+    }
 
-        let rows = stmt
-            .query(
-                "select id from nodes where parent_id = $1 and prev_sliding_id = $2",
-                &[&new_parent_id, &new_prev_id],
+    async fn update_todo_status(
+        &mut self,
+        id: &NodeId,
+        todo_status: &Option<TodoEvent>,
+    ) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "update nodes set todo_status = $1 where id = $2",
+                &[&todo_status.as_ref().map(|e| e.as_ref()), &id],
             )
-            .await?;
+            .await?)
+    }
 
-        let mut next_id = MagicNodeId::default();
+    async fn copy_node_to_history(&mut self, id: &NodeId) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "INSERT INTO nodes_history(id, name, content, node_type, domain, todo_status, delete_time, readonly, version_time, initial_time)
+SELECT id, name, content, node_type, domain, todo_status, delete_time, readonly, version_time, initial_time FROM nodes where id = $1",
+                &[&id],
+            )
+            .await?)
+    }
 
-        for row in rows {
-            let id: NodeId = row.get("id");
+    async fn query_version(
+        &mut self,
+        id: &NodeId,
+        version_time: &DateTime<Utc>,
+    ) -> anyhow::Result<Option<Node>> {
+        query_version_with(self.client(), id, version_time).await
+    }
 
-            next_id = id.into();
-        }
+    async fn query_history_times(&mut self) -> anyhow::Result<Vec<(NodeId, DateTime<Utc>)>> {
+        Ok(self
+            .client()
+            .query("select id, version_time from nodes_history", &[])
+            .await?
+            .iter()
+            .map(|row| (row.get("id"), row.get("version_time")))
+            .collect())
+    }
 
-        if stmt
+    async fn delete_history(
+        &mut self,
+        id: &NodeId,
+        version_times: &[DateTime<Utc>],
+    ) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "delete from nodes_history where id = $1 and version_time = any($2)",
+                &[&id, &version_times],
+            )
+            .await?)
+    }
+
+    async fn mark_deleted(&mut self, ids: &[NodeId], time: &DateTime<Utc>) -> anyhow::Result<u64> {
+        info!("delete flags: {:?}", ids);
+        Ok(self
+            .client()
+            .execute(
+                "update nodes set delete_time = $1 where id = any($2)",
+                &[&time, &ids],
+            )
+            .await?)
+    }
+
+    async fn clear_deleted(&mut self, ids: &[NodeId]) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "update nodes set delete_time = null where id = any($1)",
+                &[&ids],
+            )
+            .await?)
+    }
+
+    async fn replace_recycle_origin(
+        &mut self,
+        id: &NodeId,
+        origin: &RecycleOrigin,
+    ) -> anyhow::Result<()> {
+        self.client()
             .execute(
-                "update nodes set prev_sliding_id = $1, parent_id = $2 where id = $3",
-                &[&new_prev_id, &new_parent_id, &node_id],
+                "insert into recycle_bin(node_id, parent_id, prev_sliding_id) values ($1, $2, $3)
+on conflict (node_id) do update set parent_id = excluded.parent_id, prev_sliding_id = excluded.prev_sliding_id",
+                &[&id, &origin.parent_id, &origin.prev_sliding_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn query_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<Option<RecycleOrigin>> {
+        Ok(self
+            .client()
+            .query_opt(
+                "select parent_id, prev_sliding_id from recycle_bin where node_id = $1",
+                &[&id],
             )
             .await?
-            <= 0
-        {
-            return log_and_err!(", there are no node with id: {:?}", node_id);
-        }
+            .map(|row| RecycleOrigin {
+                parent_id: row.get("parent_id"),
+                prev_sliding_id: row.get("prev_sliding_id"),
+            }))
+    }
 
-        if let MagicNodeId::Id(next) = &next_id {
-            if stmt
-                .execute(
-                    "update nodes set prev_sliding_id = $1 where id = $2",
-                    &[&node_id, &next],
-                )
-                .await?
-                <= 0
-            {
-                return log_and_err!("_delete_relation there are no node with id: {:?}", next);
-            }
+    async fn delete_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute("delete from recycle_bin where node_id = $1", &[&id])
+            .await?)
+    }
+
+    async fn purge_node_rows(&mut self, ids: &[NodeId]) -> anyhow::Result<u64> {
+        for sql in [
+            "delete from nodes_history where id = any($1)",
+            "delete from tags where node_id = any($1)",
+            "delete from node_links where source_id = any($1)",
+            "delete from node_search where node_id = any($1)",
+            "delete from todos where node_id = any($1)",
+            "delete from recycle_bin where node_id = any($1)",
+            "delete from node_paths where node_id = any($1)",
+        ] {
+            self.client().execute(sql, &[&ids]).await?;
         }
+        Ok(self
+            .client()
+            .execute("delete from nodes where id = any($1)", &[&ids])
+            .await?)
+    }
 
-        Ok(NodeRelation {
-            parent_id: new_parent_id.clone(),
-            prev_id: new_prev_id.clone(),
-            next_id,
-        })
+    async fn query_contents(&mut self, ids: &[NodeId]) -> anyhow::Result<Vec<String>> {
+        Ok(self
+            .client()
+            .query(
+                "select content from nodes where id = any($1)
+union all select content from nodes_history where id = any($1)",
+                &[&ids],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("content"))
+            .collect())
     }
 
-    async fn _move_node_to_history(&self, node_id: &NodeId) -> anyhow::Result<u64> {
-        let stmt = self.pool.get().await?;
+    async fn is_asset_referenced(&mut self, asset_id: &str) -> anyhow::Result<bool> {
+        let url = format!("/api/download/{}", asset_id);
+        Ok(self
+            .client()
+            .query_one(
+                "select exists(select 1 from nodes where strpos(content, $1) > 0)
+or exists(select 1 from nodes_history where strpos(content, $1) > 0)",
+                &[&url],
+            )
+            .await?
+            .get(0))
+    }
 
-        Ok(stmt.execute(
-            "WITH moved_rows AS (
-DELETE FROM nodes a
-where id = $1
-RETURNING a.*
-)
-INSERT INTO nodes_history 
-SELECT id, name, content, node_type, domain, delete_time, version_time, initial_time FROM moved_rows;",
-            &[&node_id]
-        ).await?)
+    async fn delete_asset_row(&mut self, asset_id: &str) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute("delete from assets where id = $1", &[&asset_id])
+            .await?)
+    }
+
+    async fn copy_asset_row(&mut self, asset_id: &str, new_id: &str) -> anyhow::Result<u64> {
+        Ok(self
+            .client()
+            .execute(
+                "insert into assets(id, domain, ori_file_name, content_type, create_time) select $2, domain, ori_file_name, content_type, $3 from assets where id = $1",
+                &[&asset_id, &new_id, &Utc::now()],
+            )
+            .await?)
     }
 
     async fn find_descendant_ids(
-        &self,
+        &mut self,
         id: &NodeId,
     ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
-        let stmt = self.pool.get().await?;
+        Ok(self
+            .client()
+            .query(sql_descendants("$1").as_str(), &[&id])
+            .await?
+            .iter()
+            .map(|row| (row.get("id"), row.get("parent_id")))
+            .collect())
+    }
 
-        let map = stmt
-            .query(
-                "with recursive children(id, parent_id) as (
-select n.id, n.parent_id from nodes n where n.parent_id = $1
-union 
-select n.id, n.parent_id from nodes n, children c where n.parent_id = c.id
-)
-select * from children;",
-                &[&id],
-            )
+    async fn find_ancestor_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        Ok(self
+            .client()
+            .query(sql_ancestors("$1").as_str(), &[&id])
             .await?
             .iter()
             .map(|row| (row.get("id"), row.get("parent_id")))
-            .collect();
-        Ok(map)
+            .collect())
     }
 
-    async fn find_ancestor_ids(&self, id: &NodeId) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
-        let stmt = self.pool.get().await?;
+    async fn replace_tags(&mut self, id: &NodeId, tags: &[String]) -> anyhow::Result<()> {
+        self.client()
+            .execute("delete from tags where node_id = $1", &[&id])
+            .await?;
+        self.client()
+            .execute(
+                "insert into tags(node_id, tag) select $1, unnest($2::text[]) on conflict do nothing",
+                &[&id, &tags],
+            )
+            .await?;
+        Ok(())
+    }
 
-        let map = stmt
+    async fn replace_links(&mut self, id: &NodeId, node_refs: &[NodeRef]) -> anyhow::Result<()> {
+        self.client()
+            .execute("delete from node_links where source_id = $1", &[&id])
+            .await?;
+
+        let target_ids: Vec<&str> = node_refs.iter().map(|r| r.id.as_str()).collect();
+        let texts: Vec<&str> = node_refs.iter().map(|r| r.text.as_str()).collect();
+        let snippets: Vec<&str> = node_refs.iter().map(|r| r.snippet.as_str()).collect();
+        self.client()
+            .execute(
+                "insert into node_links(source_id, target_id, position, text, snippet)
+select $1, t.target_id, t.position, t.text, t.snippet
+from unnest($2::text[], $3::text[], $4::text[]) with ordinality as t(target_id, text, snippet, position)
+on conflict do nothing",
+                &[&id, &target_ids, &texts, &snippets],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn replace_search_text(
+        &mut self,
+        id: &NodeId,
+        name: &str,
+        text: &str,
+    ) -> anyhow::Result<()> {
+        self.client()
+            .execute(
+                "insert into node_search(node_id, name, body) values ($1, $2, $3)
+on conflict (node_id) do update set name = excluded.name, body = excluded.body",
+                &[&id, &name, &text],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn query_tagged_ids(&mut self, tags: &[String]) -> anyhow::Result<Vec<NodeId>> {
+        Ok(self
+            .client()
             .query(
-                "with recursive children(id, parent_id) as (
-select n.id, n.parent_id from nodes n where n.id = $1
-union 
-select n.id, n.parent_id from nodes n, children c where n.id = c.parent_id
-)
-select * from children;",
-                &[&id],
+                "select distinct node_id from tags where tag = any($1) order by node_id",
+                &[&tags],
             )
             .await?
             .iter()
-            .map(|row| (row.get("id"), row.get("parent_id")))
-            .collect();
-        Ok(map)
+            .map(|row| row.get("node_id"))
+            .collect())
+    }
+
+    async fn commit(mut self: Box<Self>) -> anyhow::Result<()> {
+        let client = self.client.take().expect("transaction is already finished");
+        client.batch_execute("COMMIT").await?;
+        Ok(())
     }
 }
 
 #[async_trait]
-impl Mapper for PostgresMapper {
-    async fn init(&mut self) -> anyhow::Result<()> {
-        let nodes_fields = self.get_table_fields("nodes").await?;
+impl TagMapper for PostgresMapper {
+    async fn list_tags(&self) -> anyhow::Result<Vec<TagStat>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select t.tag, count(*) as count from tags t join nodes n on n.id = t.node_id where n.delete_time is null group by t.tag order by count desc, t.tag",
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| TagStat {
+                name: row.get("tag"),
+                count: row.get("count"),
+            })
+            .collect())
+    }
+}
 
-        self.node_fields.replace(nodes_fields);
-        self.ensure_tables().await?;
+#[async_trait]
+impl SearchMapper for PostgresMapper {
+    async fn search_index(&self, req: &SearchReq) -> anyhow::Result<Vec<SearchRow>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select s.node_id, n.name, s.body, ts_rank(s.tsv, q)::float8 as rank
+from node_search s join nodes n on n.id = s.node_id, plainto_tsquery('simple', $1) q
+where s.tsv @@ q and n.delete_time is null
+order by rank desc, n.version_time desc
+limit $2 offset $3",
+                &[&tsquery_text(&req.query), &req.limit(), &req.offset()],
+            )
+            .await?
+            .iter()
+            .map(|row| SearchRow {
+                id: row.get("node_id"),
+                name: row.get("name"),
+                body: row.get("body"),
+                rank: row.get("rank"),
+            })
+            .collect())
+    }
+}
 
-        Ok(())
+#[async_trait]
+impl RecycleMapper for PostgresMapper {
+    async fn query_recycle_origins(&self) -> anyhow::Result<HashMap<NodeId, RecycleOrigin>> {
+        let stmt = self.pool.get().await?;
+        Ok(stmt
+            .query(
+                "select node_id, parent_id, prev_sliding_id from recycle_bin",
+                &[],
+            )
+            .await?
+            .iter()
+            .map(|row| {
+                (
+                    row.get("node_id"),
+                    RecycleOrigin {
+                        parent_id: row.get("parent_id"),
+                        prev_sliding_id: row.get("prev_sliding_id"),
+                    },
+                )
+            })
+            .collect())
     }
+}
 
-    async fn ensure_table_nodes(&self) -> anyhow::Result<()> {
-        self.create_table(
-            constants::TABLE_NAME_NODES,
-            "CREATE TABLE nodes (
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: "CREATE TABLE IF NOT EXISTS nodes (
     id VARCHAR(40) NOT NULL,
     name VARCHAR(255) NOT NULL,
     content TEXT NOT NULL,
     node_type VARCHAR(255) NOT NULL,
     domain TEXT NOT NULL,
-    todo_status VARCHAR(10) default NULL,
     delete_time timestamptz DEFAULT NULL,
     parent_id VARCHAR(40) NOT NULL,
     prev_sliding_id VARCHAR(40) NOT NULL,
-    readonly bool not null default false,
     version_time timestamptz NOT NULL default CURRENT_TIMESTAMP,
     initial_time timestamptz NOT NULL,
     primary key (id)
-);",
-        )
-        .await?;
+);
 
-        self.create_table(
-            constants::TABLE_NAME_NODES_HISTORY,
-            "CREATE TABLE nodes_history (
+CREATE TABLE IF NOT EXISTS nodes_history (
     id VARCHAR(40) NOT NULL,
     name VARCHAR(255) NOT NULL,
     content TEXT NOT NULL,
     node_type VARCHAR(255) NOT NULL,
     domain TEXT NOT NULL,
-    todo_status VARCHAR(10) default NULL,
     delete_time timestamptz DEFAULT NULL,
-    readonly bool not null default false,
     version_time timestamptz NOT NULL default CURRENT_TIMESTAMP,
     initial_time timestamptz NOT NULL
-);",
-        )
-        .await?;
+);
 
-        let client = self.get_client().await?;
-        client
-            .execute(
-                "CREATE INDEX if not exists idx_nodes_history_id ON nodes_history (id);",
-                &[],
-            )
-            .await
-            .map(|_| ())
-            .map_err(anyhow::Error::new)
-    }
-
-    async fn ensure_table_tags(&self) -> anyhow::Result<()> {
-        self.create_table(constants::TABLE_NAME_TAGS, "").await
-    }
+CREATE INDEX IF NOT EXISTS idx_nodes_history_id ON nodes_history (id);
 
-    async fn ensure_table_todos(&self) -> anyhow::Result<()> {
-        self.create_table(
-            constants::TABLE_NAME_TODOS,
-            "CREATE TABLE todos (
+CREATE TABLE IF NOT EXISTS assets (
+    id VARCHAR(40) NOT NULL,
+    ori_file_name TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    create_time timestamptz NOT NULL default CURRENT_TIMESTAMP,
+    content_type TEXT,
+    primary key (id)
+);",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 2,
+        name: "nodes_todo_status_and_readonly",
+        sql: "ALTER TABLE nodes ADD COLUMN IF NOT EXISTS todo_status VARCHAR(10) default NULL;
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS readonly bool not null default false;
+ALTER TABLE nodes_history ADD COLUMN IF NOT EXISTS todo_status VARCHAR(10) default NULL;
+ALTER TABLE nodes_history ADD COLUMN IF NOT EXISTS readonly bool not null default false;",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 3,
+        name: "todos",
+        sql: "CREATE TABLE IF NOT EXISTS todos (
     node_id VARCHAR(40) NOT NULL,
     todo_status VARCHAR(10) default null,
-    create_type tinyint not null default 0 comment 'Auto(0) Manual(1)',
+    create_type integer not null default 0,
     domain TEXT NOT NULL,
     create_time timestamptz NOT NULL default CURRENT_TIMESTAMP
-    );",
-        )
-        .await?;
+);
 
+CREATE INDEX IF NOT EXISTS idx_todos_node_id ON todos (node_id);",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 4,
+        name: "tags",
+        sql: "CREATE TABLE IF NOT EXISTS tags (
+    node_id VARCHAR(40) NOT NULL,
+    tag VARCHAR(255) NOT NULL,
+    primary key (node_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tags_tag ON tags (tag);",
+        reindex: true,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 5,
+        name: "node_links",
+        sql: "CREATE TABLE IF NOT EXISTS node_links (
+    source_id VARCHAR(40) NOT NULL,
+    target_id VARCHAR(40) NOT NULL,
+    position INTEGER NOT NULL default 0,
+    text TEXT NOT NULL,
+    snippet TEXT NOT NULL,
+    primary key (source_id, target_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_node_links_target_id ON node_links (target_id);",
+        reindex: true,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 6,
+        name: "node_search",
+        sql: "CREATE TABLE IF NOT EXISTS node_search (
+    node_id VARCHAR(40) NOT NULL,
+    name TEXT NOT NULL,
+    body TEXT NOT NULL,
+    tsv tsvector GENERATED ALWAYS AS (
+        setweight(to_tsvector('simple', name), 'A') || setweight(to_tsvector('simple', body), 'B')
+    ) STORED,
+    primary key (node_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_node_search_tsv ON node_search USING GIN (tsv);",
+        reindex: true,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 7,
+        name: "recycle_bin",
+        sql: "CREATE TABLE IF NOT EXISTS recycle_bin (
+    node_id VARCHAR(40) NOT NULL,
+    parent_id VARCHAR(40) NOT NULL,
+    prev_sliding_id VARCHAR(40) NOT NULL,
+    primary key (node_id)
+);",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 8,
+        name: "nodes_parent_id",
+        sql: "CREATE INDEX IF NOT EXISTS idx_nodes_parent_id ON nodes (parent_id);",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 9,
+        name: "node_paths",
+        sql: "CREATE TABLE IF NOT EXISTS node_paths (
+    ancestor_id VARCHAR(40) NOT NULL,
+    node_id VARCHAR(40) NOT NULL,
+    depth BIGINT NOT NULL,
+    primary key (ancestor_id, node_id)
+);
+CREATE INDEX IF NOT EXISTS idx_node_paths_node_id ON node_paths (node_id);
+ALTER TABLE nodes ADD COLUMN IF NOT EXISTS position BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_nodes_parent_id_position ON nodes (parent_id, position);
+DROP INDEX IF EXISTS idx_nodes_parent_id;",
+        reindex: false,
+        rebuild_tree: true,
+    },
+];
+
+#[async_trait]
+impl Mapper for PostgresMapper {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    async fn init(&mut self) -> anyhow::Result<()> {
+        self.migrate().await?;
+
+        let nodes_fields = self.get_table_fields(constants::TABLE_NAME_NODES).await?;
+        self.node_fields.replace(nodes_fields);
+
+        Ok(())
+    }
+
+    async fn ensure_table_schema_migrations(&self) -> anyhow::Result<()> {
         self.get_client()
             .await?
-            .execute(
-                "CREATE INDEX if not exists idx_todos_node_id ON todos (node_id);",
-                &[],
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version bigint NOT NULL,
+    name TEXT NOT NULL,
+    applied_time timestamptz NOT NULL default CURRENT_TIMESTAMP,
+    primary key (version)
+);",
             )
-            .await?;
-
-        Ok(())
+            .await
+            .map_err(anyhow::Error::new)
     }
-    async fn ensure_table_alarm_instances(&self) -> anyhow::Result<()> {
-        Ok(())
+
+    async fn query_schema_version(&self) -> anyhow::Result<Option<i64>> {
+        let client = self.get_client().await?;
+        let row = client
+            .query_one("select max(version) as version from schema_migrations", &[])
+            .await?;
+        Ok(row.get("version"))
     }
 
-    async fn ensure_table_alarm_definations(&self) -> anyhow::Result<()> {
-        Ok(())
+    async fn apply_migration(&self, migration: &Migration) -> anyhow::Result<()> {
+        let mut client = self.get_client().await?;
+        let tx = client.transaction().await?;
+
+        // Serialize concurrent starters, the loser sees the recorded version.
+        tx.batch_execute("LOCK TABLE schema_migrations IN EXCLUSIVE MODE")
+            .await?;
+        if tx
+            .query_opt(
+                "select 1 from schema_migrations where version = $1",
+                &[&migration.version],
+            )
+            .await?
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        tx.batch_execute(migration.sql).await?;
+        tx.execute(
+            "insert into schema_migrations(version, name) values ($1, $2)",
+            &[&migration.version, &migration.name],
+        )
+        .await?;
+
+        tx.commit().await.map_err(anyhow::Error::new)
     }
 
     async fn get_table_fields(&self, table_name: &str) -> anyhow::Result<Vec<String>> {
@@ -604,21 +1173,6 @@ impl Mapper for PostgresMapper {
         }
         Ok(row.iter().map(|c| c.get("column_name")).collect())
     }
-
-    async fn ensure_table_assets(&self) -> anyhow::Result<()> {
-        self.create_table(
-            constants::TABLE_NAME_ASSETS,
-            "CREATE TABLE assets (
-    id VARCHAR(40) NOT NULL,
-    ori_file_name TEXT NOT NULL,
-    domain TEXT NOT NULL,
-    create_time timestamptz NOT NULL default CURRENT_TIMESTAMP,
-    content_type TEXT,
-    primary key (id)
-);",
-        )
-        .await
-    }
 }
 
 #[async_trait]
@@ -730,3 +1284,74 @@ impl tokio_postgres::types::ToSql for MagicNodeId {
 
     to_sql_checked!();
 }
+
+impl tokio_postgres::types::ToSql for SqlParam {
+    fn to_sql(
+        &self,
+        ty: &postgres_types::Type,
+        out: &mut BytesMut,
+    ) -> Result<postgres_types::IsNull, Box<dyn std::error::Error + Sync + Send>>
+    where
+        Self: Sized,
+    {
+        match self {
+            SqlParam::Text(text) => <&str as ToSql>::to_sql(&text.as_str(), ty, out),
+            SqlParam::Int(int) => <i64 as ToSql>::to_sql(int, ty, out),
+            SqlParam::Bool(bool) => <bool as ToSql>::to_sql(bool, ty, out),
+            SqlParam::Time(time) => <DateTime<Utc> as ToSql>::to_sql(time, ty, out),
+        }
+    }
+
+    fn accepts(ty: &postgres_types::Type) -> bool
+    where
+        Self: Sized,
+    {
+        <&str as ToSql>::accepts(ty)
+            || <i64 as ToSql>::accepts(ty)
+            || <bool as ToSql>::accepts(ty)
+            || <DateTime<Utc> as ToSql>::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mapper::{
+        testutils::{check_copy, check_delete_and_restore, check_move, insert_tree},
+        Mapper,
+    };
+
+    use super::PostgresMapper;
+
+    /// The `PostgresConfig` of a scratch database as json, the tests are
+    /// skipped without it. Everything in the database is dropped.
+    const CONFIG_VAR: &str = "CHNOTS_TEST_POSTGRES";
+
+    async fn tree(config: &str) -> PostgresMapper {
+        let mut mapper = PostgresMapper::new(serde_json::from_str(config).unwrap()).unwrap();
+        mapper
+            .get_client()
+            .await
+            .unwrap()
+            .batch_execute("drop schema public cascade; create schema public;")
+            .await
+            .unwrap();
+        mapper.init().await.unwrap();
+        insert_tree(&mapper).await;
+        mapper
+    }
+
+    /// One test only, the scenarios share the database.
+    #[tokio::test]
+    async fn test_tree() {
+        let Ok(config) = std::env::var(CONFIG_VAR) else {
+            eprintln!("{} is not set, skipped", CONFIG_VAR);
+            return;
+        };
+
+        check_move(&tree(&config).await).await;
+        check_delete_and_restore(&tree(&config).await).await;
+        check_copy(&tree(&config).await).await;
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use chin_tools::log_and_err;
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::node::{MagicNodeId, Node, NodeId},
+    parser::tiptap_v1_parser::find_asset_ids,
+};
+
+use super::{
+    node::{NodeMapper, NodeMoveReq, NodeMoveRsp},
+    nodefilter::{NodeFetchReq, NodeFilter, NodeSelection, TimeField, TimeRange},
+    tx::NodeTx,
+};
+
+/// Where a subtree was before it is deleted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecycleOrigin {
+    pub parent_id: MagicNodeId,
+    pub prev_sliding_id: MagicNodeId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecycleEntry {
+    /// The root of a deleted subtree, without content.
+    pub node: Node,
+    /// `None` for nodes deleted before the origin is recorded.
+    pub origin: Option<RecycleOrigin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecycleRestoreReq {
+    pub id: NodeId,
+    /// Somewhere else than the origin, the first child when `prev_sliding_id`
+    /// is missing.
+    #[serde(default)]
+    pub parent_id: Option<MagicNodeId>,
+    #[serde(default)]
+    pub prev_sliding_id: Option<MagicNodeId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecyclePurgeReq {
+    pub id: NodeId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RecycleEmptyReq {
+    /// Only purge what is deleted more than this many days ago.
+    #[serde(default)]
+    pub older_than_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RecyclePurgeRsp {
+    pub node_ids: Vec<NodeId>,
+    /// Assets no node refers to any more, their files are left to the caller.
+    pub asset_ids: Vec<String>,
+}
+
+/// Deleted subtrees hang under `MagicNodeId::RecycleBin` and all their nodes
+/// carry a `delete_time`. Restoring brings a whole subtree back, purging
+/// removes it for good together with its history.
+#[async_trait]
+pub trait RecycleMapper: NodeMapper {
+    /// The origin of every subtree in the recycle bin.
+    async fn query_recycle_origins(&self) -> anyhow::Result<HashMap<NodeId, RecycleOrigin>>;
+
+    /// The roots of the deleted subtrees, the latest deleted first.
+    async fn list_recycle_bin(&self) -> anyhow::Result<Vec<RecycleEntry>> {
+        let nodes = self
+            .query_nodes(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::WithHistory]),
+                filter: Some(NodeFilter::Children(MagicNodeId::RecycleBin.into())),
+                ..Default::default()
+            })
+            .await?;
+        let mut origins = self.query_recycle_origins().await?;
+
+        let mut entries: Vec<RecycleEntry> = nodes
+            .into_iter()
+            .map(|node| RecycleEntry {
+                origin: origins.remove(&node.id),
+                node,
+            })
+            .collect();
+        entries.sort_by_key(|e| std::cmp::Reverse(e.node.delete_time));
+        Ok(entries)
+    }
+
+    /// Bring a subtree back to its origin, or to the requested position.
+    async fn restore_node(&self, req: &RecycleRestoreReq) -> anyhow::Result<NodeMoveRsp> {
+        let mut tx = self.begin().await?;
+        let rsp = restore(tx.as_mut(), req).await?;
+        tx.commit().await?;
+        Ok(rsp)
+    }
+
+    async fn purge_node(&self, req: &RecyclePurgeReq) -> anyhow::Result<RecyclePurgeRsp> {
+        let mut tx = self.begin().await?;
+        let rsp = purge(tx.as_mut(), std::slice::from_ref(&req.id)).await?;
+        tx.commit().await?;
+        Ok(rsp)
+    }
+
+    async fn empty_recycle_bin(&self, req: &RecycleEmptyReq) -> anyhow::Result<RecyclePurgeRsp> {
+        let mut tx = self.begin().await?;
+        tx.lock_parents(&[&MagicNodeId::RecycleBin]).await?;
+
+        let mut filter = NodeFilter::Children(MagicNodeId::RecycleBin.into());
+        if let Some(days) = req.older_than_days {
+            filter = NodeFilter::And(Box::new(vec![
+                filter,
+                NodeFilter::Time(
+                    TimeField::DeleteTime,
+                    TimeRange {
+                        since: None,
+                        until: Some(Utc::now() - Duration::days(days as i64)),
+                    },
+                ),
+            ]));
+        }
+        let ids: Vec<NodeId> = tx
+            .query_nodes(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::WithHistory]),
+                filter: Some(filter),
+                ..Default::default()
+            })
+            .await?
+            .into_iter()
+            .map(|e| e.id)
+            .collect();
+
+        let rsp = purge(tx.as_mut(), &ids).await?;
+        tx.commit().await?;
+        Ok(rsp)
+    }
+}
+
+async fn restore(tx: &mut dyn NodeTx, req: &RecycleRestoreReq) -> anyhow::Result<NodeMoveRsp> {
+    let origin = tx.query_recycle_origin(&req.id).await?;
+    let parent_id = match (&req.parent_id, &origin) {
+        (Some(parent_id), _) => parent_id.clone(),
+        (None, Some(origin)) => origin.parent_id.clone(),
+        (None, None) => {
+            return log_and_err!("the origin of {:?} is unknown, choose a parent", req.id)
+        }
+    };
+    let node = tx.lock_relation(&req.id, &[&parent_id]).await?;
+    if !matches!(node.parent_id, MagicNodeId::RecycleBin) {
+        return log_and_err!("node {:?} is not in the recycle bin", req.id);
+    }
+    tx.check_position(&parent_id, &MagicNodeId::Empty).await?;
+
+    let prev_sliding_id = match (&req.prev_sliding_id, origin) {
+        (Some(prev_id), _) => {
+            tx.check_position(&parent_id, prev_id).await?;
+            prev_id.clone()
+        }
+        // The old previous sibling may be gone, the first place is as good as any.
+        (None, Some(origin))
+            if origin.parent_id.as_ref() == parent_id.as_ref()
+                && is_live_child(tx, &origin.prev_sliding_id, &parent_id).await? =>
+        {
+            origin.prev_sliding_id
+        }
+        (None, _) => MagicNodeId::Empty,
+    };
+
+    let mut ids: Vec<NodeId> = tx.find_descendant_ids(&req.id).await?.into_keys().collect();
+    ids.push(req.id.clone());
+    tx.clear_deleted(&ids).await?;
+    tx.delete_recycle_origin(&req.id).await?;
+
+    tx.move_node(&NodeMoveReq {
+        id: req.id.clone(),
+        parent_id,
+        prev_sliding_id,
+    })
+    .await
+}
+
+async fn is_live_child(
+    tx: &mut dyn NodeTx,
+    id: &MagicNodeId,
+    parent_id: &MagicNodeId,
+) -> anyhow::Result<bool> {
+    Ok(match id {
+        MagicNodeId::Empty => true,
+        MagicNodeId::Id(id) => tx
+            .query_node(id)
+            .await?
+            .is_some_and(|e| e.delete_time.is_none() && e.parent_id.as_ref() == parent_id.as_ref()),
+        _ => false,
+    })
+}
+
+/// Remove the subtrees under `ids` with everything derived from them, then
+/// the assets only they referred to.
+async fn purge(tx: &mut dyn NodeTx, ids: &[NodeId]) -> anyhow::Result<RecyclePurgeRsp> {
+    tx.lock_parents(&[&MagicNodeId::RecycleBin]).await?;
+
+    let mut node_ids = vec![];
+    for id in ids {
+        match tx.query_node(id).await? {
+            Some(node) if matches!(node.parent_id, MagicNodeId::RecycleBin) => {}
+            _ => return log_and_err!("node {:?} is not in the recycle bin", id),
+        }
+        tx.delete_relation(id).await?;
+        node_ids.push(id.clone());
+        node_ids.extend(tx.find_descendant_ids(id).await?.into_keys());
+    }
+    if node_ids.is_empty() {
+        return Ok(RecyclePurgeRsp::default());
+    }
+
+    let candidates: HashSet<String> = tx
+        .query_contents(&node_ids)
+        .await?
+        .iter()
+        .flat_map(|e| find_asset_ids(e))
+        .collect();
+    tx.purge_node_rows(&node_ids).await?;
+
+    let mut asset_ids = vec![];
+    for asset_id in candidates {
+        if !tx.is_asset_referenced(&asset_id).await? && tx.delete_asset_row(&asset_id).await? > 0 {
+            asset_ids.push(asset_id);
+        }
+    }
+    asset_ids.sort();
+
+    Ok(RecyclePurgeRsp {
+        node_ids,
+        asset_ids,
+    })
+}
@@ -0,0 +1,172 @@
+use std::collections::{HashMap, HashSet};
+
+use chin_tools::log_and_err;
+use serde::{Deserialize, Serialize};
+
+use crate::model::node::{MagicNodeId, Node, NodeId};
+
+use super::{
+    error::MapperError,
+    node::{NodeMoveReq, NodeRelation},
+    nodefilter::{NodeFetchReq, NodeFilter},
+    page::{NodeSort, NodeSortKey},
+    tx::NodeTx,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeMoveBatchReq {
+    /// Applied one after another, each sees the tree left by the previous ones.
+    pub moves: Vec<NodeMoveReq>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeReorderReq {
+    pub parent_id: MagicNodeId,
+    /// Every child of the parent, in the new order.
+    pub child_ids: Vec<NodeId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeSortChildrenReq {
+    pub parent_id: MagicNodeId,
+    /// Any key but `TreeOrder`, ties are broken by the id.
+    #[serde(default)]
+    pub sort: NodeSort,
+}
+
+/// Move several nodes in one go, return where each of them ends up, in the
+/// order of `req.moves`.
+pub async fn move_batch(
+    tx: &mut dyn NodeTx,
+    req: &NodeMoveBatchReq,
+) -> anyhow::Result<Vec<NodeRelation>> {
+    // Every lock known upfront at once, the moves below find them held.
+    tx.lock_moves(&req.moves).await?;
+
+    for req in req.moves.iter() {
+        tx.check_move(req).await?;
+        tx.move_node(req).await?;
+    }
+
+    let mut relations = vec![];
+    for req in req.moves.iter() {
+        relations.push(query_relation(tx, &req.id).await?);
+    }
+    Ok(relations)
+}
+
+/// Put the children of a parent in the order asked for, return their new
+/// relations in that order.
+pub async fn reorder_children(
+    tx: &mut dyn NodeTx,
+    req: &NodeReorderReq,
+) -> anyhow::Result<Vec<NodeRelation>> {
+    let children = lock_children(tx, &req.parent_id).await?;
+
+    let current: HashSet<&str> = children.iter().map(|e| e.id.as_str()).collect();
+    let wanted: HashSet<&str> = req.child_ids.iter().map(|e| e.as_str()).collect();
+    if wanted.len() != req.child_ids.len() || wanted != current {
+        return Err(MapperError::InvalidRequest(format!(
+            "{:?} are not the children of {:?}, which are {:?}",
+            req.child_ids, req.parent_id, current
+        ))
+        .into());
+    }
+
+    relink_children(tx, &req.parent_id, &children, &req.child_ids).await
+}
+
+/// Put the children of a parent in the order of `req.sort`.
+pub async fn sort_children(
+    tx: &mut dyn NodeTx,
+    req: &NodeSortChildrenReq,
+) -> anyhow::Result<Vec<NodeRelation>> {
+    if req.sort.key == NodeSortKey::TreeOrder {
+        return Err(MapperError::InvalidRequest(
+            "children can not be sorted by their tree order".to_owned(),
+        )
+        .into());
+    }
+
+    let mut children = lock_children(tx, &req.parent_id).await?;
+    children.sort_by(|a, b| req.sort.compare(a, b));
+    let child_ids: Vec<NodeId> = children.iter().map(|e| e.id.clone()).collect();
+
+    relink_children(tx, &req.parent_id, &children, &child_ids).await
+}
+
+/// The live children of a live parent in their linked order, the parent
+/// stays locked.
+async fn lock_children(tx: &mut dyn NodeTx, parent_id: &MagicNodeId) -> anyhow::Result<Vec<Node>> {
+    tx.lock_parents(&[parent_id]).await?;
+    tx.check_position(parent_id, &MagicNodeId::Empty).await?;
+
+    tx.query_nodes(&NodeFetchReq {
+        selection: None,
+        filter: Some(NodeFilter::Children(parent_id.clone().into())),
+        sort: Some(NodeSort {
+            key: NodeSortKey::Position,
+            desc: false,
+        }),
+        ..Default::default()
+    })
+    .await
+}
+
+/// Rewrite the sibling list of `children` to the order of `child_ids`,
+/// only the nodes whose previous sibling changes are written.
+async fn relink_children(
+    tx: &mut dyn NodeTx,
+    parent_id: &MagicNodeId,
+    children: &[Node],
+    child_ids: &[NodeId],
+) -> anyhow::Result<Vec<NodeRelation>> {
+    let prev_ids: HashMap<&str, &str> = children
+        .iter()
+        .map(|e| (e.id.as_str(), e.prev_sliding_id.as_ref()))
+        .collect();
+
+    let mut relations = vec![];
+    let mut changed = false;
+    for (index, id) in child_ids.iter().enumerate() {
+        let prev_id = match index {
+            0 => MagicNodeId::Empty,
+            _ => child_ids[index - 1].clone().into(),
+        };
+        let next_id = match child_ids.get(index + 1) {
+            Some(next_id) => next_id.clone().into(),
+            None => MagicNodeId::Empty,
+        };
+
+        if prev_ids.get(id.as_str()) != Some(&prev_id.as_ref()) {
+            tx.update_relation(id, parent_id, &prev_id).await?;
+            changed = true;
+        }
+
+        relations.push(NodeRelation {
+            parent_id: parent_id.clone(),
+            prev_id,
+            next_id,
+        });
+    }
+    // The order keys only hold for whole lists, not for the steps between.
+    if changed {
+        tx.renumber_children(parent_id).await?;
+    }
+    Ok(relations)
+}
+
+async fn query_relation(tx: &mut dyn NodeTx, id: &NodeId) -> anyhow::Result<NodeRelation> {
+    let node = match tx.query_node(id).await? {
+        Some(node) => node,
+        None => return log_and_err!("there are no node with id: {:?}", id),
+    };
+    let next_id = tx
+        .query_next_id(&node.parent_id, &id.clone().into())
+        .await?;
+    Ok(NodeRelation {
+        parent_id: node.parent_id,
+        prev_id: node.prev_sliding_id,
+        next_id,
+    })
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chin_tools::log_and_err;
+use serde::{Deserialize, Serialize};
+
+use crate::model::node::{MagicNodeId, NodeId};
+
+use super::{
+    node::NodeMapper,
+    nodefilter::{NodeFetchReq, NodeFilter},
+};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 200;
+
+/// Longest snippet of a hit, in chars.
+const SNIPPET_CHARS: usize = 120;
+
+pub const HIGHLIGHT_START: &str = "<mark>";
+pub const HIGHLIGHT_END: &str = "</mark>";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchReq {
+    pub query: String,
+    #[serde(default)]
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub offset: Option<i64>,
+}
+
+impl SearchReq {
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+}
+
+/// A matched node as the index returns it, the best one first.
+#[derive(Debug, Clone)]
+pub struct SearchRow {
+    pub id: NodeId,
+    pub name: String,
+    pub body: String,
+    pub rank: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PathNode {
+    pub id: NodeId,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SearchHit {
+    pub id: NodeId,
+    pub name: String,
+    pub rank: f64,
+    /// Html escaped text around the first match, matches are wrapped in
+    /// `HIGHLIGHT_START` and `HIGHLIGHT_END`.
+    pub snippet: String,
+    /// Ancestors from the root down to the parent.
+    pub path: Vec<PathNode>,
+}
+
+/// Search over the names and the plain text of live nodes.
+///
+/// The index is kept by `NodeTx::index_content`, the backends only rank the
+/// matches, snippets and paths are built here so they look the same everywhere.
+#[async_trait]
+pub trait SearchMapper: NodeMapper {
+    async fn search_index(&self, req: &SearchReq) -> anyhow::Result<Vec<SearchRow>>;
+
+    async fn search(&self, req: &SearchReq) -> anyhow::Result<Vec<SearchHit>> {
+        let terms = search_terms(&req.query);
+        if terms.is_empty() {
+            return log_and_err!("search query is empty: {:?}", req.query);
+        }
+
+        let rows = self.search_index(req).await?;
+
+        let mut ancestors = vec![];
+        for row in rows.iter() {
+            ancestors.push(self.find_ancestor_ids(&row.id).await?);
+        }
+
+        let ids: Vec<NodeFilter> = ancestors
+            .iter()
+            .flat_map(|e| e.keys())
+            .map(|id| NodeFilter::Id(id.clone()))
+            .collect();
+        let names: HashMap<NodeId, String> = if ids.is_empty() {
+            HashMap::new()
+        } else {
+            self.query_nodes(&NodeFetchReq {
+                selection: None,
+                filter: Some(NodeFilter::Or(Box::new(ids))),
+                ..Default::default()
+            })
+            .await?
+            .into_iter()
+            .map(|n| (n.id, n.name))
+            .collect()
+        };
+
+        Ok(rows
+            .into_iter()
+            .zip(ancestors)
+            .map(|(row, ancestors)| SearchHit {
+                path: ancestor_path(&row.id, &ancestors, &names),
+                snippet: highlight(&row.body, &terms, SNIPPET_CHARS),
+                id: row.id,
+                name: row.name,
+                rank: row.rank,
+            })
+            .collect())
+    }
+}
+
+/// The words of a query, lowercased.
+///
+/// Every word must match, there are no operators, so all backends agree on
+/// what a query means.
+pub fn search_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = vec![];
+    for word in query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|e| !e.is_empty())
+    {
+        let term = word.to_lowercase();
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// `search_terms` for `plainto_tsquery`.
+pub fn tsquery_text(query: &str) -> String {
+    search_terms(query).join(" ")
+}
+
+/// Quote every term, so the input never reaches the fts5 query syntax.
+pub fn fts5_query(query: &str) -> String {
+    search_terms(query)
+        .iter()
+        .map(|t| format!("\"{}\"", t))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn ancestor_path(
+    id: &NodeId,
+    ancestors: &HashMap<NodeId, MagicNodeId>,
+    names: &HashMap<NodeId, String>,
+) -> Vec<PathNode> {
+    let mut path = vec![];
+    let mut cursor = ancestors.get(id);
+    while let Some(MagicNodeId::Id(parent_id)) = cursor {
+        if path.len() > ancestors.len() {
+            break;
+        }
+        path.push(PathNode {
+            id: parent_id.clone(),
+            name: names.get(parent_id).cloned().unwrap_or_default(),
+        });
+        cursor = ancestors.get(parent_id);
+    }
+    path.reverse();
+    path
+}
+
+/// Cut `text` around the first match and highlight every match in it.
+pub fn highlight(text: &str, terms: &[String], max_chars: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+    let terms: Vec<Vec<char>> = terms.iter().map(|t| t.chars().collect()).collect();
+
+    let match_at = |pos: usize| {
+        terms
+            .iter()
+            .filter(|t| !t.is_empty() && lower[pos..].starts_with(t))
+            .map(|t| t.len())
+            .max()
+    };
+
+    let first = (0..lower.len()).find(|pos| match_at(*pos).is_some());
+    let start = first
+        .map(|pos| pos.saturating_sub(max_chars / 4))
+        .unwrap_or(0)
+        .min(chars.len().saturating_sub(max_chars));
+    let end = (start + max_chars).min(chars.len());
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push_str("...");
+    }
+    let mut pos = start;
+    while pos < end {
+        match match_at(pos) {
+            Some(len) => {
+                let len = len.min(end - pos);
+                snippet.push_str(HIGHLIGHT_START);
+                chars[pos..pos + len]
+                    .iter()
+                    .for_each(|c| escape_html(*c, &mut snippet));
+                snippet.push_str(HIGHLIGHT_END);
+                pos += len;
+            }
+            None => {
+                escape_html(chars[pos], &mut snippet);
+                pos += 1;
+            }
+        }
+    }
+    if end < chars.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+fn escape_html(c: char, out: &mut String) {
+    match c {
+        '<' => out.push_str("&lt;"),
+        '>' => out.push_str("&gt;"),
+        '&' => out.push_str("&amp;"),
+        '"' => out.push_str("&quot;"),
+        '\'' => out.push_str("&#39;"),
+        '\n' => out.push(' '),
+        c => out.push(c),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fts5_query, highlight, search_terms};
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(
+            search_terms(r#"Rust -"async" rust* it's"#),
+            vec!["rust", "async", "it", "s"]
+        );
+        assert_eq!(fts5_query(r#"a"b c"#), r#""a" "b" "c""#);
+        assert!(search_terms(" -* ").is_empty());
+    }
+
+    #[test]
+    fn test_highlight() {
+        let terms = vec!["rust".to_owned()];
+        assert_eq!(
+            highlight("I <3 Rust & rust", &terms, 100),
+            "I &lt;3 <mark>Rust</mark> &amp; <mark>rust</mark>"
+        );
+        assert_eq!(
+            highlight("0123456789 rust 0123456789", &terms, 12),
+            "...89 <mark>rust</mark> 0123..."
+        );
+        assert_eq!(highlight("no match", &terms, 5), "no ma...");
+    }
+}
@@ -1,10 +1,43 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use async_trait::async_trait;
+use chin_tools::log_and_err;
+use chrono::{DateTime, Utc};
 use deadpool_sqlite::Pool;
+use num_traits::ToPrimitive;
+use rusqlite::{
+    params, params_from_iter,
+    types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+    Connection, OptionalExtension, Row, ToSql, TransactionBehavior,
+};
 use serde::Deserialize;
+use tracing::info;
 
-#[allow(dead_code)]
-pub struct SqliteMapper {
-    pool: Pool,
-}
+use crate::{
+    constants,
+    model::{
+        asset::Asset,
+        node::{ContentParsedInfo, MagicNodeId, Node, NodeId, NodeRef, NodeType},
+        tag::TagStat,
+        todo::TodoEvent,
+    },
+};
+
+use super::{
+    asset::AssetMapper,
+    history::HistoryConfig,
+    migration::Migration,
+    node::{
+        NodeLink, NodeMapper, NodeRenameReq, NodeUpdateReadonlyReq, NodeVersion, NodeVersionReq,
+    },
+    nodefilter::{sql_ancestors, sql_descendants, NodeFetchReq, SqlDialect, SqlParam, SqlQuery},
+    recycle::{RecycleMapper, RecycleOrigin},
+    search::{fts5_query, SearchMapper, SearchReq, SearchRow},
+    tag::TagMapper,
+    todo::{TodoCreateReq, TodoMapper},
+    tx::NodeTx,
+    Mapper,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct SqliteConfig {
@@ -17,11 +50,1663 @@ impl Into<deadpool_sqlite::Config> for SqliteConfig {
     }
 }
 
+pub struct SqliteMapper {
+    pub pool: Pool,
+    pub node_fields: Option<Vec<String>>,
+    pub snapshot_distance: usize,
+}
+
 impl SqliteMapper {
     pub fn new(config: SqliteConfig) -> anyhow::Result<SqliteMapper> {
         let config: deadpool_sqlite::Config = config.into();
 
         let pool = config.builder(deadpool_sqlite::Runtime::Tokio1)?.build()?;
-        Ok(SqliteMapper { pool })
+        Ok(SqliteMapper {
+            pool,
+            node_fields: None,
+            snapshot_distance: HistoryConfig::default().snapshot_distance,
+        })
+    }
+
+    /// Run `func` with a pooled connection on the blocking thread pool.
+    pub(crate) async fn interact<F, R>(&self, func: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Connection) -> anyhow::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.pool.get().await?;
+        interact_with(&conn, func).await
+    }
+
+    fn map_row_node(row: &Row) -> rusqlite::Result<Node> {
+        let node_type: String = row.get("node_type")?;
+        let todo_status: Option<String> = row.get("todo_status")?;
+        Ok(Node {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            content: row.get("content").unwrap_or_default(),
+            domain: row.get("domain")?,
+            parsed_info: ContentParsedInfo::default(),
+            parent_id: row.get("parent_id")?,
+            prev_sliding_id: row.get("prev_sliding_id")?,
+            position: row.get("position")?,
+            delete_time: row.get("delete_time")?,
+            version_time: row.get("version_time")?,
+            initial_time: row.get("initial_time")?,
+            node_type: NodeType::from_str(&node_type).unwrap(),
+            readonly: row.get("readonly")?,
+            todo_status: super::todo::to_todo_status(todo_status.as_deref()),
+        })
+    }
+
+    fn map_row_link(row: &Row) -> rusqlite::Result<NodeLink> {
+        Ok(NodeLink {
+            source_id: row.get("source_id")?,
+            target_id: row.get("target_id")?,
+            name: row.get("name")?,
+            text: row.get("text")?,
+            snippet: row.get("snippet")?,
+        })
+    }
+
+    fn map_row_asset(row: &Row) -> rusqlite::Result<Asset> {
+        Ok(Asset {
+            id: row.get("id")?,
+            domain: row.get("domain")?,
+            ori_file_name: row.get("ori_file_name")?,
+            content_type: row.get("content_type")?,
+            create_time: row.get("create_time")?,
+        })
+    }
+}
+
+#[async_trait]
+impl AssetMapper for SqliteMapper {
+    async fn insert_asset(
+        &self,
+        ori_file_name: &str,
+        id: String,
+        content_type: String,
+        domain: Option<String>,
+    ) -> anyhow::Result<Asset> {
+        let asset = Asset {
+            id,
+            domain,
+            ori_file_name: ori_file_name.to_string(),
+            content_type,
+            create_time: Utc::now(),
+        };
+
+        let row = asset.clone();
+        self.interact(move |conn| {
+            conn.execute(
+                "insert into assets(id, domain, ori_file_name, content_type, create_time) values (?1, ?2, ?3, ?4, ?5)",
+                params![row.id, row.domain, row.ori_file_name, row.content_type, row.create_time],
+            )?;
+            Ok(())
+        })
+        .await?;
+
+        Ok(asset)
+    }
+
+    async fn query_asset_by_id(&self, id: &str) -> anyhow::Result<Asset> {
+        let id = id.to_string();
+        self.interact(move |conn| {
+            Ok(conn.query_row(
+                "select * from assets where id = ?1",
+                params![id],
+                Self::map_row_asset,
+            )?)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl NodeMapper for SqliteMapper {
+    async fn begin(&self) -> anyhow::Result<Box<dyn NodeTx>> {
+        let conn = self.pool.get().await?;
+        // Take the writer lock up front, so the whole tree mutation is serialized.
+        interact_with(&conn, |conn| Ok(conn.execute_batch("BEGIN IMMEDIATE")?)).await?;
+        Ok(Box::new(SqliteTx {
+            conn: Some(conn),
+            node_fields: self.node_fields.clone().unwrap_or_default(),
+            snapshot_distance: self.snapshot_distance,
+        }))
+    }
+
+    async fn update_node_name(&self, req: &NodeRenameReq) -> anyhow::Result<u64> {
+        let req = req.clone();
+        self.interact(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let count = tx.execute(
+                "update nodes set name = ?1 where id = ?2",
+                params![req.name, req.id],
+            )?;
+            tx.execute(
+                "update node_search_docs set name = ?1 where node_id = ?2",
+                params![req.name, req.id],
+            )?;
+            tx.commit()?;
+            Ok(count as u64)
+        })
+        .await
+    }
+
+    async fn update_node_readonly(&self, req: &NodeUpdateReadonlyReq) -> anyhow::Result<u64> {
+        let req = req.clone();
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "update nodes set readonly = ?1 where id = ?2",
+                params![req.readonly, req.id],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn query_nodes(&self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
+        let query = node_filter.to_sql(self.node_fields.as_ref().unwrap(), SqlDialect::Sqlite);
+        self.interact(move |conn| query_nodes_with(conn, &query))
+            .await
+    }
+
+    async fn query_child_counts(&self, ids: &[NodeId]) -> anyhow::Result<HashMap<NodeId, u64>> {
+        let ids = ids.to_vec();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "select count(*) from nodes where parent_id = ?1 and delete_time is null",
+            )?;
+            let mut counts = HashMap::new();
+            for id in ids {
+                let count: i64 = stmt.query_row(params![id], |row| row.get(0))?;
+                if count > 0 {
+                    counts.insert(id, count as u64);
+                }
+            }
+            Ok(counts)
+        })
+        .await
+    }
+
+    async fn query_backlinks(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "select l.source_id, l.target_id, n.name, l.text, l.snippet from node_links l
+join nodes n on n.id = l.source_id
+where l.target_id = ?1 and n.delete_time is null
+order by n.version_time desc",
+            )?;
+            let links = stmt
+                .query_map(params![id], SqliteMapper::map_row_link)?
+                .collect::<Result<Vec<NodeLink>, rusqlite::Error>>()?;
+            Ok(links)
+        })
+        .await
+    }
+
+    async fn query_outgoing_links(&self, id: &NodeId) -> anyhow::Result<Vec<NodeLink>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "select l.source_id, l.target_id, n.name, l.text, l.snippet from node_links l
+left join nodes n on n.id = l.target_id and n.delete_time is null
+where l.source_id = ?1
+order by l.position",
+            )?;
+            let links = stmt
+                .query_map(params![id], SqliteMapper::map_row_link)?
+                .collect::<Result<Vec<NodeLink>, rusqlite::Error>>()?;
+            Ok(links)
+        })
+        .await
+    }
+
+    async fn query_versions(&self, id: &NodeId) -> anyhow::Result<Vec<NodeVersion>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "select version_time, name, length(content) as size, 1 as current from nodes where id = ?1
+union all
+select version_time, name, length(content) as size, 0 as current from nodes_history where id = ?1
+order by current desc, version_time desc",
+            )?;
+            let versions = stmt
+                .query_map(params![id], |row| {
+                    Ok(NodeVersion {
+                        version_time: row.get("version_time")?,
+                        name: row.get("name")?,
+                        size: row.get("size")?,
+                        current: row.get("current")?,
+                    })
+                })?
+                .collect::<Result<Vec<NodeVersion>, rusqlite::Error>>()?;
+            Ok(versions)
+        })
+        .await
+    }
+
+    async fn query_version(&self, req: &NodeVersionReq) -> anyhow::Result<Option<Node>> {
+        let (id, version_time) = (req.id.clone(), req.version_time);
+        self.interact(move |conn| query_version_with(conn, &id, &version_time))
+            .await
+    }
+
+    async fn find_descendant_ids(
+        &self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(&sql_descendants("?1"))?;
+            let map = stmt
+                .query_map(params![id], |row| {
+                    Ok((row.get("id")?, row.get("parent_id")?))
+                })?
+                .collect::<Result<HashMap<NodeId, MagicNodeId>, rusqlite::Error>>()?;
+            Ok(map)
+        })
+        .await
+    }
+
+    async fn find_ancestor_ids(&self, id: &NodeId) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(&sql_ancestors("?1"))?;
+            let map = stmt
+                .query_map(params![id], |row| {
+                    Ok((row.get("id")?, row.get("parent_id")?))
+                })?
+                .collect::<Result<HashMap<NodeId, MagicNodeId>, rusqlite::Error>>()?;
+            Ok(map)
+        })
+        .await
+    }
+}
+
+async fn interact_with<F, R>(conn: &deadpool_sqlite::Object, func: F) -> anyhow::Result<R>
+where
+    F: FnOnce(&mut Connection) -> anyhow::Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    conn.interact(move |conn| {
+        // Several pooled connections share one file, so wait for the
+        // writer lock instead of failing with `SQLITE_BUSY`.
+        conn.busy_timeout(Duration::from_secs(5))?;
+        func(conn)
+    })
+    .await
+    .map_err(|err| anyhow::anyhow!("unable to interact with sqlite, {}", err))?
+}
+
+fn query_version_with(
+    conn: &Connection,
+    id: &NodeId,
+    version_time: &DateTime<Utc>,
+) -> anyhow::Result<Option<Node>> {
+    Ok(conn
+        .query_row(
+            "select id, name, content, node_type, domain, todo_status, delete_time, readonly, version_time, initial_time, parent_id, prev_sliding_id, position, 0 as history
+from nodes where id = ?1 and version_time = ?2
+union all
+select h.id, h.name, h.content, h.node_type, h.domain, h.todo_status, h.delete_time, h.readonly, h.version_time, h.initial_time, n.parent_id, n.prev_sliding_id, n.position, 1 as history
+from nodes_history h join nodes n on n.id = h.id where h.id = ?1 and h.version_time = ?2
+order by history limit 1",
+            params![id, version_time],
+            SqliteMapper::map_row_node,
+        )
+        .optional()?)
+}
+
+fn query_nodes_with(conn: &Connection, query: &SqlQuery) -> anyhow::Result<Vec<Node>> {
+    let mut stmt = conn.prepare(&query.sql)?;
+    let nodes = stmt
+        .query_map(
+            params_from_iter(query.params.iter()),
+            SqliteMapper::map_row_node,
+        )?
+        .collect::<Result<Vec<Node>, rusqlite::Error>>()?;
+    Ok(nodes)
+}
+
+/// Hang the subtree of `id` under the ancestry of `parent_id` in `node_paths`,
+/// an unlinked subtree has no ancestors at all.
+fn move_paths(conn: &Connection, id: &NodeId, parent_id: &MagicNodeId) -> anyhow::Result<()> {
+    conn.execute(
+        "delete from node_paths where ancestor_id in (select ancestor_id from node_paths where node_id = ?1)
+and (node_id = ?1 or node_id in (select node_id from node_paths where ancestor_id = ?1))",
+        params![id],
+    )?;
+    if matches!(parent_id, MagicNodeId::Never) {
+        return Ok(());
+    }
+    conn.execute(
+        "insert into node_paths(ancestor_id, node_id, depth)
+select a.ancestor_id, s.node_id, a.depth + s.depth
+from (select ?2 as ancestor_id, 1 as depth
+union all select ancestor_id, depth + 1 from node_paths where node_id = ?2) a,
+(select ?1 as node_id, 0 as depth
+union all select node_id, depth from node_paths where ancestor_id = ?1) s",
+        params![id, parent_id],
+    )?;
+    Ok(())
+}
+
+/// A `NodeTx` holding one pooled connection inside `BEGIN IMMEDIATE`.
+///
+/// An unfinished transaction takes its connection out of the pool when
+/// dropped, sqlite rolls it back when the connection is closed.
+struct SqliteTx {
+    conn: Option<deadpool_sqlite::Object>,
+    node_fields: Vec<String>,
+    snapshot_distance: usize,
+}
+
+impl SqliteTx {
+    async fn interact<F, R>(&self, func: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Connection) -> anyhow::Result<R> + Send + 'static,
+        R: Send + 'static,
+    {
+        let conn = self.conn.as_ref().expect("transaction is already finished");
+        interact_with(conn, func).await
+    }
+
+    async fn query_node_row(&self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "select * from nodes where id = ?1",
+                    params![id],
+                    SqliteMapper::map_row_node,
+                )
+                .optional()?)
+        })
+        .await
+    }
+}
+
+impl Drop for SqliteTx {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            drop(deadpool_sqlite::Object::take(conn));
+        }
+    }
+}
+
+#[async_trait]
+impl NodeTx for SqliteTx {
+    fn snapshot_distance(&self) -> usize {
+        self.snapshot_distance
+    }
+
+    async fn lock_parents(&mut self, _parent_ids: &[&MagicNodeId]) -> anyhow::Result<()> {
+        // `BEGIN IMMEDIATE` already holds the database wide writer lock.
+        Ok(())
+    }
+
+    async fn lock_tree(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn query_nodes(&mut self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>> {
+        let query = node_filter.to_sql(&self.node_fields, SqlDialect::Sqlite);
+        self.interact(move |conn| query_nodes_with(conn, &query))
+            .await
+    }
+
+    async fn query_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        self.query_node_row(id).await
+    }
+
+    async fn lock_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>> {
+        self.query_node_row(id).await
+    }
+
+    async fn query_next_id(
+        &mut self,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<MagicNodeId> {
+        let parent = parent_id.clone();
+        let prev = prev_id.clone();
+        self.interact(move |conn| {
+            let next: Option<NodeId> = conn
+                .query_row(
+                    "select id from nodes where parent_id = ?1 and prev_sliding_id = ?2",
+                    params![parent, prev],
+                    |row| row.get("id"),
+                )
+                .optional()?;
+            Ok(next.map(|e| e.into()).unwrap_or_default())
+        })
+        .await
+    }
+
+    async fn update_relation(
+        &mut self,
+        id: &NodeId,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<u64> {
+        let id = id.clone();
+        let parent = parent_id.clone();
+        let prev = prev_id.clone();
+        self.interact(move |conn| {
+            let position = conn
+                .query_row(
+                    "select coalesce((select p.position + 1 from nodes p where p.id = ?2), 0) as position
+from nodes n where n.id = ?1",
+                    params![id, prev],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()?;
+            let position = match position {
+                Some(position) => position,
+                None => return Ok(0),
+            };
+
+            conn.execute(
+                "update nodes set position = position + 1 where parent_id = ?1 and position >= ?2 and id <> ?3",
+                params![parent, position, id],
+            )?;
+            let count = conn.execute(
+                "update nodes set prev_sliding_id = ?1, parent_id = ?2, position = ?3 where id = ?4",
+                params![prev, parent, position, id],
+            )? as u64;
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn move_paths(&mut self, id: &NodeId, parent_id: &MagicNodeId) -> anyhow::Result<()> {
+        let id = id.clone();
+        let parent_id = parent_id.clone();
+        self.interact(move |conn| move_paths(conn, &id, &parent_id))
+            .await
+    }
+
+    async fn update_prev_id(&mut self, id: &NodeId, prev_id: &MagicNodeId) -> anyhow::Result<u64> {
+        let id = id.clone();
+        let prev = prev_id.clone();
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "update nodes set prev_sliding_id = ?1 where id = ?2",
+                params![prev, id],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn insert_node_row(&mut self, node: &Node) -> anyhow::Result<u64> {
+        let node = node.clone();
+        self.interact(move |conn| {
+            let count = conn.execute(
+                "insert into nodes(id, name, content, node_type, domain, parent_id, prev_sliding_id, readonly, version_time, initial_time, delete_time, position) values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+                params![
+                    node.id,
+                    node.name,
+                    node.content,
+                    node.node_type.as_ref(),
+                    node.domain,
+                    node.parent_id,
+                    node.prev_sliding_id,
+                    node.readonly,
+                    node.version_time,
+                    // Stored in utc like the version time, so both sort as text.
+                    node.initial_time.with_timezone(&Utc),
+                    node.delete_time,
+                    node.position,
+                ],
+            )? as u64;
+
+            if !matches!(node.parent_id, MagicNodeId::Never) {
+                conn.execute(
+                    "insert into node_paths(ancestor_id, node_id, depth)
+select ?1, ?2, 1 union all select ancestor_id, ?2, depth + 1 from node_paths where node_id = ?1",
+                    params![node.parent_id, node.id],
+                )?;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn renumber_children(&mut self, parent_id: &MagicNodeId) -> anyhow::Result<u64> {
+        let parent_id = parent_id.clone();
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "with recursive chain(id, position) as (
+select id, 0 from nodes where parent_id = ?1 and prev_sliding_id = ?2
+union all
+select n.id, c.position + 1 from chain c join nodes n on n.parent_id = ?1 and n.prev_sliding_id = c.id
+)
+update nodes set position = c.position from chain c where nodes.id = c.id and nodes.position <> c.position",
+                params![parent_id, MagicNodeId::Empty],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn rebuild_tree(&mut self) -> anyhow::Result<u64> {
+        self.interact(move |conn| {
+            conn.execute("delete from node_paths", [])?;
+            // A parent cycle ends the walk down when it comes back to the ancestor.
+            conn.execute(
+                "insert into node_paths(ancestor_id, node_id, depth)
+with recursive paths(ancestor_id, node_id, depth) as (
+select parent_id, id, 1 from nodes where parent_id <> ?1
+union all
+select p.ancestor_id, n.id, p.depth + 1 from paths p join nodes n on n.parent_id = p.node_id where n.id <> p.ancestor_id
+)
+select ancestor_id, node_id, depth from paths",
+                params![MagicNodeId::Never],
+            )?;
+            conn.execute(
+                "with recursive chain(id, parent_id, position) as (
+select id, parent_id, 0 from nodes where prev_sliding_id = ?1 and parent_id <> ?2
+union all
+select n.id, n.parent_id, c.position + 1 from chain c join nodes n on n.parent_id = c.parent_id and n.prev_sliding_id = c.id
+)
+update nodes set position = c.position from chain c where nodes.id = c.id and nodes.position <> c.position",
+                params![MagicNodeId::Empty, MagicNodeId::Never],
+            )?;
+
+            let count: i64 = conn.query_row("select count(*) from nodes", [], |row| row.get(0))?;
+            Ok(count as u64)
+        })
+        .await
+    }
+
+    async fn update_node_row(&mut self, node: &Node) -> anyhow::Result<u64> {
+        let node = node.clone();
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "update nodes set name = ?1, content = ?2, node_type = ?3, domain = ?4, readonly = ?5, version_time = ?6 where id = ?7",
+                params![
+                    node.name,
+                    node.content,
+                    node.node_type.as_ref(),
+                    node.domain,
+                    node.readonly,
+                    node.version_time,
+                    node.id,
+                ],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn update_todo_status(
+        &mut self,
+        id: &NodeId,
+        todo_status: &Option<TodoEvent>,
+    ) -> anyhow::Result<u64> {
+        let id = id.clone();
+        let todo_status = todo_status.as_ref().map(|e| e.as_ref().to_string());
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "update nodes set todo_status = ?1 where id = ?2",
+                params![todo_status, id],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn copy_node_to_history(&mut self, id: &NodeId) -> anyhow::Result<u64> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "insert into nodes_history(id, name, content, node_type, domain, todo_status, delete_time, readonly, version_time, initial_time) select id, name, content, node_type, domain, todo_status, delete_time, readonly, version_time, initial_time from nodes where id = ?1",
+                params![id],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn query_version(
+        &mut self,
+        id: &NodeId,
+        version_time: &DateTime<Utc>,
+    ) -> anyhow::Result<Option<Node>> {
+        let (id, version_time) = (id.clone(), *version_time);
+        self.interact(move |conn| query_version_with(conn, &id, &version_time))
+            .await
+    }
+
+    async fn query_history_times(&mut self) -> anyhow::Result<Vec<(NodeId, DateTime<Utc>)>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare("select id, version_time from nodes_history")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            Ok(rows.collect::<Result<Vec<_>, _>>()?)
+        })
+        .await
+    }
+
+    async fn delete_history(
+        &mut self,
+        id: &NodeId,
+        version_times: &[DateTime<Utc>],
+    ) -> anyhow::Result<u64> {
+        let id = id.clone();
+        let version_times = version_times.to_vec();
+        self.interact(move |conn| {
+            let mut stmt =
+                conn.prepare("delete from nodes_history where id = ?1 and version_time = ?2")?;
+            let mut count = 0;
+            for version_time in version_times {
+                count += stmt.execute(params![id, version_time])? as u64;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn mark_deleted(&mut self, ids: &[NodeId], time: &DateTime<Utc>) -> anyhow::Result<u64> {
+        info!("delete flags: {:?}", ids);
+        let ids = ids.to_vec();
+        let time = *time;
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare("update nodes set delete_time = ?1 where id = ?2")?;
+            let mut count = 0;
+            for id in ids {
+                count += stmt.execute(params![time, id])? as u64;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn clear_deleted(&mut self, ids: &[NodeId]) -> anyhow::Result<u64> {
+        let ids = ids.to_vec();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare("update nodes set delete_time = null where id = ?1")?;
+            let mut count = 0;
+            for id in ids {
+                count += stmt.execute(params![id])? as u64;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn replace_recycle_origin(
+        &mut self,
+        id: &NodeId,
+        origin: &RecycleOrigin,
+    ) -> anyhow::Result<()> {
+        let (id, origin) = (id.clone(), origin.clone());
+        self.interact(move |conn| {
+            conn.execute(
+                "insert into recycle_bin(node_id, parent_id, prev_sliding_id) values (?1, ?2, ?3)
+on conflict (node_id) do update set parent_id = excluded.parent_id, prev_sliding_id = excluded.prev_sliding_id",
+                params![id, origin.parent_id, origin.prev_sliding_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn query_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<Option<RecycleOrigin>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            Ok(conn
+                .query_row(
+                    "select parent_id, prev_sliding_id from recycle_bin where node_id = ?1",
+                    params![id],
+                    |row| {
+                        Ok(RecycleOrigin {
+                            parent_id: row.get("parent_id")?,
+                            prev_sliding_id: row.get("prev_sliding_id")?,
+                        })
+                    },
+                )
+                .optional()?)
+        })
+        .await
+    }
+
+    async fn delete_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<u64> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            Ok(conn.execute("delete from recycle_bin where node_id = ?1", params![id])? as u64)
+        })
+        .await
+    }
+
+    async fn purge_node_rows(&mut self, ids: &[NodeId]) -> anyhow::Result<u64> {
+        let ids = ids.to_vec();
+        self.interact(move |conn| {
+            for sql in [
+                "delete from nodes_history where id = ?1",
+                "delete from tags where node_id = ?1",
+                "delete from node_links where source_id = ?1",
+                "delete from node_search_docs where node_id = ?1",
+                "delete from todos where node_id = ?1",
+                "delete from recycle_bin where node_id = ?1",
+                "delete from node_paths where node_id = ?1",
+            ] {
+                let mut stmt = conn.prepare(sql)?;
+                for id in ids.iter() {
+                    stmt.execute(params![id])?;
+                }
+            }
+            let mut stmt = conn.prepare("delete from nodes where id = ?1")?;
+            let mut count = 0;
+            for id in ids {
+                count += stmt.execute(params![id])? as u64;
+            }
+            Ok(count)
+        })
+        .await
+    }
+
+    async fn query_contents(&mut self, ids: &[NodeId]) -> anyhow::Result<Vec<String>> {
+        let ids = ids.to_vec();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(
+                "select content from nodes where id = ?1
+union all select content from nodes_history where id = ?1",
+            )?;
+            let mut contents = vec![];
+            for id in ids {
+                for content in stmt.query_map(params![id], |row| row.get("content"))? {
+                    contents.push(content?);
+                }
+            }
+            Ok(contents)
+        })
+        .await
+    }
+
+    async fn is_asset_referenced(&mut self, asset_id: &str) -> anyhow::Result<bool> {
+        let url = format!("/api/download/{}", asset_id);
+        self.interact(move |conn| {
+            Ok(conn.query_row(
+                "select exists(select 1 from nodes where instr(content, ?1) > 0)
+or exists(select 1 from nodes_history where instr(content, ?1) > 0)",
+                params![url],
+                |row| row.get(0),
+            )?)
+        })
+        .await
+    }
+
+    async fn delete_asset_row(&mut self, asset_id: &str) -> anyhow::Result<u64> {
+        let asset_id = asset_id.to_owned();
+        self.interact(move |conn| {
+            Ok(conn.execute("delete from assets where id = ?1", params![asset_id])? as u64)
+        })
+        .await
+    }
+
+    async fn copy_asset_row(&mut self, asset_id: &str, new_id: &str) -> anyhow::Result<u64> {
+        let (asset_id, new_id) = (asset_id.to_owned(), new_id.to_owned());
+        self.interact(move |conn| {
+            Ok(conn.execute(
+                "insert into assets(id, domain, ori_file_name, content_type, create_time) select ?2, domain, ori_file_name, content_type, ?3 from assets where id = ?1",
+                params![asset_id, new_id, Utc::now()],
+            )? as u64)
+        })
+        .await
+    }
+
+    async fn find_descendant_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(&sql_descendants("?1"))?;
+            let map = stmt
+                .query_map(params![id], |row| {
+                    Ok((row.get("id")?, row.get("parent_id")?))
+                })?
+                .collect::<Result<HashMap<NodeId, MagicNodeId>, rusqlite::Error>>()?;
+            Ok(map)
+        })
+        .await
+    }
+
+    async fn find_ancestor_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>> {
+        let id = id.clone();
+        self.interact(move |conn| {
+            let mut stmt = conn.prepare(&sql_ancestors("?1"))?;
+            let map = stmt
+                .query_map(params![id], |row| {
+                    Ok((row.get("id")?, row.get("parent_id")?))
+                })?
+                .collect::<Result<HashMap<NodeId, MagicNodeId>, rusqlite::Error>>()?;
+            Ok(map)
+        })
+        .await
+    }
+
+    async fn replace_tags(&mut self, id: &NodeId, tags: &[String]) -> anyhow::Result<()> {
+        let id = id.clone();
+        let tags = tags.to_vec();
+        self.interact(move |conn| {
+            conn.execute("delete from tags where node_id = ?1", params![id])?;
+            let mut stmt =
+                conn.prepare("insert or ignore into tags(node_id, tag) values (?1, ?2)")?;
+            for tag in tags {
+                stmt.execute(params![id, tag])?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn replace_links(&mut self, id: &NodeId, node_refs: &[NodeRef]) -> anyhow::Result<()> {
+        let id = id.clone();
+        let node_refs = node_refs.to_vec();
+        self.interact(move |conn| {
+            conn.execute("delete from node_links where source_id = ?1", params![id])?;
+            let mut stmt = conn.prepare(
+                "insert or ignore into node_links(source_id, target_id, position, text, snippet) values (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (position, node_ref) in node_refs.iter().enumerate() {
+                stmt.execute(params![
+                    id,
+                    node_ref.id,
+                    position as i64,
+                    node_ref.text,
+                    node_ref.snippet
+                ])?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn replace_search_text(
+        &mut self,
+        id: &NodeId,
+        name: &str,
+        text: &str,
+    ) -> anyhow::Result<()> {
+        let (id, name, text) = (id.clone(), name.to_owned(), text.to_owned());
+        self.interact(move |conn| {
+            conn.execute(
+                "insert into node_search_docs(node_id, name, body) values (?1, ?2, ?3)
+on conflict (node_id) do update set name = excluded.name, body = excluded.body",
+                params![id, name, text],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn query_tagged_ids(&mut self, tags: &[String]) -> anyhow::Result<Vec<NodeId>> {
+        let tags = tags.to_vec();
+        self.interact(move |conn| {
+            let placeholders = (1..=tags.len())
+                .map(|i| format!("?{}", i))
+                .collect::<Vec<String>>()
+                .join(", ");
+            let mut stmt = conn.prepare(&format!(
+                "select distinct node_id from tags where tag in ({}) order by node_id",
+                placeholders
+            ))?;
+            let ids = stmt
+                .query_map(params_from_iter(tags.iter()), |row| row.get("node_id"))?
+                .collect::<Result<Vec<NodeId>, rusqlite::Error>>()?;
+            Ok(ids)
+        })
+        .await
+    }
+
+    async fn commit(mut self: Box<Self>) -> anyhow::Result<()> {
+        // Failed or cancelled, the connection stays inside and drop detaches it.
+        self.interact(|conn| Ok(conn.execute_batch("COMMIT")?))
+            .await?;
+        self.conn.take();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TagMapper for SqliteMapper {
+    async fn list_tags(&self) -> anyhow::Result<Vec<TagStat>> {
+        self.interact(|conn| {
+            let mut stmt = conn.prepare(
+                "select t.tag, count(*) as count from tags t join nodes n on n.id = t.node_id where n.delete_time is null group by t.tag order by count desc, t.tag",
+            )?;
+            let tags = stmt
+                .query_map([], |row| {
+                    Ok(TagStat {
+                        name: row.get("tag")?,
+                        count: row.get("count")?,
+                    })
+                })?
+                .collect::<Result<Vec<TagStat>, rusqlite::Error>>()?;
+            Ok(tags)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl SearchMapper for SqliteMapper {
+    async fn search_index(&self, req: &SearchReq) -> anyhow::Result<Vec<SearchRow>> {
+        let (query, limit, offset) = (fts5_query(&req.query), req.limit(), req.offset());
+        self.interact(move |conn| {
+            // bm25 is lower for better matches, names weigh more than the body.
+            let mut stmt = conn.prepare(
+                "select d.node_id, d.name, d.body, -bm25(node_search, 10.0, 1.0) as rank
+from node_search join node_search_docs d on d.id = node_search.rowid
+join nodes n on n.id = d.node_id
+where node_search match ?1 and n.delete_time is null
+order by rank desc, n.version_time desc
+limit ?2 offset ?3",
+            )?;
+            let rows = stmt
+                .query_map(params![query, limit, offset], |row| {
+                    Ok(SearchRow {
+                        id: row.get("node_id")?,
+                        name: row.get("name")?,
+                        body: row.get("body")?,
+                        rank: row.get("rank")?,
+                    })
+                })?
+                .collect::<Result<Vec<SearchRow>, rusqlite::Error>>()?;
+            Ok(rows)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl RecycleMapper for SqliteMapper {
+    async fn query_recycle_origins(&self) -> anyhow::Result<HashMap<NodeId, RecycleOrigin>> {
+        self.interact(|conn| {
+            let mut stmt =
+                conn.prepare("select node_id, parent_id, prev_sliding_id from recycle_bin")?;
+            let origins = stmt
+                .query_map([], |row| {
+                    Ok((
+                        row.get("node_id")?,
+                        RecycleOrigin {
+                            parent_id: row.get("parent_id")?,
+                            prev_sliding_id: row.get("prev_sliding_id")?,
+                        },
+                    ))
+                })?
+                .collect::<Result<HashMap<NodeId, RecycleOrigin>, rusqlite::Error>>()?;
+            Ok(origins)
+        })
+        .await
+    }
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "init",
+        sql: "CREATE TABLE IF NOT EXISTS nodes (
+    id VARCHAR(40) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    content TEXT NOT NULL,
+    node_type VARCHAR(255) NOT NULL,
+    domain TEXT NOT NULL,
+    todo_status VARCHAR(10) default NULL,
+    delete_time TEXT DEFAULT NULL,
+    parent_id VARCHAR(40) NOT NULL,
+    prev_sliding_id VARCHAR(40) NOT NULL,
+    readonly BOOLEAN NOT NULL default false,
+    version_time TEXT NOT NULL,
+    initial_time TEXT NOT NULL,
+    primary key (id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_nodes_parent_id ON nodes (parent_id);
+
+CREATE TABLE IF NOT EXISTS nodes_history (
+    id VARCHAR(40) NOT NULL,
+    name VARCHAR(255) NOT NULL,
+    content TEXT NOT NULL,
+    node_type VARCHAR(255) NOT NULL,
+    domain TEXT NOT NULL,
+    todo_status VARCHAR(10) default NULL,
+    delete_time TEXT DEFAULT NULL,
+    readonly BOOLEAN NOT NULL default false,
+    version_time TEXT NOT NULL,
+    initial_time TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_nodes_history_id ON nodes_history (id);
+
+CREATE TABLE IF NOT EXISTS assets (
+    id VARCHAR(40) NOT NULL,
+    ori_file_name TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    create_time TEXT NOT NULL,
+    content_type TEXT,
+    primary key (id)
+);
+
+CREATE TABLE IF NOT EXISTS todos (
+    node_id VARCHAR(40) NOT NULL,
+    todo_status VARCHAR(10) default null,
+    create_type INTEGER not null default 0,
+    domain TEXT NOT NULL,
+    create_time TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_todos_node_id ON todos (node_id);",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 2,
+        name: "tags",
+        sql: "CREATE TABLE IF NOT EXISTS tags (
+    node_id VARCHAR(40) NOT NULL,
+    tag VARCHAR(255) NOT NULL,
+    primary key (node_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tags_tag ON tags (tag);",
+        reindex: true,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 3,
+        name: "node_links",
+        sql: "CREATE TABLE IF NOT EXISTS node_links (
+    source_id VARCHAR(40) NOT NULL,
+    target_id VARCHAR(40) NOT NULL,
+    position INTEGER NOT NULL default 0,
+    text TEXT NOT NULL,
+    snippet TEXT NOT NULL,
+    primary key (source_id, target_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_node_links_target_id ON node_links (target_id);",
+        reindex: true,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 4,
+        name: "node_search",
+        sql: "CREATE TABLE IF NOT EXISTS node_search_docs (
+    id INTEGER PRIMARY KEY,
+    node_id VARCHAR(40) NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    body TEXT NOT NULL
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS node_search USING fts5(
+    name, body, content='node_search_docs', content_rowid='id', tokenize='unicode61'
+);
+
+CREATE TRIGGER IF NOT EXISTS node_search_docs_ai AFTER INSERT ON node_search_docs BEGIN
+    INSERT INTO node_search(rowid, name, body) VALUES (new.id, new.name, new.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS node_search_docs_ad AFTER DELETE ON node_search_docs BEGIN
+    INSERT INTO node_search(node_search, rowid, name, body) VALUES ('delete', old.id, old.name, old.body);
+END;
+
+CREATE TRIGGER IF NOT EXISTS node_search_docs_au AFTER UPDATE ON node_search_docs BEGIN
+    INSERT INTO node_search(node_search, rowid, name, body) VALUES ('delete', old.id, old.name, old.body);
+    INSERT INTO node_search(rowid, name, body) VALUES (new.id, new.name, new.body);
+END;",
+        reindex: true,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 5,
+        name: "recycle_bin",
+        sql: "CREATE TABLE IF NOT EXISTS recycle_bin (
+    node_id VARCHAR(40) NOT NULL,
+    parent_id VARCHAR(40) NOT NULL,
+    prev_sliding_id VARCHAR(40) NOT NULL,
+    primary key (node_id)
+);",
+        reindex: false,
+        rebuild_tree: false,
+    },
+    Migration {
+        version: 6,
+        name: "node_paths",
+        sql: "CREATE TABLE IF NOT EXISTS node_paths (
+    ancestor_id VARCHAR(40) NOT NULL,
+    node_id VARCHAR(40) NOT NULL,
+    depth INTEGER NOT NULL,
+    primary key (ancestor_id, node_id)
+);
+CREATE INDEX IF NOT EXISTS idx_node_paths_node_id ON node_paths (node_id);
+ALTER TABLE nodes ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS idx_nodes_parent_id_position ON nodes (parent_id, position);
+DROP INDEX IF EXISTS idx_nodes_parent_id;",
+        reindex: false,
+        rebuild_tree: true,
+    },
+];
+
+#[async_trait]
+impl Mapper for SqliteMapper {
+    fn migrations(&self) -> &'static [Migration] {
+        MIGRATIONS
+    }
+
+    async fn init(&mut self) -> anyhow::Result<()> {
+        // The tree is rewritten with `update ... from`, new in sqlite 3.33. The
+        // bundled build has it, a system library linked instead may not.
+        if rusqlite::version_number() < 3_033_000 {
+            return log_and_err!(
+                "sqlite {} is too old, 3.33 or newer is needed",
+                rusqlite::version()
+            );
+        }
+        self.migrate().await?;
+
+        let nodes_fields = self.get_table_fields(constants::TABLE_NAME_NODES).await?;
+        self.node_fields.replace(nodes_fields);
+
+        Ok(())
+    }
+
+    async fn ensure_table_schema_migrations(&self) -> anyhow::Result<()> {
+        self.interact(|conn| {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    applied_time TEXT NOT NULL,
+    primary key (version)
+);",
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn query_schema_version(&self) -> anyhow::Result<Option<i64>> {
+        self.interact(|conn| {
+            Ok(conn.query_row(
+                "select max(version) as version from schema_migrations",
+                [],
+                |row| row.get("version"),
+            )?)
+        })
+        .await
+    }
+
+    async fn apply_migration(&self, migration: &Migration) -> anyhow::Result<()> {
+        let migration = migration.clone();
+        self.interact(move |conn| {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+            let applied = tx
+                .query_row(
+                    "select 1 from schema_migrations where version = ?1",
+                    params![migration.version],
+                    |_| Ok(()),
+                )
+                .optional()?;
+            if applied.is_some() {
+                return Ok(());
+            }
+
+            tx.execute_batch(migration.sql)?;
+            tx.execute(
+                "insert into schema_migrations(version, name, applied_time) values (?1, ?2, ?3)",
+                params![migration.version, migration.name, Utc::now()],
+            )?;
+
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_table_fields(&self, table_name: &str) -> anyhow::Result<Vec<String>> {
+        let table = table_name.to_string();
+        let fields: Vec<String> = self
+            .interact(move |conn| {
+                let mut stmt = conn.prepare("SELECT name FROM pragma_table_info(?1)")?;
+                let fields = stmt
+                    .query_map(params![table], |row| row.get("name"))?
+                    .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+                Ok(fields)
+            })
+            .await?;
+
+        if fields.is_empty() {
+            return log_and_err!("table {} has no fields.", table_name);
+        }
+        Ok(fields)
+    }
+}
+
+#[async_trait]
+impl TodoMapper for SqliteMapper {
+    async fn insert_todo_and_update(&self, req: &TodoCreateReq) -> anyhow::Result<()> {
+        let req = req.clone();
+        self.interact(move |conn| {
+            let todo_status = req.todo_event.as_ref().map(|e| e.as_ref().to_string());
+            if conn.execute(
+                "update nodes set todo_status = ?1 where id = ?2",
+                params![todo_status, req.id],
+            )? == 0
+            {
+                return log_and_err!("_delete_relation, there are no node with id: {:?}", req.id);
+            }
+
+            conn.execute(
+                "insert into todos(node_id, todo_status, create_type, domain, create_time) values(?1, ?2, ?3, ?4, ?5)",
+                params![
+                    req.id,
+                    todo_status,
+                    req.create_type.to_i32().unwrap(),
+                    "",
+                    Utc::now(),
+                ],
+            )?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+impl FromSql for NodeId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str().map(NodeId::from)
+    }
+}
+
+impl ToSql for NodeId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for MagicNodeId {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str().map(|s| s.to_string().into())
+    }
+}
+
+impl ToSql for MagicNodeId {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_ref()))
+    }
+}
+
+impl ToSql for SqlParam {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            SqlParam::Text(text) => ToSqlOutput::from(text.as_str()),
+            SqlParam::Int(int) => ToSqlOutput::from(*int),
+            SqlParam::Bool(bool) => ToSqlOutput::from(*bool),
+            SqlParam::Time(time) => time.to_sql()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, ops::Deref, path::PathBuf};
+
+    use chrono::Utc;
+
+    use crate::{
+        mapper::{
+            node::{
+                NodeCopyReq, NodeDeleteMode, NodeDeleteReq, NodeMapper, NodeMoveReq,
+                NodeUpdateContentReq,
+            },
+            nodefilter::{NodeFetchReq, NodeFilter, NodeSelection},
+            page::{NodePage, NodeSort, NodeSortKey},
+            path::NodePathReq,
+            recycle::{RecycleMapper, RecyclePurgeReq, RecycleRestoreReq},
+            reorder::NodeReorderReq,
+            search::{SearchMapper, SearchReq},
+            testutils::{
+                by_position, check_copy, check_delete_and_restore, check_move, fetch, id, ids,
+                insert_tree, node,
+            },
+            Mapper,
+        },
+        model::node::{MagicNodeId, Node, NodeId},
+    };
+
+    use super::{SqliteConfig, SqliteMapper};
+
+    /// A mapper on its own database file, removed again when dropped.
+    struct TempMapper {
+        mapper: SqliteMapper,
+        path: PathBuf,
+    }
+
+    impl Deref for TempMapper {
+        type Target = SqliteMapper;
+
+        fn deref(&self) -> &SqliteMapper {
+            &self.mapper
+        }
+    }
+
+    impl Drop for TempMapper {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    async fn mapper(name: &str) -> TempMapper {
+        let path =
+            std::env::temp_dir().join(format!("chnots-sqlite-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut mapper = SqliteMapper::new(SqliteConfig {
+            filepath: path.to_str().unwrap().to_owned(),
+        })
+        .unwrap();
+        mapper.init().await.unwrap();
+        TempMapper { mapper, path }
+    }
+
+    /// a
+    ///   c
+    ///     d
+    ///   b
+    async fn tree(name: &str) -> TempMapper {
+        let mapper = mapper(name).await;
+        insert_tree(&*mapper).await;
+        mapper
+    }
+
+    #[tokio::test]
+    async fn test_tree_and_move() {
+        check_move(&*tree("move").await).await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_and_restore() {
+        check_delete_and_restore(&*tree("restore").await).await;
+    }
+
+    #[tokio::test]
+    async fn test_copy_node() {
+        check_copy(&*tree("copy").await).await;
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let mapper = tree("search").await;
+        mapper
+            .update_node_content(&NodeUpdateContentReq {
+                id: "d".into(),
+                content: r#"{"type":"doc","content":[{"type":"paragraph","content":[{"type":"text","text":"Learning Rust"}]}]}"#.to_owned(),
+                version_time: Utc::now(),
+                base_version_time: None,
+            })
+            .await
+            .unwrap();
+
+        let req = SearchReq {
+            query: "rust".to_owned(),
+            limit: None,
+            offset: None,
+        };
+        let hits = mapper.search(&req).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id.as_str(), "d");
+        let path: Vec<&str> = hits[0].path.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(path, vec!["a", "c"]);
+        assert!(hits[0].snippet.contains("<mark>Rust</mark>"));
+
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        assert!(mapper.search(&req).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_positions() {
+        let mapper = tree("positions").await;
+        for (child, prev) in [("e", "b"), ("f", "c")] {
+            mapper
+                .insert_and_move(&node(child, id("a"), id(prev)))
+                .await
+                .unwrap();
+        }
+        assert_eq!(by_position(&*mapper, "a").await, vec!["c", "f", "b", "e"]);
+
+        mapper
+            .reorder_children(&NodeReorderReq {
+                parent_id: id("a"),
+                child_ids: vec!["b".into(), "e".into(), "c".into(), "f".into()],
+            })
+            .await
+            .unwrap();
+        assert_eq!(by_position(&*mapper, "a").await, vec!["b", "e", "c", "f"]);
+
+        mapper
+            .interact(|conn| {
+                Ok(conn.execute_batch("delete from node_paths; update nodes set position = 9;")?)
+            })
+            .await
+            .unwrap();
+        assert_eq!(mapper.rebuild_tree().await.unwrap(), 6);
+        assert_eq!(by_position(&*mapper, "a").await, vec!["b", "e", "c", "f"]);
+        assert_eq!(fetch(&*mapper, "f").await.position, 3);
+        assert_eq!(
+            ids(&*mapper, NodeFilter::Ancestor("d".into())).await,
+            vec!["a", "c"]
+        );
+    }
+
+    /// Everything derived from the ancestry, each id with its filters.
+    async fn ancestry(mapper: &SqliteMapper) -> Vec<(String, Vec<Vec<String>>)> {
+        let mut rows = mapper
+            .interact(|conn| {
+                let mut stmt = conn.prepare(
+                    "select ancestor_id, node_id, depth from node_paths order by ancestor_id, node_id",
+                )?;
+                let rows = stmt
+                    .query_map([], |row| {
+                        Ok(vec![
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, i64>(2)?.to_string(),
+                        ])
+                    })?
+                    .collect::<Result<Vec<Vec<String>>, rusqlite::Error>>()?;
+                Ok(rows)
+            })
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| ("node_paths".to_owned(), vec![e]))
+            .collect::<Vec<_>>();
+
+        let sorted = |ids: HashMap<NodeId, MagicNodeId>| {
+            let mut ids: Vec<String> = ids
+                .into_iter()
+                .map(|(id, parent_id)| format!("{}<{}", id.as_str(), parent_id.as_ref()))
+                .collect();
+            ids.sort();
+            ids
+        };
+        for id in ids(mapper, NodeFilter::All).await {
+            let node_id: NodeId = id.as_str().into();
+            rows.push((
+                id.clone(),
+                vec![
+                    ids(mapper, NodeFilter::Descendant(node_id.clone())).await,
+                    ids(mapper, NodeFilter::DescendantWithin(node_id.clone(), 1)).await,
+                    ids(mapper, NodeFilter::Ancestor(node_id.clone())).await,
+                    sorted(mapper.find_descendant_ids(&node_id).await.unwrap()),
+                    sorted(mapper.find_ancestor_ids(&node_id).await.unwrap()),
+                ],
+            ));
+        }
+        rows
+    }
+
+    async fn assert_ancestry(mapper: &SqliteMapper) {
+        let kept = ancestry(mapper).await;
+        mapper.rebuild_tree().await.unwrap();
+        assert_eq!(kept, ancestry(mapper).await);
+    }
+
+    #[tokio::test]
+    async fn test_ancestry_matches_rebuild() {
+        let mapper = tree("ancestry").await;
+        mapper
+            .insert_and_move(&node("e", id("d"), MagicNodeId::Empty))
+            .await
+            .unwrap();
+        assert_ancestry(&mapper).await;
+
+        let moves = [
+            ("c", id("b"), MagicNodeId::Empty),
+            ("b", MagicNodeId::Empty, id("a")),
+            ("d", id("a"), MagicNodeId::Empty),
+            ("c", id("a"), id("d")),
+            ("c", id("a"), MagicNodeId::Empty),
+        ];
+        for (child, parent_id, prev_sliding_id) in moves {
+            mapper
+                .move_nodes(&NodeMoveReq {
+                    id: child.into(),
+                    parent_id,
+                    prev_sliding_id,
+                })
+                .await
+                .unwrap();
+            assert_ancestry(&mapper).await;
+        }
+
+        mapper
+            .copy_node(&NodeCopyReq {
+                id: "b".into(),
+                parent_id: id("d"),
+                prev_sliding_id: MagicNodeId::Empty,
+                copy_assets: false,
+            })
+            .await
+            .unwrap();
+        assert_ancestry(&mapper).await;
+
+        for (child, mode) in [
+            ("d", NodeDeleteMode::PromoteChildren),
+            ("b", NodeDeleteMode::Subtree),
+        ] {
+            mapper
+                .delete_node(&NodeDeleteReq {
+                    id: child.into(),
+                    mode,
+                })
+                .await
+                .unwrap();
+            assert_ancestry(&mapper).await;
+        }
+
+        mapper
+            .restore_node(&RecycleRestoreReq {
+                id: "b".into(),
+                parent_id: Some(id("a")),
+                prev_sliding_id: None,
+            })
+            .await
+            .unwrap();
+        assert_ancestry(&mapper).await;
+
+        mapper
+            .purge_node(&RecyclePurgeReq { id: "d".into() })
+            .await
+            .unwrap();
+        assert_ancestry(&mapper).await;
+    }
+
+    #[tokio::test]
+    async fn test_page() {
+        let mapper = tree("page").await;
+        let req = NodeFetchReq {
+            selection: Some(vec![NodeSelection::Limit(2)]),
+            sort: Some(NodeSort {
+                key: NodeSortKey::TreeOrder,
+                desc: false,
+            }),
+            ..Default::default()
+        };
+        let ids = |page: &NodePage| -> Vec<String> {
+            page.nodes
+                .iter()
+                .map(|n| n.id.as_str().to_owned())
+                .collect()
+        };
+
+        let first = mapper.query_page(&req).await.unwrap();
+        assert_eq!(ids(&first), vec!["a", "c"]);
+
+        // The page goes on from the tree key of a deleted node.
+        mapper
+            .delete_node(&NodeDeleteReq {
+                id: "c".into(),
+                mode: NodeDeleteMode::Subtree,
+            })
+            .await
+            .unwrap();
+        let second = mapper
+            .query_page(&NodeFetchReq {
+                cursor: first.next_cursor,
+                ..req
+            })
+            .await
+            .unwrap();
+        assert_eq!(ids(&second), vec!["b"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_paths() {
+        let mapper = tree("paths").await;
+        mapper
+            .insert_and_move(&Node {
+                name: "c".to_owned(),
+                ..node("c2", id("a"), id("b"))
+            })
+            .await
+            .unwrap();
+
+        let resolve = |path: &str| NodePathReq {
+            path: path.to_owned(),
+        };
+        assert_eq!(
+            mapper
+                .resolve_path(&resolve("/a/c/d"))
+                .await
+                .unwrap()
+                .as_str(),
+            "d"
+        );
+        assert_eq!(
+            mapper
+                .resolve_path(&resolve("/a/c~2"))
+                .await
+                .unwrap()
+                .as_str(),
+            "c2"
+        );
+        assert!(mapper.resolve_path(&resolve("/a/c~3")).await.is_err());
+
+        let breadcrumb = mapper.query_breadcrumb(&"d".into()).await.unwrap();
+        assert_eq!(breadcrumb.path, "/a/c/d");
+        let breadcrumb = mapper.query_breadcrumb(&"c2".into()).await.unwrap();
+        assert_eq!(breadcrumb.path, "/a/c~2");
     }
 }
@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use chin_tools::log_and_err;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    model::{
+        node::{Node, NodeId},
+        tag::TagStat,
+    },
+    parser::tiptap_v1_parser::{normalize_tag, rename_hashtags},
+};
+
+use super::{node::NodeMapper, tx::NodeTx};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagRenameReq {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagMergeReq {
+    pub from: Vec<String>,
+    pub to: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TagUpdateRsp {
+    /// Nodes whose content was rewritten.
+    pub node_ids: Vec<NodeId>,
+}
+
+/// Tags live in the content of nodes, the `tags` table is only an index of
+/// them. So renaming a tag rewrites the hashtags of every node carrying it,
+/// readonly ones included, and the index follows the new content.
+#[async_trait]
+pub trait TagMapper: NodeMapper {
+    /// All tags of live nodes, the most used first.
+    async fn list_tags(&self) -> anyhow::Result<Vec<TagStat>>;
+
+    async fn rename_tag(&self, req: &TagRenameReq) -> anyhow::Result<TagUpdateRsp> {
+        let (from, to) = match (normalize_tag(&req.from), normalize_tag(&req.to)) {
+            (Some(from), Some(to)) if from != to => (from, to),
+            _ => return log_and_err!("unable to rename tag, {:?}", req),
+        };
+
+        let mut tx = self.begin().await?;
+        if !tx
+            .query_tagged_ids(std::slice::from_ref(&to))
+            .await?
+            .is_empty()
+        {
+            return log_and_err!("tag {} is already existed, merge them instead", to);
+        }
+        let node_ids = rewrite_tags(tx.as_mut(), &[from], &to).await?;
+        tx.commit().await?;
+
+        Ok(TagUpdateRsp { node_ids })
+    }
+
+    /// Replace all tags in `from` by `to`, which may be used already.
+    async fn merge_tags(&self, req: &TagMergeReq) -> anyhow::Result<TagUpdateRsp> {
+        let to = match normalize_tag(&req.to) {
+            Some(to) => to,
+            None => return log_and_err!("unable to merge tags, {:?}", req),
+        };
+        let from: Vec<String> = req
+            .from
+            .iter()
+            .filter_map(|e| normalize_tag(e))
+            .filter(|e| e != &to)
+            .collect();
+        if from.is_empty() {
+            return log_and_err!("unable to merge tags, {:?}", req);
+        }
+
+        let mut tx = self.begin().await?;
+        let node_ids = rewrite_tags(tx.as_mut(), &from, &to).await?;
+        tx.commit().await?;
+
+        Ok(TagUpdateRsp { node_ids })
+    }
+}
+
+async fn rewrite_tags(
+    tx: &mut dyn NodeTx,
+    from: &[String],
+    to: &str,
+) -> anyhow::Result<Vec<NodeId>> {
+    let mut node_ids = vec![];
+
+    for id in tx.query_tagged_ids(from).await? {
+        let node = match tx.lock_node(&id).await? {
+            Some(node) => node,
+            None => continue,
+        };
+
+        match rename_hashtags(&node.content, from, to)? {
+            Some(content) => {
+                tx.insert_node_only(&Node {
+                    content,
+                    version_time: Utc::now(),
+                    ..node
+                })
+                .await?;
+                node_ids.push(id);
+            }
+            // The index is stale, the content has no such tag anymore.
+            None => {
+                tx.index_content(&node).await?;
+            }
+        }
+    }
+
+    Ok(node_ids)
+}
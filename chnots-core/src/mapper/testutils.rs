@@ -0,0 +1,277 @@
+use chrono::Utc;
+
+use crate::{
+    model::{
+        node::{ContentParsedInfo, MagicNodeId, Node, NodeType},
+        todo::TodoEvent,
+    },
+    parser::toent::todoevent::TodoCreateType,
+};
+
+use super::{
+    node::{NodeCopyReq, NodeDeleteMode, NodeDeleteReq, NodeMapper, NodeMoveReq},
+    nodefilter::{NodeFetchReq, NodeFilter, NodeSelection},
+    page::{NodeSort, NodeSortKey},
+    recycle::{RecyclePurgeReq, RecycleRestoreReq},
+    todo::TodoCreateReq,
+    Mapper,
+};
+
+pub fn node(id: &str, parent_id: MagicNodeId, prev_sliding_id: MagicNodeId) -> Node {
+    Node {
+        id: id.into(),
+        delete_time: None,
+        name: id.to_owned(),
+        content: format!("content of {}", id),
+        node_type: NodeType::TiptapV1,
+        domain: "".to_owned(),
+        todo_status: None,
+        parsed_info: ContentParsedInfo::default(),
+        parent_id,
+        prev_sliding_id,
+        position: 0,
+        readonly: false,
+        version_time: Utc::now(),
+        initial_time: Utc::now().fixed_offset(),
+    }
+}
+
+pub fn id(id: &str) -> MagicNodeId {
+    MagicNodeId::Id(id.into())
+}
+
+/// a
+///   c
+///     d
+///   b
+pub async fn insert_tree(mapper: &(impl NodeMapper + Sync)) {
+    for (child, parent_id) in [
+        ("a", MagicNodeId::Empty),
+        ("b", id("a")),
+        ("c", id("a")),
+        ("d", id("c")),
+    ] {
+        mapper
+            .insert_and_move(&node(child, parent_id, MagicNodeId::Empty))
+            .await
+            .unwrap();
+    }
+}
+
+pub async fn fetch(mapper: &(impl NodeMapper + Sync), id: &str) -> Node {
+    mapper
+        .query_nodes(&NodeFetchReq {
+            selection: Some(vec![NodeSelection::WithContent, NodeSelection::WithHistory]),
+            filter: Some(NodeFilter::Id(id.into())),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .remove(0)
+}
+
+/// Sorted ids of the nodes matching `filter`, deleted ones included.
+pub async fn ids(mapper: &(impl NodeMapper + Sync), filter: NodeFilter) -> Vec<String> {
+    let mut ids: Vec<String> = mapper
+        .query_nodes(&NodeFetchReq {
+            selection: Some(vec![NodeSelection::WithHistory]),
+            filter: Some(filter),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.id.as_str().to_owned())
+        .collect();
+    ids.sort();
+    ids
+}
+
+/// Ids of the live children of `parent_id` by their order keys.
+pub async fn by_position(mapper: &(impl NodeMapper + Sync), parent_id: &str) -> Vec<String> {
+    mapper
+        .query_nodes(&NodeFetchReq {
+            selection: None,
+            filter: Some(NodeFilter::Children(parent_id.into())),
+            sort: Some(NodeSort {
+                key: NodeSortKey::Position,
+                desc: false,
+            }),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|e| e.id.as_str().to_owned())
+        .collect()
+}
+
+/// Move `c` around the tree of `insert_tree`, its subtree follows.
+pub async fn check_move(mapper: &impl Mapper) {
+    assert_eq!(fetch(mapper, "b").await.prev_sliding_id.as_ref(), "c");
+    assert_eq!(by_position(mapper, "a").await, vec!["c", "b"]);
+    assert_eq!(
+        ids(mapper, NodeFilter::Descendant("a".into())).await.len(),
+        3
+    );
+    assert_eq!(
+        ids(mapper, NodeFilter::Ancestor("d".into())).await,
+        vec!["a", "c"]
+    );
+
+    let rsp = mapper
+        .move_nodes(&NodeMoveReq {
+            id: "c".into(),
+            parent_id: id("a"),
+            prev_sliding_id: id("b"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(rsp.old.next_id.as_ref(), "b");
+    assert_eq!(
+        fetch(mapper, "b").await.prev_sliding_id.as_ref(),
+        "##Empty##"
+    );
+    assert_eq!(by_position(mapper, "a").await, vec!["b", "c"]);
+
+    // To the top level, the whole subtree follows.
+    mapper
+        .move_nodes(&NodeMoveReq {
+            id: "c".into(),
+            parent_id: MagicNodeId::Empty,
+            prev_sliding_id: id("a"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        ids(mapper, NodeFilter::Descendant("a".into())).await,
+        vec!["b"]
+    );
+    assert_eq!(
+        ids(mapper, NodeFilter::Ancestor("d".into())).await,
+        vec!["c"]
+    );
+    assert_eq!(
+        ids(mapper, NodeFilter::DescendantWithin("c".into(), 1)).await,
+        vec!["d"]
+    );
+
+    assert!(mapper
+        .move_nodes(&NodeMoveReq {
+            id: "c".into(),
+            parent_id: id("d"),
+            prev_sliding_id: MagicNodeId::Empty,
+        })
+        .await
+        .is_err());
+}
+
+/// Delete `c` of the tree of `insert_tree`, restore it, promote its children and
+/// purge it.
+pub async fn check_delete_and_restore(mapper: &impl Mapper) {
+    mapper
+        .delete_node(&NodeDeleteReq {
+            id: "c".into(),
+            mode: NodeDeleteMode::Subtree,
+        })
+        .await
+        .unwrap();
+    assert_eq!(by_position(mapper, "a").await, vec!["b"]);
+    assert_eq!(
+        fetch(mapper, "b").await.prev_sliding_id.as_ref(),
+        "##Empty##"
+    );
+    let entries = mapper.list_recycle_bin().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].node.id.as_str(), "c");
+    assert_eq!(entries[0].origin.as_ref().unwrap().parent_id.as_ref(), "a");
+
+    mapper
+        .restore_node(&RecycleRestoreReq {
+            id: "c".into(),
+            parent_id: None,
+            prev_sliding_id: None,
+        })
+        .await
+        .unwrap();
+    assert!(fetch(mapper, "d").await.delete_time.is_none());
+    assert_eq!(by_position(mapper, "a").await, vec!["c", "b"]);
+    assert_eq!(
+        ids(mapper, NodeFilter::Ancestor("d".into())).await,
+        vec!["a", "c"]
+    );
+    assert!(mapper.list_recycle_bin().await.unwrap().is_empty());
+
+    // The children take the place of their deleted parent.
+    mapper
+        .delete_node(&NodeDeleteReq {
+            id: "c".into(),
+            mode: NodeDeleteMode::PromoteChildren,
+        })
+        .await
+        .unwrap();
+    assert_eq!(by_position(mapper, "a").await, vec!["d", "b"]);
+    assert_eq!(
+        ids(mapper, NodeFilter::Ancestor("d".into())).await,
+        vec!["a"]
+    );
+
+    let purged = mapper
+        .purge_node(&RecyclePurgeReq { id: "c".into() })
+        .await
+        .unwrap();
+    assert_eq!(purged.node_ids.len(), 1);
+    assert!(mapper
+        .query_nodes(&NodeFetchReq {
+            selection: None,
+            filter: Some(NodeFilter::Id("c".into())),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+/// Copy `c` of the tree of `insert_tree` with a todo in its subtree.
+pub async fn check_copy(mapper: &impl Mapper) {
+    mapper
+        .insert_todo_and_update(&TodoCreateReq {
+            id: "d".into(),
+            todo_event: Some(TodoEvent::Doing),
+            create_type: TodoCreateType::Manual,
+        })
+        .await
+        .unwrap();
+
+    let rsp = mapper
+        .copy_node(&NodeCopyReq {
+            id: "c".into(),
+            parent_id: id("a"),
+            prev_sliding_id: id("b"),
+            copy_assets: false,
+        })
+        .await
+        .unwrap();
+    assert_eq!(rsp.node_ids.len(), 2);
+    let copied = |id: &str| rsp.node_ids[&id.into()].as_str().to_owned();
+
+    assert_eq!(
+        by_position(mapper, "a").await,
+        vec!["c".to_owned(), "b".to_owned(), copied("c")]
+    );
+    let d = fetch(mapper, &copied("d")).await;
+    assert_eq!(d.parent_id.as_ref(), copied("c"));
+    assert_eq!(d.content, "content of d");
+    assert_eq!(d.todo_status, Some(TodoEvent::Doing));
+    assert_eq!(fetch(mapper, &copied("c")).await.todo_status, None);
+    let mut ancestors = vec!["a".to_owned(), copied("c")];
+    ancestors.sort();
+    assert_eq!(
+        ids(mapper, NodeFilter::Ancestor(d.id.clone())).await,
+        ancestors
+    );
+    assert_eq!(
+        ids(mapper, NodeFilter::Descendant("a".into())).await.len(),
+        5
+    );
+}
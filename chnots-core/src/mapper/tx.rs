@@ -0,0 +1,739 @@
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use chin_tools::log_and_err;
+use chrono::{DateTime, Utc};
+use tracing::warn;
+
+use crate::{
+    model::{
+        node::{ContentParsedInfo, MagicNodeId, Node, NodeId, NodeRef},
+        todo::TodoEvent,
+    },
+    parser::{
+        self,
+        tiptap_v1_parser::{find_asset_ids, rewrite_refs},
+    },
+    utils::{diffutils, idutils},
+};
+
+use super::{
+    error::{MapperError, NodeConflict},
+    history::RetentionPolicy,
+    node::{
+        NodeCopyReq, NodeCopyRsp, NodeDeleteMode, NodeDeleteReq, NodeInsertResult, NodeMoveReq,
+        NodeMoveRsp, NodeRelation, NodeUpdateContentReq, NodeVersionReq,
+    },
+    nodefilter::{NodeFetchReq, NodeFilter, NodeSelection},
+    recycle::RecycleOrigin,
+};
+
+/// A mapper handle scoped to one database transaction.
+///
+/// Backends only provide the row level primitives, the tree mutations are
+/// composed from them by the default methods, so several mutations can be
+/// grouped into one atomic unit. Nothing is visible to other handles before
+/// `commit`, and dropping the handle without committing rolls it back.
+///
+/// The sibling list under a parent may only be changed while holding the
+/// lock of that parent, see `lock_parents`.
+#[async_trait]
+pub trait NodeTx: Send {
+    /// See `HistoryConfig::snapshot_distance`.
+    fn snapshot_distance(&self) -> usize;
+
+    /// Lock the sibling lists of `parent_ids` until the transaction ends.
+    async fn lock_parents(&mut self, parent_ids: &[&MagicNodeId]) -> anyhow::Result<()>;
+
+    /// Keep every other writer off the tree until the transaction ends, for
+    /// maintenance work which rewrites many sibling lists at once.
+    async fn lock_tree(&mut self) -> anyhow::Result<()>;
+
+    async fn query_nodes(&mut self, node_filter: &NodeFetchReq) -> anyhow::Result<Vec<Node>>;
+
+    /// Fetch a node with its content, deleted ones included.
+    async fn query_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>>;
+
+    /// Like `query_node`, but also lock the row until the transaction ends.
+    async fn lock_node(&mut self, id: &NodeId) -> anyhow::Result<Option<Node>>;
+
+    /// The node whose parent is `parent_id` and previous sibling is `prev_id`.
+    async fn query_next_id(
+        &mut self,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<MagicNodeId>;
+
+    /// Set the parent and the previous sibling of a node.
+    ///
+    /// The node takes the order key right after `prev_id`, the siblings from
+    /// there on move up by one. The ancestry is left to `move_paths`.
+    async fn update_relation(
+        &mut self,
+        id: &NodeId,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<u64>;
+
+    /// Hang the whole subtree of `id` under the ancestry of `parent_id`, once
+    /// the node was linked to another parent.
+    async fn move_paths(&mut self, id: &NodeId, parent_id: &MagicNodeId) -> anyhow::Result<()>;
+
+    /// Set the previous sibling only, the order key is untouched.
+    async fn update_prev_id(&mut self, id: &NodeId, prev_id: &MagicNodeId) -> anyhow::Result<u64>;
+
+    /// Insert the row as it is, order key included. The ancestry is taken from
+    /// the parent, which must be inserted before its children.
+    async fn insert_node_row(&mut self, node: &Node) -> anyhow::Result<u64>;
+
+    /// Give the children of `parent_id` the order keys `0..` of their linked
+    /// order, after the list was rewritten by several `update_relation`.
+    async fn renumber_children(&mut self, parent_id: &MagicNodeId) -> anyhow::Result<u64>;
+
+    /// Derive the ancestry and the order keys of every node from the
+    /// relations again, return the number of nodes.
+    async fn rebuild_tree(&mut self) -> anyhow::Result<u64>;
+
+    /// Overwrite the editable fields of a node, the relation is untouched.
+    async fn update_node_row(&mut self, node: &Node) -> anyhow::Result<u64>;
+
+    /// Set the todo status alone, without an entry in the log of todos.
+    async fn update_todo_status(
+        &mut self,
+        id: &NodeId,
+        todo_status: &Option<TodoEvent>,
+    ) -> anyhow::Result<u64>;
+
+    /// Snapshot the current row of a node into `nodes_history`.
+    async fn copy_node_to_history(&mut self, id: &NodeId) -> anyhow::Result<u64>;
+
+    /// The version of a node at `version_time`, the current row or a
+    /// snapshot of `nodes_history`, always with the current relation.
+    async fn query_version(
+        &mut self,
+        id: &NodeId,
+        version_time: &DateTime<Utc>,
+    ) -> anyhow::Result<Option<Node>>;
+
+    /// `version_time` of every snapshot in `nodes_history`.
+    async fn query_history_times(&mut self) -> anyhow::Result<Vec<(NodeId, DateTime<Utc>)>>;
+
+    async fn delete_history(
+        &mut self,
+        id: &NodeId,
+        version_times: &[DateTime<Utc>],
+    ) -> anyhow::Result<u64>;
+
+    async fn mark_deleted(&mut self, ids: &[NodeId], time: &DateTime<Utc>) -> anyhow::Result<u64>;
+
+    async fn clear_deleted(&mut self, ids: &[NodeId]) -> anyhow::Result<u64>;
+
+    /// Remember where a subtree was before it went to the recycle bin.
+    async fn replace_recycle_origin(
+        &mut self,
+        id: &NodeId,
+        origin: &RecycleOrigin,
+    ) -> anyhow::Result<()>;
+
+    async fn query_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<Option<RecycleOrigin>>;
+
+    async fn delete_recycle_origin(&mut self, id: &NodeId) -> anyhow::Result<u64>;
+
+    /// Delete the nodes with their history and every row derived from them.
+    async fn purge_node_rows(&mut self, ids: &[NodeId]) -> anyhow::Result<u64>;
+
+    /// The contents of the nodes, old versions included.
+    async fn query_contents(&mut self, ids: &[NodeId]) -> anyhow::Result<Vec<String>>;
+
+    /// Whether any node, or old version of one, refers to the asset.
+    async fn is_asset_referenced(&mut self, asset_id: &str) -> anyhow::Result<bool>;
+
+    async fn delete_asset_row(&mut self, asset_id: &str) -> anyhow::Result<u64>;
+
+    /// Insert a new asset row like the one of `asset_id`, 0 when there is none.
+    async fn copy_asset_row(&mut self, asset_id: &str, new_id: &str) -> anyhow::Result<u64>;
+
+    async fn find_descendant_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>>;
+
+    /// The node with its ancestors, each with its parent.
+    async fn find_ancestor_ids(
+        &mut self,
+        id: &NodeId,
+    ) -> anyhow::Result<HashMap<NodeId, MagicNodeId>>;
+
+    /// Replace all tags of a node.
+    async fn replace_tags(&mut self, id: &NodeId, tags: &[String]) -> anyhow::Result<()>;
+
+    /// Replace all outgoing links of a node.
+    async fn replace_links(&mut self, id: &NodeId, node_refs: &[NodeRef]) -> anyhow::Result<()>;
+
+    /// Replace the full text index entry of a node.
+    async fn replace_search_text(
+        &mut self,
+        id: &NodeId,
+        name: &str,
+        text: &str,
+    ) -> anyhow::Result<()>;
+
+    /// Nodes carrying any of `tags`, deleted ones included.
+    async fn query_tagged_ids(&mut self, tags: &[String]) -> anyhow::Result<Vec<NodeId>>;
+
+    async fn commit(self: Box<Self>) -> anyhow::Result<()>;
+
+    /// Lock the current parent of `id` together with `parent_ids` in a single
+    /// `lock_parents`.
+    ///
+    /// The parent is read before it is locked, so read it again and retry
+    /// when another transaction moved the node in the meantime.
+    async fn lock_relation(
+        &mut self,
+        id: &NodeId,
+        parent_ids: &[&MagicNodeId],
+    ) -> anyhow::Result<Node> {
+        let mut node = match self.query_node(id).await? {
+            Some(node) => node,
+            None => return log_and_err!("there are no node with id: {:?}", id),
+        };
+
+        loop {
+            let mut locked_ids = vec![&node.parent_id];
+            locked_ids.extend_from_slice(parent_ids);
+            self.lock_parents(&locked_ids).await?;
+            match self.query_node(id).await? {
+                Some(locked) if locked.parent_id.as_ref() == node.parent_id.as_ref() => {
+                    return Ok(locked)
+                }
+                Some(locked) => node = locked,
+                None => return log_and_err!("there are no node with id: {:?}", id),
+            }
+        }
+    }
+
+    /// Unlink the node from its sibling list, the parent must be locked.
+    async fn delete_relation(&mut self, node_id: &NodeId) -> anyhow::Result<NodeRelation> {
+        let node = match self.query_node(node_id).await? {
+            Some(node) => node,
+            None => return log_and_err!("there are no node with id: {:?}", node_id),
+        };
+        let parent_id = node.parent_id;
+        let prev_id = node.prev_sliding_id;
+        let next_id = self
+            .query_next_id(&parent_id, &node_id.clone().into())
+            .await?;
+
+        if let MagicNodeId::Id(next) = &next_id {
+            if self.update_prev_id(next, &prev_id).await? == 0 {
+                return log_and_err!("delete_relation, there are no node with id: {:?}", next);
+            }
+        }
+
+        if self
+            .update_relation(node_id, &MagicNodeId::Never, &MagicNodeId::Never)
+            .await?
+            == 0
+        {
+            return log_and_err!("delete_relation, there are no node with id: {:?}", node_id);
+        }
+
+        Ok(NodeRelation {
+            parent_id,
+            prev_id,
+            next_id,
+        })
+    }
+
+    /// Link the node after `prev_id` under `parent_id`, the parent must be locked.
+    async fn insert_relation(
+        &mut self,
+        node_id: &NodeId,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<NodeRelation> {
+        let next_id = self.query_next_id(parent_id, prev_id).await?;
+
+        if self.update_relation(node_id, parent_id, prev_id).await? == 0 {
+            return log_and_err!("insert_relation, there are no node with id: {:?}", node_id);
+        }
+
+        if let MagicNodeId::Id(next) = &next_id {
+            if self.update_prev_id(next, &node_id.clone().into()).await? == 0 {
+                return log_and_err!("insert_relation, there are no node with id: {:?}", next);
+            }
+        }
+
+        Ok(NodeRelation {
+            parent_id: parent_id.clone(),
+            prev_id: prev_id.clone(),
+            next_id,
+        })
+    }
+
+    async fn move_node(&mut self, req: &NodeMoveReq) -> anyhow::Result<NodeMoveRsp> {
+        self.lock_relation(&req.id, &[&req.parent_id]).await?;
+
+        let old = self.delete_relation(&req.id).await?;
+        let new = self
+            .insert_relation(&req.id, &req.parent_id, &req.prev_sliding_id)
+            .await?;
+        if old.parent_id.as_ref() != req.parent_id.as_ref() {
+            self.move_paths(&req.id, &req.parent_id).await?;
+        }
+
+        Ok(NodeMoveRsp { old, new })
+    }
+
+    /// Whether a node may be placed after `prev_id` under `parent_id`, the
+    /// parent must be locked.
+    async fn check_position(
+        &mut self,
+        parent_id: &MagicNodeId,
+        prev_id: &MagicNodeId,
+    ) -> anyhow::Result<()> {
+        let parent_live = match parent_id {
+            MagicNodeId::Empty => true,
+            MagicNodeId::Id(id) => self
+                .query_node(id)
+                .await?
+                .is_some_and(|e| e.delete_time.is_none()),
+            _ => false,
+        };
+        if !parent_live {
+            return Err(MapperError::InvalidParent(parent_id.clone()).into());
+        }
+
+        let prev_live = match prev_id {
+            MagicNodeId::Empty => true,
+            MagicNodeId::Id(id) => self.query_node(id).await?.is_some_and(|e| {
+                e.delete_time.is_none() && e.parent_id.as_ref() == parent_id.as_ref()
+            }),
+            _ => false,
+        };
+        if !prev_live {
+            return Err(MapperError::NotAChild {
+                id: prev_id.clone(),
+                parent_id: parent_id.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The sibling lists the moves depend on: the old and the new parent of
+    /// each node, and every list on the way up from the new parent, which a
+    /// crossing move putting an ancestor of the parent under the node changes.
+    async fn move_parent_ids(&mut self, reqs: &[NodeMoveReq]) -> anyhow::Result<Vec<MagicNodeId>> {
+        let mut parent_ids = vec![];
+        for req in reqs {
+            match self.query_node(&req.id).await? {
+                Some(node) => parent_ids.push(node.parent_id),
+                None => return log_and_err!("there are no node with id: {:?}", req.id),
+            }
+            parent_ids.push(req.parent_id.clone());
+            if let MagicNodeId::Id(parent_id) = &req.parent_id {
+                parent_ids.extend(self.find_ancestor_ids(parent_id).await?.into_values());
+            }
+        }
+        parent_ids.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+        parent_ids.dedup_by(|a, b| a.as_ref() == b.as_ref());
+        Ok(parent_ids)
+    }
+
+    /// Lock every sibling list the moves depend on in a single `lock_parents`,
+    /// so they are taken in one order and crossing moves never deadlock.
+    ///
+    /// The lists are read before they are locked, so read them again and
+    /// retry when another transaction moved a node in the meantime.
+    async fn lock_moves(&mut self, reqs: &[NodeMoveReq]) -> anyhow::Result<()> {
+        let mut parent_ids = self.move_parent_ids(reqs).await?;
+        loop {
+            self.lock_parents(&parent_ids.iter().collect::<Vec<_>>())
+                .await?;
+
+            let locked = self.move_parent_ids(reqs).await?;
+            let unchanged = locked.len() == parent_ids.len()
+                && locked
+                    .iter()
+                    .zip(parent_ids.iter())
+                    .all(|(a, b)| a.as_ref() == b.as_ref());
+            parent_ids = locked;
+            if unchanged {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Lock the relations `req` depends on and make sure the move keeps the
+    /// tree a tree, for moves asked by a client.
+    async fn check_move(&mut self, req: &NodeMoveReq) -> anyhow::Result<()> {
+        self.lock_moves(std::slice::from_ref(req)).await?;
+        let node = match self.query_node(&req.id).await? {
+            Some(node) => node,
+            None => return log_and_err!("there are no node with id: {:?}", req.id),
+        };
+        if node.delete_time.is_some() {
+            return Err(MapperError::Deleted(req.id.clone()).into());
+        }
+
+        if let MagicNodeId::Id(parent_id) = &req.parent_id {
+            let ancestors = self.find_ancestor_ids(parent_id).await?;
+            if ancestors.contains_key(&req.id) {
+                return Err(MapperError::IntoDescendant {
+                    id: req.id.clone(),
+                    parent_id: parent_id.clone(),
+                }
+                .into());
+            }
+        }
+
+        if req.prev_sliding_id.as_ref() == req.id.as_str() {
+            return Err(MapperError::NotAChild {
+                id: req.prev_sliding_id.clone(),
+                parent_id: req.parent_id.clone(),
+            }
+            .into());
+        }
+        self.check_position(&req.parent_id, &req.prev_sliding_id)
+            .await
+    }
+
+    /// Parse the content of a node and refresh the tables derived from it.
+    async fn index_content(&mut self, node: &Node) -> anyhow::Result<ContentParsedInfo> {
+        let parsed_info = parser::parse_content(node);
+
+        let tags: Vec<String> = parsed_info
+            .tags
+            .iter()
+            .flatten()
+            .map(|t| t.name.clone())
+            .collect();
+        self.replace_tags(&node.id, &tags).await?;
+
+        let node_refs: Vec<NodeRef> = parsed_info.node_refs.iter().flatten().cloned().collect();
+        self.replace_links(&node.id, &node_refs).await?;
+
+        let text = parsed_info.text.as_deref().unwrap_or_default();
+        self.replace_search_text(&node.id, &node.name, text).await?;
+
+        Ok(parsed_info)
+    }
+
+    /// Index every node again, for the derived tables created after the
+    /// content was written.
+    async fn reindex(&mut self) -> anyhow::Result<u64> {
+        self.lock_tree().await?;
+
+        let nodes = self
+            .query_nodes(&NodeFetchReq {
+                selection: Some(vec![NodeSelection::WithContent, NodeSelection::WithHistory]),
+                filter: Some(NodeFilter::All),
+                ..Default::default()
+            })
+            .await?;
+        for node in nodes.iter() {
+            self.index_content(node).await?;
+        }
+
+        Ok(nodes.len() as u64)
+    }
+
+    /// Insert a new node, or update an existing one without touching its
+    /// relation. The old version is kept in history when the content changed a lot.
+    async fn insert_node_only(&mut self, node: &Node) -> anyhow::Result<NodeInsertResult> {
+        match self.lock_node(&node.id).await? {
+            Some(old) => {
+                let (content, limit) = (node.content.clone(), self.snapshot_distance());
+                // Large notes take a while, keep them off the async threads.
+                let changed = tokio::task::spawn_blocking(move || {
+                    diffutils::distance_exceeds(&old.content, &content, limit)
+                })
+                .await?;
+                if changed {
+                    self.copy_node_to_history(&node.id).await?;
+                }
+                self.update_node_row(node).await?;
+            }
+            None => {
+                self.insert_node_row(node).await?;
+            }
+        }
+
+        let parsed_info = self.index_content(node).await?;
+        Ok(NodeInsertResult::ParsedInfo(parsed_info))
+    }
+
+    async fn insert_and_move(&mut self, node: &Node) -> anyhow::Result<NodeInsertResult> {
+        self.lock_parents(&[&node.parent_id]).await?;
+
+        if self.query_node(&node.id).await?.is_some() {
+            return log_and_err!("node {:?} is already existed", node.id);
+        }
+
+        self.insert_node_row(&Node {
+            parent_id: MagicNodeId::Never,
+            prev_sliding_id: MagicNodeId::Never,
+            ..node.clone()
+        })
+        .await?;
+
+        self.insert_relation(&node.id, &node.parent_id, &node.prev_sliding_id)
+            .await?;
+        self.move_paths(&node.id, &node.parent_id).await?;
+
+        let parsed_info = self.index_content(node).await?;
+        Ok(NodeInsertResult::ParsedInfo(parsed_info))
+    }
+
+    async fn update_node_content(
+        &mut self,
+        req: &NodeUpdateContentReq,
+    ) -> anyhow::Result<NodeInsertResult> {
+        match self.lock_node(&req.id).await? {
+            Some(node) if node.readonly => log_and_err!("node is readonly, {:?}", node),
+            Some(node) => {
+                if let Some(base) = req.base_version_time.filter(|e| *e < node.version_time) {
+                    let base = self.query_version(&req.id, &base).await?;
+                    return Err(MapperError::Conflict(Box::new(NodeConflict {
+                        id: req.id.clone(),
+                        base,
+                        current: node,
+                    }))
+                    .into());
+                }
+                self.insert_node_only(&Node {
+                    content: req.content.clone(),
+                    version_time: req.version_time,
+                    ..node
+                })
+                .await
+            }
+            None => log_and_err!("Unable to fetch node, {:?}", req),
+        }
+    }
+
+    async fn restore_version(&mut self, req: &NodeVersionReq) -> anyhow::Result<NodeInsertResult> {
+        let node = match self.lock_node(&req.id).await? {
+            Some(node) if node.readonly => return log_and_err!("node is readonly, {:?}", node.id),
+            Some(node) if node.delete_time.is_some() => {
+                return log_and_err!("node is deleted, {:?}", node.id)
+            }
+            Some(node) => node,
+            None => return log_and_err!("there are no node with id: {:?}", req.id),
+        };
+        if node.version_time == req.version_time {
+            return log_and_err!("version {} is the current one", req.version_time);
+        }
+        let content = match self.query_version(&req.id, &req.version_time).await? {
+            Some(version) => version.content,
+            None => {
+                return log_and_err!("node {:?} has no version at {}", req.id, req.version_time)
+            }
+        };
+
+        self.copy_node_to_history(&node.id).await?;
+        let node = Node {
+            content,
+            version_time: Utc::now(),
+            ..node
+        };
+        self.update_node_row(&node).await?;
+
+        let parsed_info = self.index_content(&node).await?;
+        Ok(NodeInsertResult::ParsedInfo(parsed_info))
+    }
+
+    /// Drop the snapshots `policy` does not keep, return how many are gone.
+    async fn compact_history(
+        &mut self,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<u64> {
+        let mut times: HashMap<NodeId, Vec<DateTime<Utc>>> = HashMap::new();
+        for (id, time) in self.query_history_times().await? {
+            times.entry(id).or_default().push(time);
+        }
+
+        let mut count = 0;
+        for (id, times) in times {
+            let expired = policy.expired(&times, now);
+            if !expired.is_empty() {
+                count += self.delete_history(&id, &expired).await?;
+            }
+        }
+        Ok(count)
+    }
+
+    async fn delete_node(&mut self, req: &NodeDeleteReq) -> anyhow::Result<()> {
+        // The children of a promoting node change their sibling list too.
+        let own_id: MagicNodeId = req.id.clone().into();
+        let mut parent_ids = vec![&MagicNodeId::RecycleBin];
+        if matches!(req.mode, NodeDeleteMode::PromoteChildren) {
+            parent_ids.push(&own_id);
+        }
+        let node = self.lock_relation(&req.id, &parent_ids).await?;
+        if matches!(node.parent_id, MagicNodeId::RecycleBin) {
+            return log_and_err!("node {:?} is already in the recycle bin", req.id);
+        }
+        self.replace_recycle_origin(
+            &req.id,
+            &RecycleOrigin {
+                parent_id: node.parent_id.clone(),
+                prev_sliding_id: node.prev_sliding_id.clone(),
+            },
+        )
+        .await?;
+
+        let all_ids: Vec<NodeId> = match req.mode {
+            NodeDeleteMode::Subtree => {
+                let descendants = self.find_descendant_ids(&req.id).await?;
+                let mut all_ids: HashSet<NodeId> = descendants.keys().cloned().collect();
+                all_ids.insert(req.id.clone());
+                all_ids.into_iter().collect()
+            }
+            NodeDeleteMode::PromoteChildren => {
+                self.promote_children(&node).await?;
+                vec![req.id.clone()]
+            }
+        };
+
+        self.mark_deleted(&all_ids, &Utc::now()).await?;
+
+        self.move_node(&NodeMoveReq {
+            id: req.id.clone(),
+            parent_id: MagicNodeId::RecycleBin,
+            prev_sliding_id: MagicNodeId::Empty,
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Move the children of `node` in front of it under its parent, keeping
+    /// their order. The parent of `node` and `node` itself must be locked.
+    async fn promote_children(&mut self, node: &Node) -> anyhow::Result<()> {
+        let own_id: MagicNodeId = node.id.clone().into();
+
+        let mut prev_id = node.prev_sliding_id.clone();
+        while let MagicNodeId::Id(child_id) =
+            self.query_next_id(&own_id, &MagicNodeId::Empty).await?
+        {
+            self.delete_relation(&child_id).await?;
+            self.insert_relation(&child_id, &node.parent_id, &prev_id)
+                .await?;
+            self.move_paths(&child_id, &node.parent_id).await?;
+            prev_id = child_id.into();
+        }
+        Ok(())
+    }
+
+    /// Copy the subtree of `req.id` after `req.prev_sliding_id` under
+    /// `req.parent_id`, every node with a new id and in the same order.
+    /// Backlinks between the copied nodes point to the copies.
+    async fn copy_node(&mut self, req: &NodeCopyReq) -> anyhow::Result<NodeCopyRsp> {
+        // Many sibling lists are read, none of them may change meanwhile.
+        self.lock_tree().await?;
+
+        self.check_position(&req.parent_id, &req.prev_sliding_id)
+            .await?;
+
+        let mut nodes = match self.query_node(&req.id).await? {
+            Some(node) if node.delete_time.is_none() => vec![node],
+            _ => return log_and_err!("there are no node with id: {:?}", req.id),
+        };
+        // Parents before their children, the copies are inserted in this order.
+        let descendants = self.find_descendant_ids(&req.id).await?;
+        let mut children: HashMap<&str, Vec<&NodeId>> = HashMap::new();
+        for (id, parent_id) in descendants.iter() {
+            children.entry(parent_id.as_ref()).or_default().push(id);
+        }
+        let mut ids = vec![&req.id];
+        let mut index = 0;
+        while let Some(id) = ids.get(index) {
+            if let Some(children) = children.get(id.as_str()) {
+                ids.extend(children.iter().copied());
+            }
+            index += 1;
+        }
+        for id in ids.into_iter().skip(1) {
+            match self.query_node(id).await? {
+                Some(node) => nodes.push(node),
+                None => return log_and_err!("there are no node with id: {:?}", id),
+            }
+        }
+
+        let node_ids: HashMap<String, String> = nodes
+            .iter()
+            .map(|e| (e.id.as_str().to_owned(), idutils::generate_uuid()))
+            .collect();
+        let mut asset_ids = HashMap::new();
+        if req.copy_assets {
+            for asset_id in nodes.iter().flat_map(|e| find_asset_ids(&e.content)) {
+                if asset_ids.contains_key(&asset_id) {
+                    continue;
+                }
+                let new_id = idutils::generate_uuid();
+                if self.copy_asset_row(&asset_id, &new_id).await? > 0 {
+                    asset_ids.insert(asset_id, new_id);
+                }
+            }
+        }
+
+        let copied_id = |id: &MagicNodeId| match id {
+            MagicNodeId::Id(id) => match node_ids.get(id.as_str()) {
+                Some(new_id) => MagicNodeId::Id(new_id.as_str().into()),
+                None => MagicNodeId::Id(id.clone()),
+            },
+            _ => id.clone(),
+        };
+        let now = Utc::now();
+        let mut copies = Vec::with_capacity(nodes.len());
+        for (index, node) in nodes.into_iter().enumerate() {
+            let content = match rewrite_refs(&node.content, &node_ids, &asset_ids) {
+                Ok(Some(content)) => content,
+                Ok(None) => node.content,
+                Err(err) => {
+                    warn!("unable to rewrite content of node {:?}: {}", node.id, err);
+                    node.content
+                }
+            };
+            // The root is linked at its new place below.
+            let (parent_id, prev_sliding_id) = if index == 0 {
+                (MagicNodeId::Never, MagicNodeId::Never)
+            } else {
+                (copied_id(&node.parent_id), copied_id(&node.prev_sliding_id))
+            };
+            let copy = Node {
+                id: node_ids[node.id.as_str()].as_str().into(),
+                content,
+                parent_id,
+                prev_sliding_id,
+                version_time: now,
+                initial_time: now.fixed_offset(),
+                ..node
+            };
+            self.insert_node_row(&copy).await?;
+            if copy.todo_status.is_some() {
+                self.update_todo_status(&copy.id, &copy.todo_status).await?;
+            }
+            copies.push(copy);
+        }
+
+        let id = copies[0].id.clone();
+        self.insert_relation(&id, &req.parent_id, &req.prev_sliding_id)
+            .await?;
+        self.move_paths(&id, &req.parent_id).await?;
+        for copy in copies.iter() {
+            self.index_content(copy).await?;
+        }
+
+        Ok(NodeCopyRsp {
+            id,
+            node_ids: node_ids
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect(),
+            asset_ids,
+        })
+    }
+}
@@ -7,7 +7,7 @@ use crate::constants::{MAGIC_EMPTY, MAGIC_NEVER, MAGIC_RECYCLE_BIN};
 
 use super::{tag::Tag, todo::TodoEvent};
 
-#[derive(Clone, Debug, EnumString, AsRefStr, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, EnumString, AsRefStr, Serialize, Deserialize)]
 pub enum NodeType {
     #[strum(serialize = "tiptap/v1")]
     #[serde(rename = "tiptap/v1")]
@@ -36,6 +36,11 @@ pub struct Node {
     #[serde(default)]
     pub prev_sliding_id: MagicNodeId,
 
+    /// Order key among the siblings, ascending in their linked order. It is
+    /// kept by the mapper, and may have gaps.
+    #[serde(default)]
+    pub position: i64,
+
     pub readonly: bool,
 
     pub version_time: DateTime<Utc>,
@@ -49,6 +54,35 @@ pub struct ContentParsedInfo {
     pub todo_status: Option<String>,
     #[serde(default)]
     pub tags: Option<Vec<Tag>>,
+    /// The content without any markup, blocks are separated by new lines.
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub mentions: Option<Vec<String>>,
+    /// Other nodes referred by the content.
+    #[serde(default)]
+    pub node_refs: Option<Vec<NodeRef>>,
+    #[serde(default)]
+    pub asset_ids: Option<Vec<String>>,
+    #[serde(default)]
+    pub tasks: Option<Vec<TaskItem>>,
+    /// Time expressions written inline, see `parser::toent`.
+    #[serde(default)]
+    pub toents: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeRef {
+    pub id: NodeId,
+    pub text: String,
+    /// Text of the block around the reference.
+    pub snippet: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TaskItem {
+    pub text: String,
+    pub checked: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -2,8 +2,12 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Tag {
-    name: String,
-    create_time: String,
+    pub name: String,
 }
 
-pub trait TagMapper {}
+/// A tag and the number of live nodes carrying it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TagStat {
+    pub name: String,
+    pub count: i64,
+}
@@ -1,7 +1,7 @@
 
 use strum::{AsRefStr, EnumIter, EnumString};
 
-#[derive(Clone, Debug, EnumString, AsRefStr, EnumIter)]
+#[derive(Clone, Debug, PartialEq, EnumString, AsRefStr, EnumIter)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum TodoEvent {
     Todo,
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use chin_tools::utils::pathutils::split_uuid_to_file_name;
 
@@ -13,3 +13,33 @@ pub fn asset_path_by_uuid(config: &Config, id: &str) -> PathBuf {
         .join(filename_parts.2);
     save_filepath
 }
+
+/// Remove the stored files of the assets, missing ones are skipped.
+pub async fn remove_asset_files(config: &Config, ids: &[String]) -> anyhow::Result<()> {
+    for id in ids {
+        match tokio::fs::remove_file(asset_path_by_uuid(config, id)).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Copy the stored files of the assets to the paths of their new ids, missing
+/// ones are skipped.
+pub async fn copy_asset_files(
+    config: &Config,
+    ids: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    for (id, new_id) in ids {
+        let target = asset_path_by_uuid(config, new_id);
+        if let Some(dir) = target.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        match tokio::fs::copy(asset_path_by_uuid(config, id), &target).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -2,3 +2,19 @@ pub mod toent;
 pub mod tiptap_v1_parser;
 pub mod possible;
 pub mod asset;
+
+use tracing::warn;
+
+use crate::model::node::{ContentParsedInfo, Node, NodeType};
+
+/// Extract what a node contains, content which can not be parsed contains nothing.
+pub fn parse_content(node: &Node) -> ContentParsedInfo {
+    let parsed = match node.node_type {
+        NodeType::TiptapV1 => tiptap_v1_parser::parse(&node.content),
+    };
+
+    parsed.unwrap_or_else(|err| {
+        warn!("unable to parse content of node {:?}: {}", node.id, err);
+        ContentParsedInfo::default()
+    })
+}
@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use serde_json::{Map, Value};
+
+use crate::model::{
+    node::{ContentParsedInfo, NodeRef, TaskItem},
+    tag::Tag,
+};
+
+const MARK_HASHTAG: &str = "hashtag";
+const MARK_REMINDER: &str = "reminder";
+const MARK_BACKLINK: &str = "backlink";
+const MARK_LINK: &str = "link";
+
+/// Nodes which start a new line in the plain text.
+const BLOCK_TYPES: [&str; 10] = [
+    "paragraph",
+    "heading",
+    "blockquote",
+    "codeBlock",
+    "listItem",
+    "taskItem",
+    "mathBlock",
+    "horizontalRule",
+    "image",
+    "tableRow",
+];
+
+/// Longest snippet kept for a node reference, in chars.
+const SNIPPET_CHARS: usize = 160;
+
+static ASSET_REGEX: Lazy<Regex> = regex_static::lazy_regex!(r#"/api/download/([^/?#\s"\\]+)"#);
+
+/// Parse the stored json of a `tiptap/v1` node.
+pub fn parse(content: &str) -> anyhow::Result<ContentParsedInfo> {
+    let doc: Value = serde_json::from_str(content)?;
+
+    let mut collector = Collector::default();
+    collector.visit(&doc);
+
+    Ok(collector.into_parsed_info())
+}
+
+#[derive(Default)]
+struct Collector {
+    text: String,
+    tags: Vec<String>,
+    mentions: Vec<String>,
+    node_refs: Vec<NodeRef>,
+    asset_ids: Vec<String>,
+    tasks: Vec<TaskItem>,
+    toents: Vec<String>,
+}
+
+impl Collector {
+    fn visit(&mut self, node: &Value) {
+        let node_type = node.get("type").and_then(|e| e.as_str()).unwrap_or("");
+        let attrs = node.get("attrs");
+        let attr = |name: &str| attrs.and_then(|a| a.get(name)).and_then(|e| e.as_str());
+
+        match node_type {
+            "text" => {
+                let text = node.get("text").and_then(|e| e.as_str()).unwrap_or("");
+                self.text.push_str(text);
+                self.visit_marks(text, node.get("marks"));
+            }
+            "hardBreak" => self.text.push('\n'),
+            "mention" => {
+                if let Some(id) = attr("id") {
+                    push_unique(&mut self.mentions, id.to_owned());
+                }
+                self.text.push('@');
+                self.text
+                    .push_str(attr("label").or(attr("id")).unwrap_or(""));
+            }
+            "image" => {
+                if let Some(src) = attr("src") {
+                    self.visit_url(src);
+                }
+            }
+            "taskItem" => {
+                // Nested lists are items of their own.
+                let mut item = Collector::default();
+                children(node)
+                    .filter(|c| c.get("type").and_then(|e| e.as_str()) != Some("taskList"))
+                    .for_each(|c| item.visit(c));
+
+                self.tasks.push(TaskItem {
+                    text: item.text.trim().to_owned(),
+                    checked: attrs
+                        .and_then(|a| a.get("checked"))
+                        .and_then(|e| e.as_bool())
+                        .unwrap_or(false),
+                });
+            }
+            _ => {}
+        }
+
+        let text_start = self.text.len();
+        let refs_start = self.node_refs.len();
+
+        children(node).for_each(|c| self.visit(c));
+
+        if BLOCK_TYPES.contains(&node_type) {
+            // Inner blocks come first, so a reference gets its closest block.
+            let block = self.text[text_start..].trim();
+            for node_ref in self.node_refs[refs_start..]
+                .iter_mut()
+                .filter(|r| r.snippet.is_empty())
+            {
+                node_ref.snippet = snippet_around(block, &node_ref.text, SNIPPET_CHARS);
+            }
+
+            if !self.text.is_empty() && !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+        }
+    }
+
+    fn visit_marks(&mut self, text: &str, marks: Option<&Value>) {
+        for mark in marks.and_then(|e| e.as_array()).into_iter().flatten() {
+            let attr = |name: &str| {
+                mark.get("attrs")
+                    .and_then(|a| a.get(name))
+                    .and_then(|e| e.as_str())
+            };
+
+            match mark.get("type").and_then(|e| e.as_str()) {
+                Some(MARK_HASHTAG) => {
+                    if let Some(tag) = normalize_tag(text) {
+                        push_unique(&mut self.tags, tag);
+                    }
+                }
+                Some(MARK_REMINDER) => {
+                    let toent = text.trim().trim_start_matches('%').trim();
+                    if !toent.is_empty() {
+                        self.toents.push(toent.to_owned());
+                    }
+                }
+                Some(MARK_BACKLINK) => {
+                    if let Some(id) = attr("chnothref").filter(|e| !e.is_empty()) {
+                        if !self.node_refs.iter().any(|r| r.id.as_str() == id) {
+                            self.node_refs.push(NodeRef {
+                                id: id.into(),
+                                text: text.trim().trim_start_matches('&').to_owned(),
+                                snippet: "".to_owned(),
+                            });
+                        }
+                    }
+                }
+                Some(MARK_LINK) => {
+                    if let Some(href) = attr("href") {
+                        self.visit_url(href);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn visit_url(&mut self, url: &str) {
+        if let Some(captures) = ASSET_REGEX.captures(url) {
+            push_unique(&mut self.asset_ids, captures[1].to_owned());
+        }
+    }
+
+    fn into_parsed_info(self) -> ContentParsedInfo {
+        ContentParsedInfo {
+            todo_status: None,
+            tags: Some(self.tags.into_iter().map(|name| Tag { name }).collect()),
+            text: Some(self.text.trim_end().to_owned()),
+            mentions: Some(self.mentions),
+            node_refs: Some(self.node_refs),
+            asset_ids: Some(self.asset_ids),
+            tasks: Some(self.tasks),
+            toents: Some(self.toents),
+        }
+    }
+}
+
+fn children(node: &Value) -> impl Iterator<Item = &Value> {
+    node.get("content")
+        .and_then(|e| e.as_array())
+        .into_iter()
+        .flatten()
+}
+
+/// At most `max_chars` of `text`, keeping `needle` near the middle.
+fn snippet_around(text: &str, needle: &str, max_chars: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= max_chars {
+        return text.to_owned();
+    }
+
+    let center = text
+        .find(needle)
+        .map(|pos| text[..pos].chars().count() + needle.chars().count() / 2)
+        .unwrap_or(0);
+    let start = center
+        .saturating_sub(max_chars / 2)
+        .min(chars.len() - max_chars);
+
+    chars[start..start + max_chars].iter().collect()
+}
+
+/// Assets referred to anywhere in a stored content, found in the raw text so
+/// that it works for any version of any node.
+pub fn find_asset_ids(content: &str) -> Vec<String> {
+    let mut asset_ids = vec![];
+    for captures in ASSET_REGEX.captures_iter(content) {
+        push_unique(&mut asset_ids, captures[1].to_owned());
+    }
+    asset_ids
+}
+
+fn push_unique(vec: &mut Vec<String>, value: String) {
+    if !vec.contains(&value) {
+        vec.push(value);
+    }
+}
+
+/// Rewrite every hashtag in `from` to `to`, other content is kept as it is.
+///
+/// Return `None` when no hashtag is changed.
+pub fn rename_hashtags(content: &str, from: &[String], to: &str) -> anyhow::Result<Option<String>> {
+    let mut doc: Value = serde_json::from_str(content)?;
+
+    let mut changed = false;
+    walk_text_mut(&mut doc, &mut |text, marks| {
+        if has_mark(marks, MARK_HASHTAG) {
+            if let Some(tag) = normalize_tag(text) {
+                if from.contains(&tag) {
+                    *text = format!("#{}", to);
+                    changed = true;
+                }
+            }
+        }
+    });
+
+    Ok(if changed {
+        Some(serde_json::to_string(&doc)?)
+    } else {
+        None
+    })
+}
+
+/// Point the backlinks and asset urls to the new ids found in the maps,
+/// other content is kept as it is.
+///
+/// Return `None` when nothing is changed.
+pub fn rewrite_refs(
+    content: &str,
+    node_ids: &HashMap<String, String>,
+    asset_ids: &HashMap<String, String>,
+) -> anyhow::Result<Option<String>> {
+    let mut doc: Value = serde_json::from_str(content)?;
+
+    let mut changed = false;
+    walk_attrs_mut(&mut doc, &mut |node_type, attrs| {
+        for (name, value) in attrs.iter_mut() {
+            let Value::String(value) = value else {
+                continue;
+            };
+            if node_type == Some(MARK_BACKLINK) && name == "chnothref" {
+                if let Some(id) = node_ids.get(value.as_str()) {
+                    *value = id.clone();
+                    changed = true;
+                }
+            } else if ASSET_REGEX.is_match(value) {
+                let rewritten =
+                    ASSET_REGEX.replace_all(value, |c: &Captures| match asset_ids.get(&c[1]) {
+                        Some(id) => format!("/api/download/{}", id),
+                        None => c[0].to_owned(),
+                    });
+                if rewritten != value.as_str() {
+                    *value = rewritten.into_owned();
+                    changed = true;
+                }
+            }
+        }
+    });
+
+    Ok(if changed {
+        Some(serde_json::to_string(&doc)?)
+    } else {
+        None
+    })
+}
+
+/// `#rust ` and `rust` are the same tag, an empty one is no tag.
+pub fn normalize_tag(text: &str) -> Option<String> {
+    let tag = text.trim().trim_start_matches('#').trim();
+    if tag.is_empty() {
+        None
+    } else {
+        Some(tag.to_owned())
+    }
+}
+
+fn has_mark(marks: Option<&Value>, mark_type: &str) -> bool {
+    marks.and_then(|e| e.as_array()).is_some_and(|marks| {
+        marks
+            .iter()
+            .any(|m| m.get("type").and_then(|t| t.as_str()) == Some(mark_type))
+    })
+}
+
+fn walk_text_mut<F>(node: &mut Value, func: &mut F)
+where
+    F: FnMut(&mut String, Option<&Value>),
+{
+    if let Some(obj) = node.as_object_mut() {
+        let marks = obj.get("marks").cloned();
+        if let Some(Value::String(text)) = obj.get_mut("text") {
+            func(text, marks.as_ref());
+        }
+
+        if let Some(Value::Array(children)) = obj.get_mut("content") {
+            for child in children {
+                walk_text_mut(child, func);
+            }
+        }
+    }
+}
+
+/// Visit the attrs of every node and mark, with the type of their owner.
+fn walk_attrs_mut<F>(node: &mut Value, func: &mut F)
+where
+    F: FnMut(Option<&str>, &mut Map<String, Value>),
+{
+    if let Some(obj) = node.as_object_mut() {
+        let node_type = obj.get("type").and_then(|e| e.as_str()).map(str::to_owned);
+        if let Some(Value::Object(attrs)) = obj.get_mut("attrs") {
+            func(node_type.as_deref(), attrs);
+        }
+
+        for key in ["marks", "content"] {
+            if let Some(Value::Array(children)) = obj.get_mut(key) {
+                for child in children {
+                    walk_attrs_mut(child, func);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::model::node::TaskItem;
+
+    use super::{find_asset_ids, parse, rename_hashtags, rewrite_refs, snippet_around};
+
+    const DOC: &str = r##"{"type":"doc","content":[{"type":"paragraph","content":[
+        {"type":"text","text":"#rust","marks":[{"type":"hashtag"}]},
+        {"type":"text","text":" and #notatag "},
+        {"type":"text","text":"#db","marks":[{"type":"bold"},{"type":"hashtag"}]},
+        {"type":"text","text":"#rust","marks":[{"type":"hashtag"}]}
+    ]}]}"##;
+
+    const FULL_DOC: &str = r##"{"type":"doc","content":[
+        {"type":"heading","attrs":{"level":1},"content":[{"type":"text","text":"Title"}]},
+        {"type":"paragraph","content":[
+            {"type":"text","text":"see "},
+            {"type":"text","text":"&Other","marks":[{"type":"backlink","attrs":{"chnothref":"node-2"}}]},
+            {"type":"text","text":" ask "},
+            {"type":"mention","attrs":{"id":"u1","label":"bob"}},
+            {"type":"hardBreak"},
+            {"type":"text","text":"%tomorrow 9am","marks":[{"type":"reminder"}]},
+            {"type":"text","text":"file","marks":[{"type":"link","attrs":{"href":"http://host/api/download/asset-2?x=1"}}]}
+        ]},
+        {"type":"image","attrs":{"src":"http://host:3011/api/download/asset-1"}},
+        {"type":"taskList","content":[
+            {"type":"taskItem","attrs":{"checked":true},"content":[
+                {"type":"paragraph","content":[{"type":"text","text":"done it"}]},
+                {"type":"taskList","content":[
+                    {"type":"taskItem","attrs":{"checked":false},"content":[
+                        {"type":"paragraph","content":[{"type":"text","text":"sub task"}]}
+                    ]}
+                ]}
+            ]}
+        ]}
+    ]}"##;
+
+    #[test]
+    fn test_tags() {
+        let tags: Vec<String> = parse(DOC)
+            .unwrap()
+            .tags
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(tags, vec!["rust", "db"]);
+
+        assert!(parse("not json").is_err());
+    }
+
+    #[test]
+    fn test_parse() {
+        let info = parse(FULL_DOC).unwrap();
+
+        assert_eq!(
+            info.text.unwrap(),
+            "Title\nsee &Other ask @bob\n%tomorrow 9amfile\ndone it\nsub task"
+        );
+        assert_eq!(info.mentions.unwrap(), vec!["u1"]);
+
+        let node_refs = info.node_refs.unwrap();
+        assert_eq!(node_refs.len(), 1);
+        assert_eq!(node_refs[0].id.as_str(), "node-2");
+        assert_eq!(node_refs[0].text, "Other");
+        assert_eq!(
+            node_refs[0].snippet,
+            "see &Other ask @bob\n%tomorrow 9amfile"
+        );
+
+        assert_eq!(info.asset_ids.unwrap(), vec!["asset-2", "asset-1"]);
+        assert_eq!(find_asset_ids(FULL_DOC), vec!["asset-2", "asset-1"]);
+        assert_eq!(info.toents.unwrap(), vec!["tomorrow 9am"]);
+        assert_eq!(
+            info.tasks.unwrap(),
+            vec![
+                TaskItem {
+                    text: "done it".to_owned(),
+                    checked: true
+                },
+                TaskItem {
+                    text: "sub task".to_owned(),
+                    checked: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snippet() {
+        assert_eq!(snippet_around("short", "x", 10), "short");
+        assert_eq!(snippet_around("0123456789abcdef", "8", 6), "56789a");
+        assert_eq!(snippet_around("0123456789abcdef", "f", 6), "abcdef");
+        assert_eq!(snippet_around("0123456789abcdef", "none", 6), "012345");
+        assert_eq!(snippet_around("一二三四五六", "五", 3), "四五六");
+    }
+
+    #[test]
+    fn test_rename() {
+        let renamed = rename_hashtags(DOC, &["rust".to_owned()], "lang")
+            .unwrap()
+            .unwrap();
+        let tags: Vec<String> = parse(&renamed)
+            .unwrap()
+            .tags
+            .unwrap()
+            .into_iter()
+            .map(|t| t.name)
+            .collect();
+        assert_eq!(tags, vec!["lang", "db"]);
+        assert!(renamed.contains(" and #notatag "));
+
+        assert!(rename_hashtags(DOC, &["go".to_owned()], "lang")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_rewrite_refs() {
+        let node_ids = HashMap::from([("node-2".to_owned(), "node-3".to_owned())]);
+        let asset_ids = HashMap::from([("asset-1".to_owned(), "asset-9".to_owned())]);
+
+        let rewritten = rewrite_refs(FULL_DOC, &node_ids, &asset_ids)
+            .unwrap()
+            .unwrap();
+        let info = parse(&rewritten).unwrap();
+        assert_eq!(info.node_refs.unwrap()[0].id.as_str(), "node-3");
+        assert_eq!(info.asset_ids.unwrap(), vec!["asset-2", "asset-9"]);
+        assert!(rewritten.contains("http://host:3011/api/download/asset-9"));
+
+        assert!(rewrite_refs(FULL_DOC, &HashMap::new(), &HashMap::new())
+            .unwrap()
+            .is_none());
+    }
+}
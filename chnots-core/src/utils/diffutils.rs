@@ -0,0 +1,285 @@
+use serde::{Deserialize, Serialize};
+
+/// Edits beyond this are not worth the search, the changed part is shown as
+/// replaced as a whole then.
+const MAX_EDITS: usize = 2000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Equal,
+    Insert,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DiffLine {
+    pub op: DiffOp,
+    pub text: String,
+}
+
+/// Diff `old` and `new` line by line.
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    diff(&old, &new)
+        .into_iter()
+        .map(|(op, text)| DiffLine {
+            op,
+            text: text.to_owned(),
+        })
+        .collect()
+}
+
+/// The shortest edit script turning `old` into `new`, by the Myers algorithm.
+pub fn diff<T: PartialEq + Clone>(old: &[T], new: &[T]) -> Vec<(DiffOp, T)> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<(DiffOp, T)> = old[..prefix]
+        .iter()
+        .map(|e| (DiffOp::Equal, e.clone()))
+        .collect();
+    match myers(a, b) {
+        Some(middle) => ops.extend(middle),
+        None => {
+            ops.extend(a.iter().map(|e| (DiffOp::Delete, e.clone())));
+            ops.extend(b.iter().map(|e| (DiffOp::Insert, e.clone())));
+        }
+    }
+    ops.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|e| (DiffOp::Equal, e.clone())),
+    );
+    ops
+}
+
+/// Whether the Levenshtein distance of `old` and `new` is over `limit`.
+///
+/// After cutting the common prefix and suffix only the diagonals within
+/// `limit` of each other are computed, which keeps it linear in the length of
+/// the texts instead of quadratic.
+pub fn distance_exceeds(old: &str, new: &str, limit: usize) -> bool {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    if a.len().abs_diff(b.len()) > limit {
+        return true;
+    }
+
+    // `row[d]` is the distance of `a[..i]` and `b[..i + d - limit]`, capped
+    // at `over`. Cells off the band cost more than `limit` anyway.
+    let (n, m, over) = (a.len(), b.len(), limit + 1);
+    let width = 2 * limit + 1;
+    let mut prev: Vec<usize> = (0..width)
+        .map(|d| match d.checked_sub(limit) {
+            Some(j) if j <= m => j,
+            _ => over,
+        })
+        .collect();
+    let mut cur = vec![over; width];
+    for i in 1..=n {
+        let mut row_min = over;
+        for d in 0..width {
+            cur[d] = match (i + d).checked_sub(limit) {
+                Some(0) => i,
+                Some(j) if j <= m => {
+                    let replace = prev[d] + (a[i - 1] != b[j - 1]) as usize;
+                    let delete = prev.get(d + 1).map_or(over, |e| e + 1);
+                    let insert = if d > 0 { cur[d - 1] + 1 } else { over };
+                    replace.min(delete).min(insert)
+                }
+                _ => over,
+            }
+            .min(over);
+            row_min = row_min.min(cur[d]);
+        }
+        // Rows never get cheaper going down.
+        if row_min > limit {
+            return true;
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[m + limit - n] > limit
+}
+
+/// `None` when it takes more than `MAX_EDITS` edits.
+fn myers<T: PartialEq + Clone>(a: &[T], b: &[T]) -> Option<Vec<(DiffOp, T)>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (a.len() + b.len()).min(MAX_EDITS) as isize;
+
+    // `v[k + offset]` is the furthest x reached on the diagonal k = x - y.
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * offset as usize + 1];
+    // The diagonals `-d - 1..=d + 1` of `v` before the d-th edit.
+    let mut trace: Vec<Vec<isize>> = vec![];
+
+    let mut found = false;
+    'search: for d in 0..=max {
+        trace.push(v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                found = true;
+                break 'search;
+            }
+        }
+    }
+    if !found {
+        return None;
+    }
+
+    let mut ops = vec![];
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let at = |k: isize| trace[d as usize][(k + d + 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            ops.push((DiffOp::Equal, a[(x - 1) as usize].clone()));
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            if x == prev_x {
+                ops.push((DiffOp::Insert, b[prev_y as usize].clone()));
+            } else {
+                ops.push((DiffOp::Delete, a[prev_x as usize].clone()));
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    Some(ops)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff, diff_lines, distance_exceeds, DiffOp};
+
+    fn sides(ops: &[(DiffOp, char)]) -> (String, String) {
+        let old = ops
+            .iter()
+            .filter(|e| e.0 != DiffOp::Insert)
+            .map(|e| e.1)
+            .collect();
+        let new = ops
+            .iter()
+            .filter(|e| e.0 != DiffOp::Delete)
+            .map(|e| e.1)
+            .collect();
+        (old, new)
+    }
+
+    #[test]
+    fn test_diff() {
+        for (old, new, edits) in [
+            ("ABCABBA", "CBABAC", 5),
+            ("", "abc", 3),
+            ("abc", "", 3),
+            ("same", "same", 0),
+            (
+                "a long prefix, x, and suffix",
+                "a long prefix, y, and suffix",
+                2,
+            ),
+        ] {
+            let old: Vec<char> = old.chars().collect();
+            let new: Vec<char> = new.chars().collect();
+            let ops = diff(&old, &new);
+
+            let (o, n) = sides(&ops);
+            assert_eq!(o, old.iter().collect::<String>());
+            assert_eq!(n, new.iter().collect::<String>());
+            assert_eq!(ops.iter().filter(|e| e.0 != DiffOp::Equal).count(), edits);
+        }
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let lines = diff_lines("title\nold line\nend", "title\nnew line\nend\nmore");
+        let ops: Vec<(DiffOp, &str)> = lines.iter().map(|e| (e.op, e.text.as_str())).collect();
+        assert_eq!(
+            ops,
+            vec![
+                (DiffOp::Equal, "title"),
+                (DiffOp::Delete, "old line"),
+                (DiffOp::Insert, "new line"),
+                (DiffOp::Equal, "end"),
+                (DiffOp::Insert, "more"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_distance_exceeds() {
+        // A small LCG, the texts only need to be arbitrary and repeatable.
+        let mut seed = 7u64;
+        let mut text = |len: usize| -> String {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    ['a', 'b', 'c', '表'][(seed >> 33) as usize % 4]
+                })
+                .collect()
+        };
+
+        for _ in 0..200 {
+            let (old, new) = (text(12), text(9));
+            let head: String = old.chars().take(4).collect();
+            let new = format!("shared {}{} tail", head, new);
+            let old = format!("shared {} tail", old);
+            let distance = distance::levenshtein(&old, &new);
+            for limit in 0..16 {
+                assert_eq!(
+                    distance_exceeds(&old, &new, limit),
+                    distance > limit,
+                    "{:?} {:?} {}",
+                    old,
+                    new,
+                    limit
+                );
+            }
+        }
+        assert!(!distance_exceeds("", "", 0));
+        assert!(distance_exceeds("", "abc", 2));
+        assert!(!distance_exceeds("abc", "", 3));
+    }
+}
@@ -1,4 +1,5 @@
 pub mod colutils;
+pub mod diffutils;
 pub mod idutils;
 #[macro_use]
 pub mod marcos;
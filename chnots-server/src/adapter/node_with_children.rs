@@ -1,9 +1,6 @@
 use std::collections::{HashMap, HashSet};
 
-use kcore::{
-    model::node::{MagicNodeId, Node, NodeId},
-    utils::colutils::sort_with_precessors,
-};
+use kcore::model::node::{MagicNodeId, Node, NodeId};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -11,39 +8,50 @@ pub struct NodeWithChildren {
     #[serde(flatten)]
     node: Node,
     children: Vec<Box<NodeWithChildren>>,
+    /// Live children in the database, loaded or not.
+    child_count: u64,
+    has_children: bool,
 }
 
 fn to_children(
     relation_map: &mut HashMap<&NodeId, (&Node, Vec<&Node>)>,
+    child_counts: &HashMap<NodeId, u64>,
     nid: &NodeId,
 ) -> anyhow::Result<NodeWithChildren> {
     let (n, children) = relation_map.remove(nid).unwrap();
     let mut t1: Vec<Box<NodeWithChildren>> = vec![];
     for child in children.iter() {
-        t1.push(Box::new(to_children(relation_map, &child.id)?));
+        t1.push(Box::new(to_children(
+            relation_map,
+            child_counts,
+            &child.id,
+        )?));
     }
+    let child_count = child_counts
+        .get(nid)
+        .copied()
+        .unwrap_or_default()
+        .max(t1.len() as u64);
 
-    let children = sort_with_precessors(
-        t1,
-        |e| e.node.id.clone(),
-        |e| match &e.node.prev_sliding_id {
-            kcore::model::node::MagicNodeId::RecycleBin => None,
-            kcore::model::node::MagicNodeId::Empty => None,
-            kcore::model::node::MagicNodeId::Never => None,
-            kcore::model::node::MagicNodeId::Id(id) => Some(id.clone()),
-        },
-        |e| e.node.version_time.clone(),
-    )?;
+    t1.sort_by(|a, b| order_key(&a.node).cmp(&order_key(&b.node)));
 
     let nc = NodeWithChildren {
         node: n.clone(),
-        children,
+        children: t1,
+        child_count,
+        has_children: child_count > 0,
     };
 
     Ok(nc)
 }
 
-pub fn nodes_with_childrens(nodes: Vec<Node>) -> anyhow::Result<Vec<NodeWithChildren>> {
+/// Build the trees of `nodes`, a node whose parent is not loaded becomes a
+/// root. `child_counts` covers children that are not loaded, the loaded ones
+/// are counted without it.
+pub fn nodes_with_childrens(
+    nodes: Vec<Node>,
+    child_counts: &HashMap<NodeId, u64>,
+) -> anyhow::Result<Vec<NodeWithChildren>> {
     let mut relation_map: HashMap<&NodeId, (&Node, Vec<&Node>)> = HashMap::new();
     let mut top_lvl_ids: HashSet<&NodeId> = HashSet::new();
 
@@ -76,26 +84,23 @@ pub fn nodes_with_childrens(nodes: Vec<Node>) -> anyhow::Result<Vec<NodeWithChil
     let mut nodes = vec![];
 
     for nid in top_lvl_ids.iter() {
-        nodes.push(to_children(&mut relation_map, nid)?)
+        nodes.push(to_children(&mut relation_map, child_counts, nid)?)
     }
 
-    Ok(sort_with_precessors(
-        nodes,
-        |e| e.node.id.clone(),
-        |e| match &e.node.prev_sliding_id {
-            kcore::model::node::MagicNodeId::RecycleBin => None,
-            kcore::model::node::MagicNodeId::Empty => None,
-            kcore::model::node::MagicNodeId::Never => None,
-            kcore::model::node::MagicNodeId::Id(id) => Some(id.clone()),
-        },
-        |e| e.node.version_time.clone(),
-    )?)
+    nodes.sort_by(|a, b| order_key(&a.node).cmp(&order_key(&b.node)));
+    Ok(nodes)
+}
+
+/// Siblings go by their order key, the roots of a partial tree may come from
+/// several parents and are grouped by them.
+fn order_key(node: &Node) -> (&str, i64, &str) {
+    (node.parent_id.as_ref(), node.position, node.id.as_str())
 }
 
 #[cfg(test)]
 mod test {
 
-    use std::vec;
+    use std::{collections::HashMap, vec};
 
     use chrono::Utc;
     use kcore::model::node::{ContentParsedInfo, MagicNodeId, Node};
@@ -115,6 +120,7 @@ mod test {
             parsed_info: ContentParsedInfo::default(),
             parent_id: MagicNodeId::default(),
             prev_sliding_id: MagicNodeId::default(),
+            position: 0,
             version_time: Utc::now(),
             initial_time: Utc::now().fixed_offset(),
             node_type: kcore::model::node::NodeType::TiptapV1,
@@ -124,10 +130,59 @@ mod test {
         let node = NodeWithChildren {
             node: node1.clone(),
             children: vec![],
+            child_count: 0,
+            has_children: false,
         };
 
-        nodes_with_childrens(vec![node1]);
+        nodes_with_childrens(vec![node1], &HashMap::new());
 
         println!("{:?}", serde_json::to_string(&node).unwrap())
     }
+
+    #[test]
+    fn test_child_counts() {
+        let node = |id: &str, parent_id: MagicNodeId| Node {
+            id: id.into(),
+            delete_time: None,
+            name: id.to_string(),
+            content: "".to_string(),
+            domain: "".to_string(),
+            parsed_info: ContentParsedInfo::default(),
+            parent_id,
+            prev_sliding_id: MagicNodeId::Empty,
+            position: 0,
+            version_time: Utc::now(),
+            initial_time: Utc::now().fixed_offset(),
+            node_type: kcore::model::node::NodeType::TiptapV1,
+            readonly: false,
+            todo_status: None,
+        };
+        // `b` is loaded, the children of `b` and `c` are not.
+        let nodes = vec![
+            node("a", MagicNodeId::Empty),
+            node("b", MagicNodeId::Id("a".into())),
+            node("c", MagicNodeId::Empty),
+        ];
+        let counts = HashMap::from([("a".into(), 1), ("b".into(), 3)]);
+
+        let trees = nodes_with_childrens(nodes, &counts).unwrap();
+        let a = trees.iter().find(|e| e.node.id.as_str() == "a").unwrap();
+        assert_eq!((a.child_count, a.has_children), (1, true));
+        assert_eq!(a.children[0].child_count, 3);
+        assert!(a.children[0].children.is_empty());
+        let c = trees.iter().find(|e| e.node.id.as_str() == "c").unwrap();
+        assert_eq!((c.child_count, c.has_children), (0, false));
+
+        // Without counts everything is expected to be loaded.
+        let trees = nodes_with_childrens(
+            vec![
+                node("d", MagicNodeId::Empty),
+                node("e", MagicNodeId::Id("d".into())),
+            ],
+            &HashMap::new(),
+        )
+        .unwrap();
+        assert_eq!(trees[0].child_count, 1);
+        assert!(!trees[0].children[0].has_children);
+    }
 }
@@ -1,10 +1,22 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser, Default, Debug, Clone)]
 #[command(author = "aeghn", version = "0.1", about = "chnots server")]
 pub struct Arguments {
     #[clap(long, short, help = "Config file to read")]
     pub config: String,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    #[command(about = "Check the node tree, print the report and exit")]
+    Fsck {
+        #[clap(long, help = "Repair the issues found")]
+        repair: bool,
+    },
 }
 
 unsafe impl Sync for Arguments {}
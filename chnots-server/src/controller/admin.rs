@@ -0,0 +1,25 @@
+use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
+use kcore::mapper::fsck::FsckReq;
+use tracing::info;
+
+use crate::controller::print_and_trans_to_response;
+
+use super::WebAppState;
+
+pub fn routes() -> Router<WebAppState> {
+    Router::new()
+        .route("/api/admin/fsck", post(fsck))
+        .route("/api/admin/reindex", post(reindex))
+}
+
+async fn fsck(state: State<WebAppState>, Json(req): Json<FsckReq>) -> impl IntoResponse {
+    info!("fsck: {:?}", req);
+    let rest = state.mapper.fsck(&req).await;
+    print_and_trans_to_response(rest)
+}
+
+async fn reindex(state: State<WebAppState>) -> impl IntoResponse {
+    info!("reindex");
+    let rest = state.mapper.reindex().await;
+    print_and_trans_to_response(rest)
+}
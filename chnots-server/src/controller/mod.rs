@@ -1,3 +1,4 @@
+mod admin;
 mod asset;
 mod service;
 mod staticfiles;
@@ -17,7 +18,7 @@ use axum::{
     Json, Router,
 };
 
-use kcore::mapper::Mapper;
+use kcore::mapper::{error::MapperError, Mapper};
 use serde::Serialize;
 use tower_http::{
     compression::CompressionLayer,
@@ -55,6 +56,7 @@ pub async fn serve(mapper: Arc<dyn Mapper>, config: ServerConfig) {
 
     let app = Router::new()
         .merge(service::routes())
+        .merge(admin::routes())
         .merge(asset::routes())
         .merge(staticfiles::routes())
         .with_state(state)
@@ -94,10 +96,24 @@ where
             debug!("return result: {:?}", j);
             (StatusCode::OK, j.into_response())
         }
-        Err(err) => {
-            let err_str = err.to_string();
-            error!("{}", err_str);
-            (StatusCode::INTERNAL_SERVER_ERROR, err_str.into_response())
-        }
+        Err(err) => match err.downcast_ref::<MapperError>() {
+            Some(MapperError::Conflict(conflict)) => {
+                info!("{}", err);
+                (StatusCode::CONFLICT, Json(conflict).into_response())
+            }
+            Some(MapperError::NoSuchPath(_)) => {
+                info!("{}", err);
+                (StatusCode::NOT_FOUND, err.to_string().into_response())
+            }
+            Some(_) => {
+                info!("{}", err);
+                (StatusCode::BAD_REQUEST, err.to_string().into_response())
+            }
+            None => {
+                let err_str = err.to_string();
+                error!("{}", err_str);
+                (StatusCode::INTERNAL_SERVER_ERROR, err_str.into_response())
+            }
+        },
     }
 }
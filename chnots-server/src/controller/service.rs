@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use axum::{
     extract::State,
     response::IntoResponse,
@@ -7,18 +9,29 @@ use axum::{
 use kcore::{
     mapper::{
         node::{
-            NodeDeleteReq, NodeMoveReq, NodeRenameReq, NodeUpdateContentReq, NodeUpdateReadonlyReq,
+            NodeCopyReq, NodeCopyRsp, NodeDeleteReq, NodeDiffReq, NodeLinksReq, NodeMoveReq,
+            NodeRenameReq, NodeUpdateContentReq, NodeUpdateReadonlyReq, NodeVersionReq,
+            NodeVersionsReq,
         },
         nodefilter::{NodeFetchReq, NodeFilter},
+        path::{NodeBreadcrumbReq, NodePathReq},
+        recycle::{RecycleEmptyReq, RecyclePurgeReq, RecyclePurgeRsp, RecycleRestoreReq},
+        reorder::{NodeMoveBatchReq, NodeReorderReq, NodeSortChildrenReq},
+        search::SearchReq,
+        tag::{TagMergeReq, TagRenameReq},
     },
-    model::node::Node,
+    model::node::{Node, NodeId},
+    parser::asset::{copy_asset_files, remove_asset_files},
     /*     parser::toent::timestamp::guess_tss,
      */
 };
 use serde::Deserialize;
 use tracing::info;
 
-use crate::controller::print_and_trans_to_response;
+use crate::{
+    adapter::node_with_children::{nodes_with_childrens, NodeWithChildren},
+    controller::print_and_trans_to_response,
+};
 
 use super::WebAppState;
 
@@ -28,12 +41,33 @@ pub fn routes() -> Router<WebAppState> {
         .route("/api/insert-node-only", post(insert_node_only))
         .route("/api/fetch-nodes", post(fetch_nodes))
         .route("/api/fetch-all-nodes", get(fetch_all_nodes))
+        .route("/api/fetch-node-tree", post(fetch_node_tree))
         .route("/api/move-node", post(move_node))
+        .route("/api/move-nodes", post(move_nodes))
+        .route("/api/reorder-children", post(reorder_children))
+        .route("/api/sort-children", post(sort_children))
         .route("/api/delete-node", post(delete_node))
+        .route("/api/copy-node", post(copy_node))
         .route("/api/guess-toent", post(guess_toent))
         .route("/api/update-node-content", post(update_node_content))
         .route("/api/update-node-readonly", post(update_node_readonly))
         .route("/api/update-node-name", post(update_node_name))
+        .route("/api/fetch-backlinks", post(fetch_backlinks))
+        .route("/api/fetch-outgoing-links", post(fetch_outgoing_links))
+        .route("/api/resolve-node-path", post(resolve_node_path))
+        .route("/api/fetch-node-breadcrumb", post(fetch_node_breadcrumb))
+        .route("/api/list-tags", get(list_tags))
+        .route("/api/rename-tag", post(rename_tag))
+        .route("/api/merge-tags", post(merge_tags))
+        .route("/api/search", post(search))
+        .route("/api/list-node-versions", post(list_node_versions))
+        .route("/api/fetch-node-version", post(fetch_node_version))
+        .route("/api/diff-node-versions", post(diff_node_versions))
+        .route("/api/restore-node-version", post(restore_node_version))
+        .route("/api/list-recycle-bin", get(list_recycle_bin))
+        .route("/api/restore-node", post(restore_node))
+        .route("/api/purge-node", post(purge_node))
+        .route("/api/empty-recycle-bin", post(empty_recycle_bin))
 }
 
 async fn insert_node(state: State<WebAppState>, Json(node): Json<Node>) -> impl IntoResponse {
@@ -53,30 +87,44 @@ async fn fetch_nodes(
     Json(req): Json<NodeFetchReq>,
 ) -> impl IntoResponse {
     info!("fetch_nodes: {:?}", req);
+    let rest = state.mapper.query_page(&req).await;
+    print_and_trans_to_response(rest)
+}
+
+async fn fetch_all_nodes(state: State<WebAppState>) -> impl IntoResponse {
     let rest = state
         .mapper
-        .query_nodes(&req)
+        .query_nodes(&NodeFetchReq {
+            selection: None,
+            filter: Some(NodeFilter::All),
+            ..Default::default()
+        })
         .await
-        .map(|e| crate::adapter::node_with_children::nodes_with_childrens(e));
+        .and_then(|nodes| nodes_with_childrens(nodes, &HashMap::new()));
 
-    let rest = match rest {
-        Ok(Ok(o)) => Ok(o),
-        Ok(err) => err,
-        Err(err) => Err(err),
-    };
+    print_and_trans_to_response(rest)
+}
 
+/// The matching nodes as trees, with the number of children each has, so
+/// the branches can be loaded when they are expanded, e.g. with a depth
+/// limited `descendant` filter.
+async fn fetch_node_tree(
+    state: State<WebAppState>,
+    Json(req): Json<NodeFetchReq>,
+) -> impl IntoResponse {
+    info!("fetch_node_tree: {:?}", req);
+    let rest = query_node_tree(&state, &req).await;
     print_and_trans_to_response(rest)
 }
 
-async fn fetch_all_nodes(state: State<WebAppState>) -> impl IntoResponse {
-    fetch_nodes(
-        state,
-        Json(NodeFetchReq {
-            selection: None,
-            filter: Some(NodeFilter::All),
-        }),
-    )
-    .await
+async fn query_node_tree(
+    state: &WebAppState,
+    req: &NodeFetchReq,
+) -> anyhow::Result<Vec<NodeWithChildren>> {
+    let nodes = state.mapper.query_nodes(req).await?;
+    let ids: Vec<NodeId> = nodes.iter().map(|e| e.id.clone()).collect();
+    let child_counts = state.mapper.query_child_counts(&ids).await?;
+    nodes_with_childrens(nodes, &child_counts)
 }
 
 async fn move_node(state: State<WebAppState>, Json(req): Json<NodeMoveReq>) -> impl IntoResponse {
@@ -85,6 +133,33 @@ async fn move_node(state: State<WebAppState>, Json(req): Json<NodeMoveReq>) -> i
     print_and_trans_to_response(rest)
 }
 
+async fn move_nodes(
+    state: State<WebAppState>,
+    Json(req): Json<NodeMoveBatchReq>,
+) -> impl IntoResponse {
+    info!("move_nodes: {:?}", req);
+    let rest = state.mapper.move_nodes_batch(&req).await;
+    print_and_trans_to_response(rest)
+}
+
+async fn reorder_children(
+    state: State<WebAppState>,
+    Json(req): Json<NodeReorderReq>,
+) -> impl IntoResponse {
+    info!("reorder_children: {:?}", req);
+    let rest = state.mapper.reorder_children(&req).await;
+    print_and_trans_to_response(rest)
+}
+
+async fn sort_children(
+    state: State<WebAppState>,
+    Json(req): Json<NodeSortChildrenReq>,
+) -> impl IntoResponse {
+    info!("sort_children: {:?}", req);
+    let rest = state.mapper.sort_children(&req).await;
+    print_and_trans_to_response(rest)
+}
+
 async fn delete_node(
     state: State<WebAppState>,
     Json(req): Json<NodeDeleteReq>,
@@ -94,6 +169,21 @@ async fn delete_node(
     print_and_trans_to_response(rest)
 }
 
+async fn copy_node(state: State<WebAppState>, Json(req): Json<NodeCopyReq>) -> impl IntoResponse {
+    info!("copy_node: {:?}", req);
+    let res = state.mapper.copy_node(&req).await;
+    print_and_trans_to_response(copy_node_assets(&state, res).await)
+}
+
+async fn copy_node_assets(
+    state: &WebAppState,
+    res: anyhow::Result<NodeCopyRsp>,
+) -> anyhow::Result<NodeCopyRsp> {
+    let rsp = res?;
+    copy_asset_files(&state.config.config, &rsp.asset_ids).await?;
+    Ok(rsp)
+}
+
 async fn update_node_name(
     state: State<WebAppState>,
     Json(req): Json<NodeRenameReq>,
@@ -119,6 +209,135 @@ async fn update_node_readonly(
     print_and_trans_to_response(res)
 }
 
+async fn fetch_backlinks(
+    state: State<WebAppState>,
+    Json(req): Json<NodeLinksReq>,
+) -> impl IntoResponse {
+    let res = state.mapper.query_backlinks(&req.id).await;
+    print_and_trans_to_response(res)
+}
+
+async fn fetch_outgoing_links(
+    state: State<WebAppState>,
+    Json(req): Json<NodeLinksReq>,
+) -> impl IntoResponse {
+    let res = state.mapper.query_outgoing_links(&req.id).await;
+    print_and_trans_to_response(res)
+}
+
+async fn resolve_node_path(
+    state: State<WebAppState>,
+    Json(req): Json<NodePathReq>,
+) -> impl IntoResponse {
+    info!("resolve_node_path: {:?}", req);
+    let res = state.mapper.resolve_path(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn fetch_node_breadcrumb(
+    state: State<WebAppState>,
+    Json(req): Json<NodeBreadcrumbReq>,
+) -> impl IntoResponse {
+    let res = state.mapper.query_breadcrumb(&req.id).await;
+    print_and_trans_to_response(res)
+}
+
+async fn list_tags(state: State<WebAppState>) -> impl IntoResponse {
+    let res = state.mapper.list_tags().await;
+    print_and_trans_to_response(res)
+}
+
+async fn rename_tag(state: State<WebAppState>, Json(req): Json<TagRenameReq>) -> impl IntoResponse {
+    info!("rename_tag: {:?}", req);
+    let res = state.mapper.rename_tag(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn merge_tags(state: State<WebAppState>, Json(req): Json<TagMergeReq>) -> impl IntoResponse {
+    info!("merge_tags: {:?}", req);
+    let res = state.mapper.merge_tags(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn search(state: State<WebAppState>, Json(req): Json<SearchReq>) -> impl IntoResponse {
+    let res = state.mapper.search(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn list_node_versions(
+    state: State<WebAppState>,
+    Json(req): Json<NodeVersionsReq>,
+) -> impl IntoResponse {
+    let res = state.mapper.query_versions(&req.id).await;
+    print_and_trans_to_response(res)
+}
+
+async fn fetch_node_version(
+    state: State<WebAppState>,
+    Json(req): Json<NodeVersionReq>,
+) -> impl IntoResponse {
+    let res = state.mapper.query_version(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn diff_node_versions(
+    state: State<WebAppState>,
+    Json(req): Json<NodeDiffReq>,
+) -> impl IntoResponse {
+    let res = state.mapper.diff_versions(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn restore_node_version(
+    state: State<WebAppState>,
+    Json(req): Json<NodeVersionReq>,
+) -> impl IntoResponse {
+    info!("restore_node_version: {:?}", req);
+    let res = state.mapper.restore_version(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn list_recycle_bin(state: State<WebAppState>) -> impl IntoResponse {
+    let res = state.mapper.list_recycle_bin().await;
+    print_and_trans_to_response(res)
+}
+
+async fn restore_node(
+    state: State<WebAppState>,
+    Json(req): Json<RecycleRestoreReq>,
+) -> impl IntoResponse {
+    info!("restore_node: {:?}", req);
+    let res = state.mapper.restore_node(&req).await;
+    print_and_trans_to_response(res)
+}
+
+async fn purge_node(
+    state: State<WebAppState>,
+    Json(req): Json<RecyclePurgeReq>,
+) -> impl IntoResponse {
+    info!("purge_node: {:?}", req);
+    let res = state.mapper.purge_node(&req).await;
+    print_and_trans_to_response(remove_purged_assets(&state, res).await)
+}
+
+async fn empty_recycle_bin(
+    state: State<WebAppState>,
+    Json(req): Json<RecycleEmptyReq>,
+) -> impl IntoResponse {
+    info!("empty_recycle_bin: {:?}", req);
+    let res = state.mapper.empty_recycle_bin(&req).await;
+    print_and_trans_to_response(remove_purged_assets(&state, res).await)
+}
+
+async fn remove_purged_assets(
+    state: &WebAppState,
+    res: anyhow::Result<RecyclePurgeRsp>,
+) -> anyhow::Result<RecyclePurgeRsp> {
+    let rsp = res?;
+    remove_asset_files(&state.config.config, &rsp.asset_ids).await?;
+    Ok(rsp)
+}
+
 #[derive(Clone, Debug, Deserialize)]
 struct TimeGuessReq {
     input: String,
@@ -174,6 +393,7 @@ mod test {
             domain: String::new(),
             parent_id: pid.into(),
             prev_sliding_id: prev.into(),
+            position: 0,
             version_time: cur.clone(),
             initial_time: cur.clone().fixed_offset(),
             parsed_info: ContentParsedInfo::default(),
@@ -1,10 +1,10 @@
 use std::sync::Arc;
 
-use arguments::Arguments;
+use arguments::{Arguments, Command};
 use clap::Parser;
 use config::ServerConfig;
-use kcore::mapper::Mapper;
-use service::time_worker::backup;
+use kcore::mapper::{fsck::FsckReq, Mapper};
+use service::time_worker::{backup, compact_history, empty_recycle_bin};
 use tracing::{info, Level};
 
 pub mod adapter;
@@ -35,12 +35,24 @@ async fn main() -> anyhow::Result<()> {
     match config_file {
         Ok(cf) => {
             let config: ServerConfig = toml::from_str(cf.as_str())?;
-            let mapper: anyhow::Result<Arc<dyn Mapper + 'static>> = config.db_config.clone().into().await;
+            let mapper: anyhow::Result<Arc<dyn Mapper + 'static>> =
+                config.db_config.clone().build(&config.history).await;
             let mapper = mapper?;
 
+            if let Some(Command::Fsck { repair }) = args.command {
+                let report = mapper.fsck(&FsckReq { repair }).await?;
+                println!("{}", serde_json::to_string_pretty(&report)?);
+                return Ok(());
+            }
+
             if let Some(backup_config) = config.backup.as_ref() {
                 backup(&mapper, &Arc::new(config.config.clone()), backup_config).await?;
             }
+            compact_history(&mapper, &config.history).await?;
+            if let Some(recycle_config) = config.recycle.as_ref() {
+                empty_recycle_bin(&mapper, &Arc::new(config.config.clone()), recycle_config)
+                    .await?;
+            }
 
             controller::serve(mapper, config).await;
         }
@@ -1,8 +1,9 @@
 use std::{path::PathBuf, sync::Arc};
 
 use kcore::{
-    config::{BackupConfig, Config},
-    mapper::Mapper,
+    config::{BackupConfig, Config, RecycleConfig},
+    mapper::{history::HistoryConfig, recycle::RecycleEmptyReq, Mapper},
+    parser::asset::remove_asset_files,
 };
 use tokio::time;
 use tracing::{error, info};
@@ -38,3 +39,58 @@ pub async fn backup(
     });
     Ok(())
 }
+
+pub async fn compact_history(
+    mapper: &Arc<dyn Mapper + 'static>,
+    history_config: &HistoryConfig,
+) -> anyhow::Result<()> {
+    let Some(policy) = history_config.retention.clone() else {
+        return Ok(());
+    };
+    let mapper = mapper.clone();
+    let seconds = history_config.interval.map_or(86400, |e| e.get()) as u64;
+    tokio::spawn(async move {
+        let mut interval = time::interval(time::Duration::from_secs(seconds));
+
+        loop {
+            interval.tick().await;
+            info!("begin to compact node history");
+            match mapper.compact_history(&policy).await {
+                Ok(count) => info!("removed {} node versions", count),
+                Err(err) => error!("Unable to compact node history, {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+pub async fn empty_recycle_bin(
+    mapper: &Arc<dyn Mapper + 'static>,
+    config: &Arc<Config>,
+    recycle_config: &RecycleConfig,
+) -> anyhow::Result<()> {
+    let mapper = mapper.clone();
+    let config = config.clone();
+    let req = RecycleEmptyReq {
+        older_than_days: Some(recycle_config.keep_days),
+    };
+    let seconds = recycle_config.interval.map_or(86400, |e| e.get()) as u64;
+    tokio::spawn(async move {
+        let mut interval = time::interval(time::Duration::from_secs(seconds));
+
+        loop {
+            interval.tick().await;
+            info!("begin to empty recycle bin");
+            match mapper.empty_recycle_bin(&req).await {
+                Ok(rsp) => {
+                    info!("purged {} nodes", rsp.node_ids.len());
+                    if let Err(err) = remove_asset_files(&config, &rsp.asset_ids).await {
+                        error!("Unable to remove purged assets, {}", err);
+                    }
+                }
+                Err(err) => error!("Unable to empty recycle bin, {}", err),
+            }
+        }
+    });
+    Ok(())
+}
@@ -1,4 +1,4 @@
-import { Asset, Toent, KNode, NodeId, ContentParsedInfo } from "@/model";
+import { Asset, Toent, KNode, NodeId, ContentParsedInfo, NodePage } from "@/model";
 import requests from "./request";
 
 export const fetchAllNodes = async (): Promise<KNode[]> => {
@@ -6,26 +6,29 @@ export const fetchAllNodes = async (): Promise<KNode[]> => {
 };
 
 export const fetchNodesLike = async (query: string): Promise<KNode[]> => {
-  return requests.post("api/fetch-nodes", {
-    selection: ["cont", "lim"],
-    filter: {
-      filter: "like",
-      value: query,
-    },
-  });
+  return requests
+    .post<NodePage>("api/fetch-nodes", {
+      selection: ["cont", "lim"],
+      filter: {
+        filter: "like",
+        value: query,
+      },
+      sort: { key: "version_time", desc: true },
+    })
+    .then((page) => page.nodes);
 };
 
 export const fetchNodeContent = async (id: string): Promise<KNode> => {
   return requests
-    .post<KNode[]>("api/fetch-nodes", {
+    .post<NodePage>("api/fetch-nodes", {
       selection: ["cont"],
       filter: {
         filter: "id",
         value: id,
       },
     })
-    .then((nodes) => {
-      return nodes[0];
+    .then((page) => {
+      return page.nodes[0];
     });
 };
 
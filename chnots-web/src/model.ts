@@ -18,7 +18,6 @@ export interface MagicConstants {
 
 export interface KTag {
   name: string;
-  create_time: Date;
 }
 
 export enum NodeType {
@@ -48,9 +47,31 @@ export interface KNode {
   children?: KNode[];
 }
 
+export interface NodePage {
+  nodes: KNode[];
+  next_cursor?: string;
+}
+
 export interface ContentParsedInfo {
   todo_status?: string;
   tags?: KTag[];
+  text?: string;
+  mentions?: string[];
+  node_refs?: NodeRef[];
+  asset_ids?: string[];
+  tasks?: TaskItem[];
+  toents?: string[];
+}
+
+export interface NodeRef {
+  id: NodeId;
+  text: string;
+  snippet?: string;
+}
+
+export interface TaskItem {
+  text: string;
+  checked: boolean;
 }
 
 export interface Asset {